@@ -0,0 +1,32 @@
+//! Embeds the git commit and target triple for `--version`.
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    // Builds from a source tarball or a Docker context have no `.git`; the
+    // commit can then be passed in through the environment instead.
+    let commit = env::var("GIT_COMMIT")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|commit| commit.trim().to_string())
+                .filter(|commit| !commit.is_empty())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+    println!(
+        "cargo:rustc-env=BUILD_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
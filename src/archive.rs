@@ -0,0 +1,813 @@
+//! Writing a folder to `<ARCHIVE_DIR>/<name>.tar.gz` before it is removed.
+//!
+//! The tar and gzip framing follow POSIX ustar (with GNU long-name records)
+//! and RFC 1952. Compression is LZ77 with the fixed DEFLATE Huffman codes
+//! (RFC 1951, block type 1): less tight than zlib's dynamic codes, but
+//! readable by any `tar`/`gzip` and needing no extra dependencies.
+//!
+//! Archives keep names, folder structure, file contents, symlinks (as links),
+//! permission bits and modification times. They leave out owners (written as
+//! uid/gid 0), hard links (each name gets its own copy) and sockets, FIFOs
+//! and devices (skipped). A name or link target that is not valid UTF-8
+//! cannot be recorded faithfully, so it fails the archive and the folder is
+//! kept.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::filesystem::FileSystem;
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    table
+}
+
+/// Packs bits least-significant first, as DEFLATE requires.
+struct BitWriter<W: Write> {
+    out: W,
+    acc: u64,
+    bits: u32,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn write_bits(&mut self, value: u32, count: u32) -> io::Result<()> {
+        self.acc |= (value as u64) << self.bits;
+        self.bits += count;
+        while self.bits >= 8 {
+            self.out.write_all(&[self.acc as u8])?;
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+        Ok(())
+    }
+
+    /// Huffman codes are defined most-significant bit first.
+    fn write_code(&mut self, code: u32, len: u32) -> io::Result<()> {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write_bits(reversed, len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.bits > 0 {
+            self.out.write_all(&[self.acc as u8])?;
+            self.acc = 0;
+            self.bits = 0;
+        }
+        Ok(())
+    }
+}
+
+enum Token {
+    Literal(u8),
+    Match(usize, usize),
+}
+
+impl Token {
+    /// Encoded size with the fixed Huffman codes.
+    fn bits(&self) -> usize {
+        match *self {
+            Token::Literal(byte) if byte < 144 => 8,
+            Token::Literal(_) => 9,
+            Token::Match(length, distance) => {
+                let code = length_code(length);
+                let symbol_bits = if code < 24 { 7 } else { 8 };
+                symbol_bits
+                    + LENGTH_EXTRA[code] as usize
+                    + 5
+                    + DIST_EXTRA[distance_code(distance)] as usize
+            }
+        }
+    }
+}
+
+fn length_code(length: usize) -> usize {
+    LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .expect("match length is at least 3")
+}
+
+fn distance_code(distance: usize) -> usize {
+    DIST_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .expect("match distance is at least 1")
+}
+
+/// Splits `data` into literals and back-references using hash chains over
+/// three-byte prefixes.
+fn tokenize(data: &[u8]) -> Vec<Token> {
+    let hash = |i: usize| {
+        let value = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    };
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = i - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            tokens.push(Token::Match(best_len, best_dist));
+            for j in i..i + best_len {
+                insert(j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Streams gzip output, compressing input in independent chunks.
+struct GzipWriter<W: Write> {
+    bits: BitWriter<W>,
+    pending: Vec<u8>,
+    crc_table: [u32; 256],
+    crc: u32,
+    input_len: u64,
+}
+
+impl<W: Write> GzipWriter<W> {
+    fn new(mut out: W) -> io::Result<GzipWriter<W>> {
+        // Magic, deflate, no flags, no mtime, no extra flags, unknown OS.
+        out.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255])?;
+        Ok(GzipWriter {
+            bits: BitWriter {
+                out,
+                acc: 0,
+                bits: 0,
+            },
+            pending: Vec::with_capacity(CHUNK_SIZE),
+            crc_table: crc32_table(),
+            crc: 0xFFFF_FFFF,
+            input_len: 0,
+        })
+    }
+
+    fn write_symbol(&mut self, symbol: u16) -> io::Result<()> {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.bits.write_code(0x30 + symbol, 8),
+            144..=255 => self.bits.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.bits.write_code(symbol - 256, 7),
+            _ => self.bits.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) -> io::Result<()> {
+        let code = length_code(length);
+        self.write_symbol(257 + code as u16)?;
+        self.bits.write_bits(
+            (length - LENGTH_BASE[code] as usize) as u32,
+            LENGTH_EXTRA[code] as u32,
+        )?;
+
+        let code = distance_code(distance);
+        self.bits.write_code(code as u32, 5)?;
+        self.bits.write_bits(
+            (distance - DIST_BASE[code] as usize) as u32,
+            DIST_EXTRA[code] as u32,
+        )
+    }
+
+    /// Emits `pending` as a fixed-Huffman block, or as stored blocks when
+    /// the data does not compress (already-compressed media, for instance).
+    fn compress_pending(&mut self, last: bool) -> io::Result<()> {
+        let data = std::mem::take(&mut self.pending);
+        let tokens = tokenize(&data);
+        let compressed_bits: usize = tokens.iter().map(Token::bits).sum::<usize>() + 7;
+
+        if compressed_bits > data.len() * 8 {
+            let mut pieces = data.chunks(u16::MAX as usize).peekable();
+            if pieces.peek().is_none() {
+                self.write_stored(&[], last)?;
+            }
+            while let Some(piece) = pieces.next() {
+                self.write_stored(piece, last && pieces.peek().is_none())?;
+            }
+        } else {
+            self.bits.write_bits(last as u32, 1)?;
+            self.bits.write_bits(1, 2)?;
+            for token in tokens {
+                match token {
+                    Token::Literal(byte) => self.write_symbol(byte as u16)?,
+                    Token::Match(length, distance) => self.write_match(length, distance)?,
+                }
+            }
+            self.write_symbol(256)?;
+        }
+
+        self.pending = data;
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn write_stored(&mut self, data: &[u8], last: bool) -> io::Result<()> {
+        self.bits.write_bits(last as u32, 1)?;
+        self.bits.write_bits(0, 2)?;
+        self.bits.flush()?;
+        let len = data.len() as u16;
+        self.bits.out.write_all(&len.to_le_bytes())?;
+        self.bits.out.write_all(&(!len).to_le_bytes())?;
+        self.bits.out.write_all(data)
+    }
+
+    /// Writes the final block and gzip trailer, returning the inner writer.
+    fn finish(mut self) -> io::Result<W> {
+        self.compress_pending(true)?;
+        self.bits.flush()?;
+        let crc = self.crc ^ 0xFFFF_FFFF;
+        self.bits.out.write_all(&crc.to_le_bytes())?;
+        self.bits
+            .out
+            .write_all(&(self.input_len as u32).to_le_bytes())?;
+        Ok(self.bits.out)
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_SIZE - self.pending.len());
+        let buf = &buf[..len];
+        for &byte in buf {
+            self.crc = self.crc_table[((self.crc ^ byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+        self.input_len += len as u64;
+        self.pending.extend_from_slice(buf);
+        if self.pending.len() == CHUNK_SIZE {
+            self.compress_pending(false)?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.bits.out.flush()
+    }
+}
+
+/// Fills a numeric tar header field with zero-padded octal, switching to the
+/// GNU base-256 form for values that do not fit.
+fn write_number(field: &mut [u8], value: u64) {
+    let octal = format!("{:0width$o}", value, width = field.len() - 1);
+    if octal.len() < field.len() {
+        field[..octal.len()].copy_from_slice(octal.as_bytes());
+        field[octal.len()] = 0;
+    } else {
+        field.fill(0);
+        let bytes = value.to_be_bytes();
+        let start = field.len() - bytes.len();
+        field[start..].copy_from_slice(&bytes);
+        field[0] = 0x80;
+    }
+}
+
+struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn header(
+        &mut self,
+        name: &str,
+        kind: u8,
+        mode: u32,
+        size: u64,
+        mtime: u64,
+        link: &str,
+    ) -> io::Result<()> {
+        if name.len() > 100 {
+            self.long_name(b'L', name)?;
+        }
+        if link.len() > 100 {
+            self.long_name(b'K', link)?;
+        }
+
+        let mut header = [0u8; 512];
+        let name = &name.as_bytes()[..name.len().min(100)];
+        header[..name.len()].copy_from_slice(name);
+        write_number(&mut header[100..108], mode as u64);
+        write_number(&mut header[108..116], 0);
+        write_number(&mut header[116..124], 0);
+        write_number(&mut header[124..136], size);
+        write_number(&mut header[136..148], mtime);
+        header[148..156].fill(b' ');
+        header[156] = kind;
+        let link = &link.as_bytes()[..link.len().min(100)];
+        header[157..157 + link.len()].copy_from_slice(link);
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        let checksum = format!("{:06o}\0 ", checksum);
+        header[148..156].copy_from_slice(checksum.as_bytes());
+        self.out.write_all(&header)
+    }
+
+    fn long_name(&mut self, kind: u8, name: &str) -> io::Result<()> {
+        let mut data = name.as_bytes().to_vec();
+        data.push(0);
+        self.header("././@LongLink", kind, 0o644, data.len() as u64, 0, "")?;
+        self.out.write_all(&data)?;
+        self.pad(data.len() as u64)
+    }
+
+    fn pad(&mut self, size: u64) -> io::Result<()> {
+        let remainder = (size % 512) as usize;
+        if remainder > 0 {
+            self.out.write_all(&[0u8; 512][..512 - remainder])?;
+        }
+        Ok(())
+    }
+
+    /// Adds `path` and everything below it under the archive name `name`.
+    fn append(&mut self, fs: &dyn FileSystem, path: &Path, name: &str) -> io::Result<()> {
+        let metadata = fs.symlink_metadata(path)?;
+        let mtime = metadata
+            .modified
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        let mode = metadata
+            .mode
+            .unwrap_or(if metadata.is_dir() { 0o755 } else { 0o644 });
+
+        if metadata.is_symlink() {
+            let target = fs.read_link(path)?;
+            self.header(name, b'2', mode, 0, mtime, utf8(&target)?)
+        } else if metadata.is_dir() {
+            self.header(&format!("{}/", name), b'5', mode, 0, mtime, "")?;
+            let mut entries = fs.read_dir(path)?;
+            entries.sort();
+            for entry in entries {
+                if entry.file_name().is_none() {
+                    continue;
+                }
+                let child = format!("{}/{}", name, file_name(&entry)?);
+                self.append(fs, &entry, &child)?;
+            }
+            Ok(())
+        } else if metadata.is_file() {
+            let size = metadata.len;
+            self.header(name, b'0', mode, size, mtime, "")?;
+            // Write exactly the size already recorded in the header, even if
+            // the file changes underneath us.
+            let copied = io::copy(&mut fs.open(path)?.take(size), &mut self.out)?;
+            io::copy(&mut io::repeat(0).take(size - copied), &mut self.out)?;
+            self.pad(size)
+        } else {
+            // Sockets, FIFOs and devices have no content worth keeping.
+            Ok(())
+        }
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0u8; 1024])?;
+        Ok(self.out)
+    }
+}
+
+fn not_utf8(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "{} is not valid UTF-8 and cannot be archived",
+            path.to_string_lossy()
+        ),
+    )
+}
+
+fn utf8(path: &Path) -> io::Result<&str> {
+    path.to_str().ok_or_else(|| not_utf8(path))
+}
+
+/// The last component of `path`, which must be valid UTF-8.
+fn file_name(path: &Path) -> io::Result<&str> {
+    path.file_name()
+        .unwrap_or_default()
+        .to_str()
+        .ok_or_else(|| not_utf8(path))
+}
+
+/// Picks `<name>.tar.gz` inside `archive_dir`, adding a numeric suffix if that
+/// name is already taken.
+fn archive_destination(fs: &dyn FileSystem, archive_dir: &Path, name: &str) -> PathBuf {
+    let mut destination = archive_dir.join(format!("{}.tar.gz", name));
+    let mut suffix = 1;
+    while fs.exists(&destination) {
+        destination = archive_dir.join(format!("{}.{}.tar.gz", name, suffix));
+        suffix += 1;
+    }
+    destination
+}
+
+/// Writes `folder` to a `.tar.gz` in `archive_dir` and returns the archive
+/// path and its size. The archive is written under a temporary name and only
+/// renamed into place once complete, so a failure never leaves a truncated
+/// archive that looks valid.
+pub(crate) fn archive_folder(
+    fs: &dyn FileSystem,
+    folder: &Path,
+    archive_dir: &Path,
+) -> io::Result<(PathBuf, u64)> {
+    let name = file_name(folder)?.to_string();
+    fs.create_dir_all(archive_dir)?;
+    let destination = archive_destination(fs, archive_dir, &name);
+    let partial = destination.with_extension("gz.partial");
+
+    let result = (|| {
+        let file = io::BufWriter::new(fs.create(&partial)?);
+        let mut tar = TarWriter {
+            out: GzipWriter::new(file)?,
+        };
+        tar.append(fs, folder, &name)?;
+        tar.finish()?.finish()?.flush()?;
+        fs.sync(&partial)
+    })();
+
+    if let Err(err) = result {
+        let _ = fs.remove_file(&partial);
+        return Err(err);
+    }
+    fs.rename(&partial, &destination)?;
+    let size = fs.metadata(&destination)?.len;
+    Ok((destination, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::test_support::MemoryFileSystem;
+
+    /// Reads DEFLATE bits least-significant first.
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bit(&mut self) -> u32 {
+            let bit = (self.data[self.pos / 8] >> (self.pos % 8)) & 1;
+            self.pos += 1;
+            bit as u32
+        }
+
+        fn bits(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |value, i| value | self.bit() << i)
+        }
+
+        /// A Huffman code, most-significant bit first.
+        fn code(&mut self, len: u32) -> u32 {
+            (0..len).fold(0, |value, _| value << 1 | self.bit())
+        }
+
+        fn fixed_symbol(&mut self) -> u32 {
+            let code = self.code(7);
+            if code <= 0b001_0111 {
+                return 256 + code;
+            }
+            let code = code << 1 | self.bit();
+            match code {
+                0x30..=0xBF => code - 0x30,
+                0xC0..=0xC7 => 280 + code - 0xC0,
+                _ => 144 + (code << 1 | self.bit()) - 0x190,
+            }
+        }
+    }
+
+    /// Inflates the stored and fixed-Huffman blocks `GzipWriter` emits and
+    /// counts each kind.
+    fn inflate(data: &[u8]) -> (Vec<u8>, usize, usize) {
+        let mut reader = BitReader { data, pos: 0 };
+        let mut out: Vec<u8> = Vec::new();
+        let (mut stored, mut fixed) = (0, 0);
+        loop {
+            let last = reader.bits(1) == 1;
+            match reader.bits(2) {
+                0 => {
+                    stored += 1;
+                    let start = reader.pos.div_ceil(8);
+                    let len = u16::from_le_bytes([data[start], data[start + 1]]) as usize;
+                    let nlen = u16::from_le_bytes([data[start + 2], data[start + 3]]);
+                    assert_eq!(!nlen as usize, len);
+                    out.extend_from_slice(&data[start + 4..start + 4 + len]);
+                    reader.pos = (start + 4 + len) * 8;
+                }
+                1 => {
+                    fixed += 1;
+                    loop {
+                        let symbol = reader.fixed_symbol() as usize;
+                        match symbol {
+                            0..=255 => out.push(symbol as u8),
+                            256 => break,
+                            _ => {
+                                let code = symbol - 257;
+                                let length = LENGTH_BASE[code] as usize
+                                    + reader.bits(LENGTH_EXTRA[code] as u32) as usize;
+                                let code = reader.code(5) as usize;
+                                let distance = DIST_BASE[code] as usize
+                                    + reader.bits(DIST_EXTRA[code] as u32) as usize;
+                                for _ in 0..length {
+                                    out.push(out[out.len() - distance]);
+                                }
+                            }
+                        }
+                    }
+                }
+                kind => panic!("unexpected block type {}", kind),
+            }
+            if last {
+                return (out, stored, fixed);
+            }
+        }
+    }
+
+    /// Checks the gzip framing and returns the inflated data.
+    fn gunzip(data: &[u8]) -> Vec<u8> {
+        assert_eq!(data[..10], [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255]);
+        let (out, _, _) = inflate(&data[10..data.len() - 8]);
+        let trailer = &data[data.len() - 8..];
+        assert_eq!(trailer[..4], crc32(&out).to_le_bytes());
+        assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let table = crc32_table();
+        !data.iter().fold(0xFFFF_FFFF, |crc, &byte| {
+            table[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+        })
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut gzip = GzipWriter::new(Vec::new()).unwrap();
+        gzip.write_all(data).unwrap();
+        gzip.finish().unwrap()
+    }
+
+    /// Bytes no LZ77 match helps with.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Entry {
+        name: String,
+        kind: u8,
+        mode: u32,
+        mtime: u64,
+        link: String,
+        contents: Vec<u8>,
+    }
+
+    fn field(header: &[u8]) -> String {
+        let end = header.iter().position(|&b| b == 0).unwrap_or(header.len());
+        String::from_utf8(header[..end].to_vec()).unwrap()
+    }
+
+    fn octal(header: &[u8]) -> u64 {
+        u64::from_str_radix(field(header).trim(), 8).unwrap()
+    }
+
+    /// Reads back a ustar stream, applying GNU long-name records.
+    fn untar(data: &[u8]) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let (mut long_name, mut long_link) = (None, None);
+        let mut blocks = data.chunks(512);
+        while let Some(header) = blocks.next() {
+            if header.iter().all(|&b| b == 0) {
+                assert!(blocks.all(|block| block.iter().all(|&b| b == 0)));
+                return entries;
+            }
+            let mut summed = header.to_vec();
+            summed[148..156].fill(b' ');
+            assert_eq!(
+                summed.iter().map(|&b| b as u64).sum::<u64>(),
+                octal(&header[148..156])
+            );
+            assert_eq!(header[257..265], *b"ustar\x0000");
+
+            let size = octal(&header[124..136]) as usize;
+            let mut contents = Vec::new();
+            for _ in 0..size.div_ceil(512) {
+                contents.extend_from_slice(blocks.next().unwrap());
+            }
+            contents.truncate(size);
+            match header[156] {
+                b'L' => long_name = Some(field(&contents)),
+                b'K' => long_link = Some(field(&contents)),
+                kind => entries.push(Entry {
+                    name: long_name.take().unwrap_or_else(|| field(&header[..100])),
+                    kind,
+                    mode: octal(&header[100..108]) as u32,
+                    mtime: octal(&header[136..148]),
+                    link: long_link.take().unwrap_or_else(|| field(&header[157..257])),
+                    contents,
+                }),
+            }
+        }
+        panic!("no end-of-archive marker");
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn gzip_round_trips_through_fixed_and_stored_blocks() {
+        let text = b"cam1/2024-05-01/video.mp4 ".repeat(2000);
+        let compressed = gzip(&text);
+        assert!(compressed.len() < text.len() / 10);
+        let (_, stored, fixed) = inflate(&compressed[10..compressed.len() - 8]);
+        assert_eq!((stored, fixed), (0, 1));
+        assert_eq!(gunzip(&compressed), text);
+
+        // Incompressible input falls back to stored blocks of at most 64 KiB.
+        let random = noise(150_000);
+        let compressed = gzip(&random);
+        let (_, stored, fixed) = inflate(&compressed[10..compressed.len() - 8]);
+        assert_eq!((stored, fixed), (3, 0));
+        assert_eq!(gunzip(&compressed), random);
+
+        assert_eq!(gunzip(&gzip(b"")), b"");
+        // Longer than one chunk, so it spans several blocks.
+        let mixed: Vec<u8> = [text.clone(), random.clone()].concat().repeat(8);
+        assert!(mixed.len() > CHUNK_SIZE);
+        assert_eq!(gunzip(&gzip(&mixed)), mixed);
+    }
+
+    #[test]
+    fn tar_numbers_switch_to_base_256_when_octal_overflows() {
+        let mut field = [0xFFu8; 12];
+        write_number(&mut field, 0o755);
+        assert_eq!(&field, b"00000000755\0");
+        write_number(&mut field, 0o77_777_777_777);
+        assert_eq!(&field, b"77777777777\0");
+        write_number(&mut field, 1 << 33);
+        assert_eq!(field, [0x80, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn archive_folder_records_the_tree() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let tree = MemoryFileSystem::new();
+        let folder = Path::new("/srv/cam1/2024-05-01");
+        let long = "a".repeat(120);
+        tree.create_dir_all(&folder.join("hour01")).unwrap();
+        tree.write(&folder.join("hour01/video.mp4"), &noise(1000))
+            .unwrap();
+        tree.write(&folder.join("index.txt"), b"hour01/video.mp4\n")
+            .unwrap();
+        tree.write(&folder.join(&long), b"").unwrap();
+        tree.symlink(Path::new("hour01/video.mp4"), &folder.join("latest"))
+            .unwrap();
+        tree.symlink(Path::new(&long), &folder.join("long-link"))
+            .unwrap();
+        for path in ["", "hour01", "hour01/video.mp4", "index.txt"] {
+            tree.set_age(&folder.join(path), now, 1);
+        }
+
+        let archive_dir = Path::new("/srv/archive");
+        let (path, size) = archive_folder(&tree, folder, archive_dir).unwrap();
+        assert_eq!(path, archive_dir.join("2024-05-01.tar.gz"));
+        assert_eq!(tree.names(archive_dir), ["2024-05-01.tar.gz"]);
+
+        let mut compressed = Vec::new();
+        tree.open(&path)
+            .unwrap()
+            .read_to_end(&mut compressed)
+            .unwrap();
+        assert_eq!(compressed.len() as u64, size);
+        let entries = untar(&gunzip(&compressed));
+        let (created, dated) = (1_700_000_000, 1_700_000_000 - 24 * 60 * 60);
+        let entry = |name: &str, kind, mode, mtime, link: &str, contents: &[u8]| Entry {
+            name: format!("2024-05-01{}", name),
+            kind,
+            mode,
+            mtime,
+            link: link.to_string(),
+            contents: contents.to_vec(),
+        };
+        let expected = [
+            entry("/", b'5', 0o755, dated, "", b""),
+            entry(&format!("/{}", long), b'0', 0o644, created, "", b""),
+            entry("/hour01/", b'5', 0o755, dated, "", b""),
+            entry("/hour01/video.mp4", b'0', 0o644, dated, "", &noise(1000)),
+            entry("/index.txt", b'0', 0o644, dated, "", b"hour01/video.mp4\n"),
+            entry("/latest", b'2', 0o644, created, "hour01/video.mp4", b""),
+            entry("/long-link", b'2', 0o644, created, &long, b""),
+        ];
+        assert_eq!(entries, expected);
+
+        // A second archive of the same name gets a suffix.
+        let (again, _) = archive_folder(&tree, folder, archive_dir).unwrap();
+        assert_eq!(again, archive_dir.join("2024-05-01.1.tar.gz"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_utf8_fail_without_leaving_a_partial_archive() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let tree = MemoryFileSystem::new();
+        let folder = Path::new("/srv/cam1/day1");
+        let bad = folder.join(OsStr::from_bytes(b"clip-\xff.mp4"));
+        tree.create_dir_all(folder).unwrap();
+        tree.write(&folder.join("ok.mp4"), b"ok").unwrap();
+        tree.write(&bad, b"bad").unwrap();
+
+        let archive_dir = Path::new("/srv/archive");
+        let err = archive_folder(&tree, folder, archive_dir).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("is not valid UTF-8"));
+        assert!(tree.names(archive_dir).is_empty());
+
+        tree.remove_file(&bad).unwrap();
+        tree.symlink(Path::new(OsStr::from_bytes(b"\xfe")), &folder.join("link"))
+            .unwrap();
+        assert!(archive_folder(&tree, folder, archive_dir).is_err());
+        assert!(tree.names(archive_dir).is_empty());
+    }
+}
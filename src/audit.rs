@@ -0,0 +1,161 @@
+//! The `AUDIT_LOG`: one JSON line per completed deletion, kept apart from the
+//! operational log so it can be retained and shipped on its own terms.
+
+use std::fs;
+use std::io::Write;
+use std::time::Duration;
+
+use crate::clock;
+use crate::config::Config;
+use crate::logging::{format_timestamp, json_escape};
+
+/// One completed deletion, as recorded in the audit log.
+pub(crate) struct AuditRecord<'a> {
+    pub(crate) path: &'a str,
+    /// `folder`, `file` or `folder contents`.
+    pub(crate) kind: &'a str,
+    /// `delete`, `trash` or `archive`.
+    pub(crate) action: &'a str,
+    pub(crate) bytes: u64,
+    /// Age by the `SORT_BY` timestamp, when it could be read.
+    pub(crate) age: Option<Duration>,
+    /// What picked the entry: the `STRATEGY` name or `extensions_priority`.
+    pub(crate) policy: &'a str,
+}
+
+impl AuditRecord<'_> {
+    fn to_json(&self, config: &Config) -> String {
+        format!(
+            "{{\"timestamp\":{},\"instance\":{},\"path\":{},\"kind\":{},\"action\":{},\"bytes\":{},\"age_seconds\":{},\"policy\":{}}}",
+            json_escape(&format_timestamp(clock::now())),
+            json_escape(&config.instance_id),
+            json_escape(self.path),
+            json_escape(self.kind),
+            json_escape(self.action),
+            self.bytes,
+            self.age
+                .map(|age| age.as_secs().to_string())
+                .unwrap_or_else(|| "null".to_string()),
+            json_escape(self.policy)
+        )
+    }
+}
+
+/// Appends `record` to `AUDIT_LOG`, when set. The file is only ever appended
+/// to, one line per write, so concurrent instances cannot interleave records.
+pub(crate) fn audit_deletion(config: &Config, record: &AuditRecord) -> std::io::Result<()> {
+    let Some(audit_log) = &config.audit_log else {
+        return Ok(());
+    };
+    let mut line = record.to_json(config);
+    line.push('\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(audit_log)?
+        .write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use crate::cleanup::clean_disk;
+    use crate::clock::TestClock;
+    use crate::config::Config;
+    use crate::test_support::{MemoryDisk, MemoryFileSystem, TempDir};
+
+    /// Five 100-byte recordings under `base/cam1`, leaving a 2000-byte disk
+    /// 15% free.
+    fn setup(now: SystemTime, base: &Path) -> (MemoryFileSystem, MemoryDisk) {
+        let tree = MemoryFileSystem::new();
+        for (recording, days_old) in [("a", 50), ("b", 40), ("c", 30), ("d", 20), ("e", 10)] {
+            let folder = base.join("cam1").join(recording);
+            tree.file(&folder.join("video"), 100, now, days_old);
+            tree.set_age(&folder, now, days_old);
+        }
+        let disks = MemoryDisk {
+            fs: tree.clone(),
+            total: 2000,
+            reserved: 1200,
+        };
+        (tree, disks)
+    }
+
+    /// The `path` and `bytes` of every line in the audit log.
+    fn audited(audit_log: &Path) -> Vec<(String, u64)> {
+        let Ok(contents) = std::fs::read_to_string(audit_log) else {
+            return Vec::new();
+        };
+        contents
+            .lines()
+            .map(|line| {
+                let field = |name: &str| {
+                    let start = line.find(&format!("\"{}\":", name)).unwrap() + name.len() + 3;
+                    let rest = &line[start..];
+                    let end = rest.find([',', '}']).unwrap();
+                    rest[..end].trim_matches('"').to_string()
+                };
+                assert_eq!(field("action"), "delete");
+                assert_eq!(field("kind"), "folder");
+                (field("path"), field("bytes").parse().unwrap())
+            })
+            .collect()
+    }
+
+    fn audited_config(dir: &TempDir, audit_log: &Path, extra: &str) -> Config {
+        Config::for_test(
+            dir.path(),
+            &format!(
+                "depth = 1\nstop_threshold = 33\nlog.audit = {:?}\n{}",
+                audit_log.to_string_lossy(),
+                extra
+            ),
+        )
+    }
+
+    #[test]
+    fn every_deleted_folder_gets_one_audit_record() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("audit");
+        let base = dir.path().join("base");
+        let audit_log = dir.path().join("audit.log");
+        let (tree, disks) = setup(now, &base);
+        let config = audited_config(&dir, &audit_log, "");
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        let cam1 = base.join("cam1");
+        let expected: Vec<(String, u64)> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| (cam1.join(name).to_string_lossy().to_string(), 100))
+            .collect();
+        assert_eq!(stats.deleted, expected);
+        assert_eq!(audited(&audit_log), expected);
+    }
+
+    #[test]
+    fn dry_runs_and_failed_deletions_are_not_audited() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("audit-skipped");
+        let base = dir.path().join("base");
+        let audit_log = dir.path().join("audit.log");
+        let (tree, disks) = setup(now, &base);
+
+        let config = audited_config(&dir, &audit_log, "dry_run = true");
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+        assert!(!stats.deleted.is_empty());
+        assert_eq!(audited(&audit_log), []);
+
+        let cam1 = base.join("cam1");
+        tree.pin(&cam1.join("a"), std::io::ErrorKind::PermissionDenied);
+        let config = audited_config(&dir, &audit_log, "");
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+        assert_eq!(stats.deleted, []);
+        assert_eq!(stats.skipped.errored, 1);
+        assert_eq!(audited(&audit_log), []);
+    }
+}
@@ -0,0 +1,91 @@
+//! The `check` subcommand: reports disk pressure for every base directory as
+//! a monitoring-plugin exit status, without deleting anything.
+
+use std::process::ExitCode;
+
+use crate::config::Config;
+use crate::logging::{log_message, LogLevel};
+use crate::storage::DiskInfo;
+
+/// How close the most pressed base directory is to needing cleanup, from
+/// best to worst. The exit codes follow the Nagios plugin convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pressure {
+    /// Free space is above the stop threshold (exit code 0).
+    Ok,
+    /// Free space is below the stop threshold but cleanup would not start
+    /// yet (exit code 1).
+    Warning,
+    /// Free space is below the start threshold, so cleanup would run
+    /// (exit code 2).
+    Critical,
+    /// A base directory's disk could not be found (exit code 3).
+    Unknown,
+}
+
+impl Pressure {
+    pub fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            Pressure::Ok => 0,
+            Pressure::Warning => 1,
+            Pressure::Critical => 2,
+            Pressure::Unknown => 3,
+        })
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Pressure::Ok => "OK",
+            Pressure::Warning => "WARNING",
+            Pressure::Critical => "CRITICAL",
+            Pressure::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+/// Logs the free space of every base directory against its thresholds and
+/// prints a one-line plugin status, such as `DISK WARNING - /srv/a 22.00%
+/// (4.10 GiB) free, below the 25% stop threshold`. Returns the worst
+/// pressure found.
+pub(crate) fn check(config: &Config, disks: &dyn DiskInfo) -> std::io::Result<Pressure> {
+    let mut worst = Pressure::Ok;
+    let mut details = Vec::new();
+    for base_dir in &config.base_dirs {
+        let dir_config = config.for_dir(base_dir);
+        let (pressure, detail) = match disks.check_storage(dir_config, base_dir) {
+            None => (Pressure::Unknown, format!("{}: disk not found", base_dir)),
+            Some(free) if free.below_start(dir_config) => (
+                Pressure::Critical,
+                format!(
+                    "{} {} free, below the {} start threshold",
+                    base_dir,
+                    free,
+                    dir_config.start_label()
+                ),
+            ),
+            Some(free) if !free.above_stop(dir_config) => (
+                Pressure::Warning,
+                format!(
+                    "{} {} free, below the {} stop threshold",
+                    base_dir,
+                    free,
+                    dir_config.stop_label()
+                ),
+            ),
+            Some(free) => (Pressure::Ok, format!("{} {} free", base_dir, free)),
+        };
+        log_message(
+            &config.log_path,
+            match pressure {
+                Pressure::Ok => LogLevel::Info,
+                _ => LogLevel::Warn,
+            },
+            &format!("[check] {}: {}", pressure.label(), detail),
+        )?;
+        worst = worst.max(pressure);
+        details.push(detail);
+    }
+
+    println!("DISK {} - {}", worst.label(), details.join("; "));
+    Ok(worst)
+}
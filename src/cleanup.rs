@@ -0,0 +1,1441 @@
+//! The clean_disk loop that deletes folders until free space recovers and the
+//! directory fits its size quota.
+
+use std::collections::BTreeSet;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::SystemTime;
+
+use crate::clock;
+use crate::config::{invalid_input, Config};
+use crate::dedup::{start_hash_cache, stop_hash_cache};
+use crate::delete::delete_entry;
+use crate::filesystem::FileSystem;
+use crate::logging::{log_event, log_message, span, LogEvent, LogLevel};
+use crate::scan::{
+    get_highest_score_entry, get_largest_entry, get_oldest_entry, is_excluded, is_real_dir,
+    on_base_device, priority_files, ranked_candidates, skip_entry, take_skips, track_skips,
+    Strategy,
+};
+use crate::signal;
+use crate::size::{format_bytes, start_size_cache, stop_size_cache};
+use crate::storage::{bytes_to_stop, DiskInfo};
+
+/// Totals for one `clean_disk` call. In dry-run mode these describe what
+/// would have been removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanupStats {
+    pub folders_deleted: usize,
+    pub bytes_freed: u64,
+    /// Path and size of every removed folder, in deletion order.
+    pub deleted: Vec<(String, u64)>,
+    /// Entries passed over during the call, by reason.
+    pub skipped: SkipSummary,
+}
+
+/// Distinct entries `clean_disk` passed over, by reason. An entry seen on
+/// several passes is counted once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SkipSummary {
+    /// Matched `EXCLUDE`, missed `INCLUDE_PATTERN` or hidden with
+    /// `SKIP_HIDDEN`.
+    pub excluded: usize,
+    /// Younger than `MIN_AGE_DAYS`.
+    pub too_young: usize,
+    /// Smaller than `MIN_FOLDER_BYTES`.
+    pub too_small: usize,
+    /// Modified within `ACTIVE_GRACE_SECONDS`.
+    pub in_use: usize,
+    /// On another filesystem, with `STAY_ON_DEVICE`.
+    pub other_device: usize,
+    /// Outside every `.cleanup-ok` directory, with `REQUIRE_MARKER`.
+    pub unmarked: usize,
+    /// Vanished or unreadable while being scanned or deleted.
+    pub errored: usize,
+}
+
+/// How far below a base directory candidates are picked (`DEPTH`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    /// Exactly this many levels down; `0` picks among the base's children.
+    Levels(usize),
+    /// `0` when the base's children look like the rotation units
+    /// themselves, `2` otherwise; see `resolve_depth`.
+    Auto,
+}
+
+impl Depth {
+    pub(crate) fn parse(value: &str) -> Option<Depth> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("auto") {
+            return Some(Depth::Auto);
+        }
+        value.parse().ok().map(Depth::Levels)
+    }
+}
+
+/// Whether a folder name carries a date, i.e. a run of at least four digits
+/// starting with `19` or `20`, as in `2024-05-01`, `20240501` or
+/// `cam1_2024_05`.
+fn is_dated_name(name: &str) -> bool {
+    name.split(|c: char| !c.is_ascii_digit())
+        .any(|digits| digits.len() >= 4 && (digits.starts_with("19") || digits.starts_with("20")))
+}
+
+/// The number of levels `find_selection_dirs` descends for `base_dir`. For
+/// `DEPTH=auto` the base is flat, picked at level 0, when it has child
+/// folders and each of them either carries a date in its name or holds no
+/// folders of its own; anything else keeps the default of two levels.
+pub(crate) fn resolve_depth(
+    config: &Config,
+    fs: &dyn FileSystem,
+    base_dir: &str,
+) -> std::io::Result<usize> {
+    if let Depth::Levels(levels) = config.depth {
+        return Ok(levels);
+    }
+
+    let base_dir = Path::new(base_dir);
+    let mut children = 0;
+    for path in fs.read_dir(base_dir)? {
+        if !is_real_dir(&path, config, fs)? || is_excluded(&path, config)? {
+            continue;
+        }
+        children += 1;
+        if path
+            .file_name()
+            .is_some_and(|name| is_dated_name(&name.to_string_lossy()))
+        {
+            continue;
+        }
+        let Some(grandchildren) = skip_entry(config, &path, fs.read_dir(&path))? else {
+            continue;
+        };
+        for grandchild in grandchildren {
+            if is_real_dir(&grandchild, config, fs)? {
+                return Ok(2);
+            }
+        }
+    }
+    Ok(if children > 0 { 0 } else { 2 })
+}
+
+/// How many passes `clean_disk` makes over the selection directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanMode {
+    /// One deletion per selection directory, then stop; the next scheduled
+    /// run continues if space is still short.
+    Single,
+    /// Keep passing until the targets are met or nothing is left to delete.
+    UntilThreshold,
+}
+
+impl CleanMode {
+    pub(crate) fn parse(value: &str) -> Option<CleanMode> {
+        match value.trim().to_lowercase().as_str() {
+            "single" | "once" => Some(CleanMode::Single),
+            "until-threshold" | "until_threshold" => Some(CleanMode::UntilThreshold),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            CleanMode::Single => "single",
+            CleanMode::UntilThreshold => "until-threshold",
+        }
+    }
+}
+
+/// What happens when cleanup ends with a directory still short of its
+/// targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnFailure {
+    /// Log a warning and end the run with exit code 3.
+    Log,
+    /// Treat the directory as failed, so the run ends with exit code 1.
+    ExitError,
+    /// Run `ON_FAILURE_HOOK`, then carry on as with `Log`.
+    Hook,
+}
+
+impl OnFailure {
+    pub(crate) fn parse(value: &str) -> Option<OnFailure> {
+        match value.trim().to_lowercase().as_str() {
+            "log" => Some(OnFailure::Log),
+            "exit-error" | "exit_error" => Some(OnFailure::ExitError),
+            "hook" => Some(OnFailure::Hook),
+            _ => None,
+        }
+    }
+}
+
+/// How a run ended, from best to worst. Each outcome has its own process exit
+/// code so wrapper scripts can tell a disk that is still full after cleanup
+/// apart from a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    /// Every directory had enough free space and fit its quota (exit code 0).
+    NoActionNeeded,
+    /// Cleanup ran and brought every directory back within its targets, or
+    /// in a dry run would have (exit code 2).
+    CleanedSufficient,
+    /// Cleanup ran but at least one directory is still below the stop
+    /// threshold or over its quota (exit code 3).
+    CleanedInsufficient,
+    /// Another instance holds the lock file, so nothing was done
+    /// (exit code 4).
+    AlreadyRunning,
+    /// The run failed (exit code 1).
+    Error,
+}
+
+impl Outcome {
+    pub fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            Outcome::NoActionNeeded => 0,
+            Outcome::Error => 1,
+            Outcome::CleanedSufficient => 2,
+            Outcome::CleanedInsufficient => 3,
+            Outcome::AlreadyRunning => 4,
+        })
+    }
+}
+
+/// Base directories must sit at least this many levels below the filesystem
+/// root, so a `DIRPATH` of `/` or `/home` cannot wipe system directories.
+const MIN_BASE_DEPTH: usize = 2;
+
+/// Refuses base directories that are a filesystem root or shallower than
+/// `MIN_BASE_DEPTH`, unless `I_KNOW_WHAT_IM_DOING` is set. Symlinks and `..`
+/// are resolved first so they cannot hide a root. The error is logged by the
+/// caller like any other per-directory failure.
+pub(crate) fn check_base_dir(
+    config: &Config,
+    fs: &dyn FileSystem,
+    base_dir: &str,
+) -> std::io::Result<()> {
+    let path = fs
+        .canonicalize(Path::new(base_dir))
+        .unwrap_or_else(|_| PathBuf::from(base_dir));
+    let depth = path
+        .components()
+        .filter(|component| matches!(component, std::path::Component::Normal(_)))
+        .count();
+    if depth >= MIN_BASE_DEPTH || config.allow_shallow_base_dir {
+        return Ok(());
+    }
+
+    let message = format!(
+        "Refusing to clean {}: it is {} and must be at least {} levels below the filesystem root. \
+         Set I_KNOW_WHAT_IM_DOING=true to override.",
+        path.to_string_lossy(),
+        if depth == 0 {
+            "a filesystem root".to_string()
+        } else {
+            format!("only {} level(s) deep", depth)
+        },
+        MIN_BASE_DEPTH
+    );
+    Err(invalid_input(message))
+}
+
+/// Asks on `output` whether to delete `path` and reads the answer from
+/// `input`. Only `y` confirms; anything else, including end of input, skips.
+pub(crate) fn confirm_deletion(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    path: &Path,
+    size: u64,
+) -> std::io::Result<bool> {
+    write!(
+        output,
+        "Delete {} ({})? [y/N] ",
+        path.to_string_lossy(),
+        format_bytes(size)
+    )?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+/// Deletions smaller than this are too small to measure reliably against
+/// other writers on the same disk, so they are not verified.
+const RECLAIM_EPSILON: u64 = 1024 * 1024;
+
+/// Consecutive deletions that free nothing before cleanup gives up.
+const MAX_UNRECLAIMED: usize = 3;
+
+/// Deletes folders until free space rises above the stop threshold and
+/// `dir_bytes`, the base directory's size when `MAX_DIR_BYTES` is set, fits
+/// under the quota. Returns how many folders and bytes were freed. With
+/// `EXTENSIONS_PRIORITY`, matching loose files are deleted first and folders
+/// only once they are exhausted.
+///
+/// After every real deletion the disk is measured again. If several deletions
+/// in a row do not give any space back, the files are most likely still held
+/// open by another process and cleanup stops instead of deleting more.
+pub(crate) fn clean_disk(
+    config: &Config,
+    disks: &dyn DiskInfo,
+    fs: &dyn FileSystem,
+    base_dir: &str,
+    mut dir_bytes: Option<u64>,
+) -> std::io::Result<CleanupStats> {
+    let mut span = span("clean_disk", || vec![("dir", base_dir.to_string())]);
+    let started = clock::now();
+    check_base_dir(config, fs, base_dir)?;
+    let log_path = config.log_path.as_str();
+    log_message(
+        log_path,
+        LogLevel::Info,
+        &format!("[{}] CLEAN_MODE={}", base_dir, config.clean_mode.name()),
+    )?;
+    let depth = resolve_depth(config, fs, base_dir)?;
+    if config.depth == Depth::Auto {
+        log_message(
+            log_path,
+            LogLevel::Info,
+            &format!(
+                "[{}] DEPTH=auto: {}",
+                base_dir,
+                if depth == 0 {
+                    "flat layout, picking among the base directory's own folders"
+                } else {
+                    "nested layout, picking two levels down"
+                }
+            ),
+        )?;
+    }
+    let stop_target = config.stop_label();
+    let dry_run = config.dry_run;
+    let mut deleted_count: usize = 0;
+    let mut deleted_bytes = 0;
+    let mut deleted = Vec::new();
+    track_skips();
+    start_size_cache();
+    start_hash_cache();
+    // Moving to a trash directory on the same disk frees nothing by design,
+    // and an archive of incompressible data frees little.
+    let verify_reclaimed = !dry_run && config.trash_dir.is_none() && config.archive_dir.is_none();
+    let mut unreclaimed: usize = 0;
+    // Parents of deleted folders, checked for emptiness by PRUNE_EMPTY.
+    let mut emptied: BTreeSet<PathBuf> = BTreeSet::new();
+    // `--interactive` only prompts when someone can answer; under cron or a
+    // service manager stdin is not a terminal and the run must not block.
+    let prompt = config.interactive && !dry_run && std::io::stdin().is_terminal();
+    let mut declined: BTreeSet<PathBuf> = BTreeSet::new();
+
+    // A forced run rotates folders regardless of free space, so it has no
+    // use for the priority phase.
+    let keep_going = config.extensions_priority.is_empty()
+        || config.force
+        || clean_priority_files(
+            config,
+            disks,
+            fs,
+            base_dir,
+            started,
+            &mut dir_bytes,
+            prompt,
+            &mut deleted,
+        )?;
+    let priority_count = deleted.len();
+    let priority_bytes: u64 = deleted.iter().map(|(_, bytes)| bytes).sum();
+    deleted_count += priority_count;
+    deleted_bytes += priority_bytes;
+    if priority_count > 0 {
+        log_message(
+            log_path,
+            LogLevel::Info,
+            &format!(
+                "[{}] EXTENSIONS_PRIORITY phase freed {} across {} files",
+                base_dir,
+                format_bytes(priority_bytes),
+                priority_count
+            ),
+        )?;
+    }
+
+    'cleanup: loop {
+        // Shutdown, MAX_DELETIONS or MAX_RUNTIME_SECONDS already ended the
+        // priority phase.
+        if !keep_going {
+            break;
+        }
+
+        // A disk that vanished mid-cleanup cannot be measured; treat it as
+        // having enough free space rather than deleting blindly.
+        let free_space = disks.check_storage(config, base_dir);
+        let free_space_label = free_space
+            .map(|free| free.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let over_quota = dir_bytes
+            .zip(config.max_dir_bytes)
+            .filter(|(dir_bytes, max_dir_bytes)| dir_bytes > max_dir_bytes);
+
+        if config.force {
+            log_message(
+                log_path,
+                LogLevel::Info,
+                &format!(
+                    "[{}] Forced run: deleting one folder per subfolder regardless of free space ({})",
+                    base_dir, free_space_label
+                ),
+            )?;
+        } else if let Some((dir_bytes, max_dir_bytes)) = over_quota {
+            log_message(
+                log_path,
+                LogLevel::Info,
+                &format!(
+                    "[{}] Directory size {} exceeds the {} quota. Cleaning up...",
+                    base_dir,
+                    format_bytes(dir_bytes),
+                    format_bytes(max_dir_bytes)
+                ),
+            )?;
+        } else if free_space.is_none_or(|free| free.above_stop(config)) {
+            log_message(
+                log_path,
+                LogLevel::Info,
+                &format!(
+                    "[{}] Free space is {}, above the {} stop threshold. Exiting cleanup.",
+                    base_dir, free_space_label, stop_target
+                ),
+            )?;
+            break;
+        } else {
+            log_message(
+                log_path,
+                LogLevel::Info,
+                &format!(
+                    "[{}] Free space is {}, below the {} stop threshold. Cleaning up...",
+                    base_dir, free_space_label, stop_target
+                ),
+            )?;
+        }
+
+        // What this pass has to free for the stop target, so it can end
+        // part-way instead of taking a folder from every selection
+        // directory. Unknown when the disk cannot be measured.
+        let needed = disks
+            .disk_space(config, base_dir)
+            .filter(|_| !config.force)
+            .map(|(total, available)| bytes_to_stop(config, total, available));
+        let mut freed_this_pass = 0;
+        let mut deleted_this_pass = false;
+        let mut declined_this_pass = false;
+        let mut selection_dirs = Vec::new();
+        find_selection_dirs(config, fs, Path::new(base_dir), depth, &mut selection_dirs)?;
+        for selection_dir in selection_dirs {
+            // Checked between folders only: a folder being removed when the
+            // signal arrives is finished, so nothing is left half-deleted.
+            if signal::stop_requested() {
+                log_message(
+                    log_path,
+                    LogLevel::Warn,
+                    &format!(
+                        "[{}] Shutdown requested; stopping after current item",
+                        base_dir
+                    ),
+                )?;
+                break 'cleanup;
+            }
+            if runtime_exceeded(config, base_dir, started)? {
+                break 'cleanup;
+            }
+            let selection_str = selection_dir.to_string_lossy();
+            let candidate = if prompt {
+                // Walk past entries the operator already declined instead of
+                // offering the same one again on every pass.
+                ranked_candidates(&selection_str, config, fs).map(|ranked| {
+                    ranked
+                        .into_iter()
+                        .find(|(path, _)| !declined.contains(path))
+                })
+            } else {
+                let candidate = match config.strategy {
+                    Strategy::Oldest => get_oldest_entry(&selection_str, config, fs, config.target),
+                    Strategy::Largest => {
+                        get_largest_entry(&selection_str, config, fs, config.target)
+                    }
+                    Strategy::Score => {
+                        get_highest_score_entry(&selection_str, config, fs, config.target)
+                    }
+                    Strategy::Dedup => {
+                        ranked_candidates(&selection_str, config, fs).map(|ranked| {
+                            ranked
+                                .into_iter()
+                                .next()
+                                .map(|(path, _)| path.to_string_lossy().to_string())
+                        })
+                    }
+                };
+                candidate.map(|candidate| candidate.map(|path| (PathBuf::from(path), 0)))
+            };
+            let Some(candidate) = skip_entry(config, &selection_dir, candidate)? else {
+                continue;
+            };
+
+            if let Some((candidate_path, size)) = candidate {
+                if prompt {
+                    let stdin = std::io::stdin();
+                    if !confirm_deletion(
+                        &mut stdin.lock(),
+                        &mut std::io::stdout(),
+                        &candidate_path,
+                        size,
+                    )? {
+                        log_message(
+                            log_path,
+                            LogLevel::Info,
+                            &format!("Skipped by operator: {}", candidate_path.to_string_lossy()),
+                        )?;
+                        declined.insert(candidate_path);
+                        declined_this_pass = true;
+                        continue;
+                    }
+                }
+                let candidate = candidate_path.to_string_lossy().to_string();
+                let available_before = if verify_reclaimed {
+                    disks.available_bytes(config, base_dir)
+                } else {
+                    None
+                };
+                let Some(freed) = skip_entry(
+                    config,
+                    Path::new(&candidate),
+                    delete_entry(&candidate, config, fs, config.strategy.name()),
+                )?
+                else {
+                    continue;
+                };
+                deleted_bytes += freed;
+                dir_bytes = dir_bytes.map(|bytes| bytes.saturating_sub(freed));
+                deleted_count += 1;
+                deleted.push((candidate.clone(), freed));
+                deleted_this_pass = true;
+                emptied.insert(selection_dir.clone());
+
+                if let Some(before) = available_before.filter(|_| freed >= RECLAIM_EPSILON) {
+                    let after = disks.available_bytes(config, base_dir).unwrap_or(before);
+                    if after.saturating_sub(before) < RECLAIM_EPSILON {
+                        unreclaimed += 1;
+                    } else {
+                        unreclaimed = 0;
+                    }
+                    if unreclaimed >= MAX_UNRECLAIMED {
+                        log_message(
+                            log_path,
+                            LogLevel::Warn,
+                            &format!(
+                                "[{}] Free space did not increase after {} deletions; \
+                                             deleted files may still be held open by another \
+                                             process. Stopping cleanup.",
+                                base_dir, unreclaimed
+                            ),
+                        )?;
+                        break 'cleanup;
+                    }
+                }
+
+                if config
+                    .max_deletions
+                    .is_some_and(|max_deletions| deleted_count >= max_deletions)
+                {
+                    log_message(
+                        log_path,
+                        LogLevel::Warn,
+                        &format!(
+                            "[{}] Reached max deletions limit ({})",
+                            base_dir, deleted_count
+                        ),
+                    )?;
+                    break 'cleanup;
+                }
+
+                freed_this_pass += freed;
+                let within_quota = dir_bytes
+                    .zip(config.max_dir_bytes)
+                    .is_none_or(|(dir_bytes, max_dir_bytes)| dir_bytes <= max_dir_bytes);
+                if let Some(needed) = needed.filter(|needed| freed_this_pass >= *needed) {
+                    if within_quota {
+                        // The next pass re-measures the disk, and carries on
+                        // if the deletions did not give the space back.
+                        log_message(
+                            log_path,
+                            LogLevel::Info,
+                            &format!(
+                                "[{}] Freed {} this pass, enough for the {} stop threshold ({} needed); re-checking free space",
+                                base_dir,
+                                format_bytes(freed_this_pass),
+                                stop_target,
+                                format_bytes(needed)
+                            ),
+                        )?;
+                        break;
+                    }
+                }
+            } else {
+                log_message(
+                    log_path,
+                    LogLevel::Debug,
+                    &format!(
+                        "No subfolders found in: {}",
+                        selection_dir.to_string_lossy()
+                    ),
+                )?;
+            }
+        }
+
+        // Nothing is actually removed in dry-run mode, so another pass would
+        // pick the same folders and free space would never recover. A forced
+        // run is a single rotation pass by definition.
+        if dry_run || config.force || config.clean_mode == CleanMode::Single {
+            break;
+        }
+
+        // A pass where every selection directory came up empty, or every
+        // deletion failed, would repeat forever: nothing changed on disk.
+        // Declined entries are remembered, so those passes still advance.
+        if !deleted_this_pass && !declined_this_pass {
+            log_message(
+                log_path,
+                LogLevel::Warn,
+                "No more deletable folders; aborting cleanup",
+            )?;
+            break;
+        }
+    }
+
+    if config.prune_empty && !dry_run {
+        prune_empty(config, fs, base_dir, &emptied)?;
+    }
+
+    let message = if dry_run {
+        format!(
+            "[DRY-RUN] Would free {} across {} folders",
+            format_bytes(deleted_bytes),
+            deleted_count
+        )
+    } else {
+        format!(
+            "Cleanup complete: freed {} across {} folders",
+            format_bytes(deleted_bytes),
+            deleted_count
+        )
+    };
+    log_event(
+        log_path,
+        &LogEvent::new(LogLevel::Info, "summary", message).freed_bytes(deleted_bytes),
+    )?;
+
+    if priority_count > 0 {
+        log_message(
+            log_path,
+            LogLevel::Info,
+            &format!(
+                "[{}] Freed {} from EXTENSIONS_PRIORITY files and {} from folders",
+                base_dir,
+                format_bytes(priority_bytes),
+                format_bytes(deleted_bytes - priority_bytes)
+            ),
+        )?;
+    }
+
+    stop_size_cache();
+    stop_hash_cache();
+    let skipped = take_skips();
+    if skipped != SkipSummary::default() {
+        log_message(
+            log_path,
+            if skipped.errored > 0 {
+                LogLevel::Warn
+            } else {
+                LogLevel::Info
+            },
+            &format!(
+                "[{}] Deleted {}; skipped {} excluded, {} too young, {} too small, {} in use, {} on another device, {} unmarked; {} errored",
+                base_dir,
+                deleted_count,
+                skipped.excluded,
+                skipped.too_young,
+                skipped.too_small,
+                skipped.in_use,
+                skipped.other_device,
+                skipped.unmarked,
+                skipped.errored
+            ),
+        )?;
+    }
+
+    span.record("folders_deleted", deleted_count);
+    span.record("bytes_freed", deleted_bytes);
+    Ok(CleanupStats {
+        folders_deleted: deleted_count,
+        bytes_freed: deleted_bytes,
+        deleted,
+        skipped,
+    })
+}
+
+/// Returns true, after logging it, once the `clean_disk` call that began at
+/// `started` has used up `MAX_RUNTIME_SECONDS` by the installed clock.
+fn runtime_exceeded(config: &Config, base_dir: &str, started: SystemTime) -> std::io::Result<bool> {
+    let Some(max_runtime) = config.max_runtime else {
+        return Ok(false);
+    };
+    if clock::age(started) < max_runtime {
+        return Ok(false);
+    }
+    log_message(
+        &config.log_path,
+        LogLevel::Warn,
+        &format!(
+            "[{}] Runtime budget exceeded (MAX_RUNTIME_SECONDS={}); stopping cleanup",
+            base_dir,
+            max_runtime.as_secs()
+        ),
+    )?;
+    Ok(true)
+}
+
+/// Deletes the `EXTENSIONS_PRIORITY` files below `base_dir`, oldest first,
+/// until free space is above the stop threshold and `dir_bytes` fits the
+/// quota, adding each one to `deleted`. In dry-run mode every such file is
+/// listed. Returns false when cleanup must stop altogether because shutdown
+/// was requested, `MAX_DELETIONS` was reached or `MAX_RUNTIME_SECONDS` ran
+/// out.
+#[allow(clippy::too_many_arguments)]
+fn clean_priority_files(
+    config: &Config,
+    disks: &dyn DiskInfo,
+    fs: &dyn FileSystem,
+    base_dir: &str,
+    started: SystemTime,
+    dir_bytes: &mut Option<u64>,
+    prompt: bool,
+    deleted: &mut Vec<(String, u64)>,
+) -> std::io::Result<bool> {
+    let log_path = config.log_path.as_str();
+    for (path, size) in priority_files(Path::new(base_dir), config, fs)? {
+        let over_quota = dir_bytes
+            .zip(config.max_dir_bytes)
+            .is_some_and(|(dir_bytes, max_dir_bytes)| dir_bytes > max_dir_bytes);
+        if !config.dry_run
+            && !over_quota
+            && disks
+                .check_storage(config, base_dir)
+                .is_none_or(|free| free.above_stop(config))
+        {
+            break;
+        }
+        if signal::stop_requested() {
+            log_message(
+                log_path,
+                LogLevel::Warn,
+                &format!(
+                    "[{}] Shutdown requested; stopping after current item",
+                    base_dir
+                ),
+            )?;
+            return Ok(false);
+        }
+        if runtime_exceeded(config, base_dir, started)? {
+            return Ok(false);
+        }
+        if prompt {
+            let stdin = std::io::stdin();
+            if !confirm_deletion(&mut stdin.lock(), &mut std::io::stdout(), &path, size)? {
+                log_message(
+                    log_path,
+                    LogLevel::Info,
+                    &format!("Skipped by operator: {}", path.to_string_lossy()),
+                )?;
+                continue;
+            }
+        }
+
+        let candidate = path.to_string_lossy().to_string();
+        let Some(freed) = skip_entry(
+            config,
+            &path,
+            delete_entry(&candidate, config, fs, "extensions_priority"),
+        )?
+        else {
+            continue;
+        };
+        *dir_bytes = dir_bytes.map(|bytes| bytes.saturating_sub(freed));
+        deleted.push((candidate, freed));
+        if config
+            .max_deletions
+            .is_some_and(|max_deletions| deleted.len() >= max_deletions)
+        {
+            log_message(
+                log_path,
+                LogLevel::Warn,
+                &format!(
+                    "[{}] Reached max deletions limit ({})",
+                    base_dir,
+                    deleted.len()
+                ),
+            )?;
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Collects the directories `depth` levels below `dir` that candidates are
+/// picked from, descending only through real, non-excluded directories (on
+/// the base directory's device, with `STAY_ON_DEVICE`). With
+/// `DEPTH=0` that is `dir` itself.
+pub(crate) fn find_selection_dirs(
+    config: &Config,
+    fs: &dyn FileSystem,
+    dir: &Path,
+    depth: usize,
+    selection_dirs: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    if depth == 0 {
+        selection_dirs.push(dir.to_path_buf());
+        return Ok(());
+    }
+
+    for path in fs.read_dir(dir)? {
+        if is_real_dir(&path, config, fs)?
+            && !is_excluded(&path, config)?
+            && on_base_device(&path, config, fs)?
+        {
+            // A subtree that vanishes or cannot be read is skipped, but the
+            // base directory itself must be listable.
+            skip_entry(
+                config,
+                &path,
+                find_selection_dirs(config, fs, &path, depth - 1, selection_dirs),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes each directory in `emptied` that no longer has any entries, then
+/// walks up towards `base_dir` removing parents that became empty as a
+/// result. `base_dir` itself is always kept.
+fn prune_empty(
+    config: &Config,
+    fs: &dyn FileSystem,
+    base_dir: &str,
+    emptied: &BTreeSet<PathBuf>,
+) -> std::io::Result<()> {
+    let base_dir = Path::new(base_dir);
+    for dir in emptied {
+        let mut dir = dir.as_path();
+        while dir != base_dir && dir.starts_with(base_dir) {
+            let Some(entries) = skip_entry(config, dir, fs.read_dir(dir))? else {
+                break;
+            };
+            if !entries.is_empty() {
+                break;
+            }
+            if skip_entry(config, dir, fs.remove_dir(dir))?.is_none() {
+                break;
+            }
+            let path = dir.to_string_lossy();
+            log_event(
+                &config.log_path,
+                &LogEvent::new(
+                    LogLevel::Info,
+                    "prune",
+                    format!("Removed empty folder: {}", path),
+                )
+                .path(&path),
+            )?;
+            match dir.parent() {
+                Some(parent) => dir = parent,
+                None => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    use crate::clock::TestClock;
+    use crate::test_support::{MemoryDisk, MemoryFileSystem, TempDir};
+
+    /// A 2000-byte disk that is 1200 bytes full before the tree is added.
+    fn disk(tree: &MemoryFileSystem) -> MemoryDisk {
+        MemoryDisk {
+            fs: tree.clone(),
+            total: 2000,
+            reserved: 1200,
+        }
+    }
+
+    /// Two cameras of three 100-byte recordings each, so the disk starts at
+    /// 10% free; `(camera, recording, days old)`.
+    fn recordings(tree: &MemoryFileSystem, base: &Path, now: SystemTime) {
+        for (camera, recording, days_old) in [
+            ("cam1", "a", 30),
+            ("cam1", "b", 20),
+            ("cam1", "c", 10),
+            ("cam2", "d", 25),
+            ("cam2", "e", 15),
+            ("cam2", "f", 5),
+        ] {
+            let folder = base.join(camera).join(recording);
+            tree.file(&folder.join("video"), 100, now, days_old);
+            tree.set_age(&folder, now, days_old);
+        }
+    }
+
+    fn deleted_names(stats: &CleanupStats) -> Vec<String> {
+        stats
+            .deleted
+            .iter()
+            .map(|(path, _)| {
+                Path::new(path)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clean_disk_deletes_the_oldest_folders_until_above_the_stop_threshold() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-oldest");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        let disks = disk(&tree);
+        let config = Config::for_test(dir.path(), "stop_threshold = 25\ndepth = 1");
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        // The first pass takes the oldest of each camera and leaves 20% free;
+        // the second needs 101 more bytes and ends once the second camera
+        // gives them.
+        assert_eq!(deleted_names(&stats), ["a", "d", "b", "e"]);
+        assert_eq!(stats.folders_deleted, 4);
+        assert_eq!(stats.bytes_freed, 400);
+        assert_eq!(tree.names(&base.join("cam1")), ["c"]);
+        assert_eq!(tree.names(&base.join("cam2")), ["f"]);
+        assert_eq!(disks.available_bytes(&config, ""), Some(600));
+    }
+
+    #[test]
+    fn clean_disk_respects_min_age_and_stops_when_nothing_is_left() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-exhausted");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        let disks = disk(&tree);
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 50\ndepth = 1\nmin_age_days = 18",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        assert_eq!(deleted_names(&stats), ["a", "d", "b"]);
+        assert_eq!(stats.skipped.too_young, 3);
+        assert_eq!(tree.names(&base.join("cam1")), ["c"]);
+        assert_eq!(tree.names(&base.join("cam2")), ["e", "f"]);
+    }
+
+    #[test]
+    fn clean_disk_deletes_until_the_directory_fits_its_quota() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-quota");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        // 40% free, well above the stop threshold.
+        let disks = MemoryDisk {
+            fs: tree.clone(),
+            total: 2000,
+            reserved: 600,
+        };
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 25\ndepth = 1\nmax_dir_bytes = \"350\"",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), Some(600)).unwrap();
+
+        // 600 bytes against a 350-byte quota: the third deletion brings the
+        // directory down to 300 and ends cleanup.
+        assert_eq!(deleted_names(&stats), ["a", "d", "b"]);
+        assert_eq!(stats.bytes_freed, 300);
+        assert_eq!(tree.names(&base.join("cam1")), ["c"]);
+        assert_eq!(tree.names(&base.join("cam2")), ["e", "f"]);
+    }
+
+    #[test]
+    fn prune_empty_removes_selection_directories_left_empty() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-prune-empty");
+        let base = dir.path().join("base");
+        for (prune_empty, cam1) in [(true, false), (false, true)] {
+            let tree = MemoryFileSystem::new();
+            for (camera, recording, days_old) in
+                [("cam1", "a", 30), ("cam2", "d", 25), ("cam2", "e", 15)]
+            {
+                let folder = base.join(camera).join(recording);
+                tree.file(&folder.join("video"), 100, now, days_old);
+                tree.set_age(&folder, now, days_old);
+            }
+            let disks = MemoryDisk {
+                fs: tree.clone(),
+                total: 2000,
+                reserved: 1400,
+            };
+            let config = Config::for_test(
+                dir.path(),
+                &format!(
+                    "stop_threshold = 24\ndepth = 1\nprune_empty = {}",
+                    prune_empty
+                ),
+            );
+
+            let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+            assert_eq!(deleted_names(&stats), ["a", "d"]);
+            assert_eq!(tree.names(&base).contains(&"cam1".to_string()), cam1);
+            assert_eq!(tree.names(&base.join("cam2")), ["e"]);
+        }
+    }
+
+    #[test]
+    fn target_files_deletes_the_oldest_loose_file_and_keeps_folders() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-target-files");
+        let base = dir.path().join("base");
+        let cam = base.join("cam1");
+        let tree = MemoryFileSystem::new();
+        for (folder, days_old) in [("a", 60), ("b", 20)] {
+            tree.file(&cam.join(folder).join("video"), 100, now, days_old);
+            tree.set_age(&cam.join(folder), now, days_old);
+        }
+        tree.file(&cam.join("old.mp4"), 100, now, 40);
+        tree.file(&cam.join("new.mp4"), 100, now, 10);
+        let disks = disk(&tree);
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 22\ndepth = 1\ntarget = \"files\"",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        // The mock refuses remove_dir_all on a file, so this went through
+        // remove_file; the older folder was never a candidate.
+        assert_eq!(deleted_names(&stats), ["old.mp4"]);
+        assert_eq!(stats.bytes_freed, 100);
+        assert_eq!(tree.names(&cam), ["a", "b", "new.mp4"]);
+    }
+
+    #[test]
+    fn emptied_folders_stay_in_place_and_are_not_picked_again() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-empty-instead");
+        let base = dir.path().join("base");
+        let cam = base.join("cam1");
+        let tree = MemoryFileSystem::new();
+        for (recording, days_old) in [("a", 30), ("b", 20), ("c", 10)] {
+            tree.file(&cam.join(recording).join("video"), 100, now, days_old);
+            tree.set_age(&cam.join(recording), now, days_old);
+        }
+        // A filesystem without access times must not stop the emptying.
+        tree.clear_accessed(&cam.join("a"));
+        let disks = disk(&tree);
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 31\ndepth = 1\nempty_instead_of_delete = true",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        // The second pass passes over the already emptied a.
+        assert_eq!(deleted_names(&stats), ["a", "b"]);
+        assert_eq!(tree.names(&cam), ["a", "b", "c"]);
+        assert!(tree.names(&cam.join("a")).is_empty());
+        assert!(tree.names(&cam.join("b")).is_empty());
+        assert_eq!(tree.names(&cam.join("c")), ["video"]);
+        let modified = tree.metadata(&cam.join("a")).unwrap().modified().unwrap();
+        assert_eq!(modified, now - Duration::from_secs(30 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn max_runtime_stops_cleanup_once_deletions_use_up_the_budget() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = TestClock::install(now);
+        let dir = TempDir::new("clean-max-runtime");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        tree.slow_removals(&clock, Duration::from_secs(15));
+        let disks = disk(&tree);
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 25\ndepth = 1\nmax_runtime_seconds = 10",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        // The first deletion takes 15s; the budget is checked before cam2.
+        assert_eq!(deleted_names(&stats), ["a"]);
+        assert_eq!(tree.names(&base.join("cam2")), ["d", "e", "f"]);
+        let log = std::fs::read_to_string(dir.path().join("cleanup.log")).unwrap();
+        assert!(
+            log.contains("Runtime budget exceeded (MAX_RUNTIME_SECONDS=10)"),
+            "{}",
+            log
+        );
+    }
+
+    #[test]
+    fn single_mode_deletes_one_folder_per_selection_directory() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-single");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        let disks = disk(&tree);
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 28\ndepth = 1\nclean_mode = \"single\"",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        // Still short of 28% free at 20%; the next run carries on.
+        assert_eq!(deleted_names(&stats), ["a", "d"]);
+        assert_eq!(tree.names(&base.join("cam1")), ["b", "c"]);
+        assert_eq!(tree.names(&base.join("cam2")), ["e", "f"]);
+    }
+
+    #[test]
+    fn until_threshold_mode_keeps_passing_until_the_target_is_met() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-until-threshold");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        let disks = disk(&tree);
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 28\ndepth = 1\nclean_mode = \"until-threshold\"",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        assert_eq!(deleted_names(&stats), ["a", "d", "b", "e"]);
+        assert_eq!(tree.names(&base.join("cam1")), ["c"]);
+        assert_eq!(tree.names(&base.join("cam2")), ["f"]);
+    }
+
+    #[test]
+    fn clean_disk_keeps_the_most_recent_folders() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-keep-recent");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        for (recording, days_old) in [("a", 5), ("b", 40), ("c", 20), ("d", 30), ("e", 10)] {
+            let folder = base.join("cam1").join(recording);
+            tree.file(&folder.join("video"), 100, now, days_old);
+            tree.set_age(&folder, now, days_old);
+        }
+        let disks = disk(&tree);
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 90\ndepth = 1\nkeep_recent = 3",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        // Far from the threshold, but only the two oldest may go.
+        assert_eq!(deleted_names(&stats), ["b", "d"]);
+        assert_eq!(tree.names(&base.join("cam1")), ["a", "c", "e"]);
+    }
+
+    #[test]
+    fn find_selection_dirs_descends_depth_levels() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let dir = TempDir::new("selection-dirs");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        for recording in ["site1/cam1/day1", "site1/cam2/day1", "site2/cam1/day1"] {
+            tree.file(&base.join(recording).join("video"), 1, now, 0);
+        }
+        tree.file(&base.join("site1/notes.txt"), 1, now, 0);
+        let config = Config::for_test(dir.path(), "");
+        let selection_dirs = |depth| {
+            let mut dirs = Vec::new();
+            find_selection_dirs(&config, &tree, &base, depth, &mut dirs).unwrap();
+            dirs.sort();
+            dirs
+        };
+
+        assert_eq!(selection_dirs(0), vec![base.clone()]);
+        assert_eq!(selection_dirs(1), [base.join("site1"), base.join("site2")]);
+        assert_eq!(
+            selection_dirs(2),
+            [
+                base.join("site1/cam1"),
+                base.join("site1/cam2"),
+                base.join("site2/cam1")
+            ]
+        );
+        assert_eq!(
+            selection_dirs(3),
+            [
+                base.join("site1/cam1/day1"),
+                base.join("site1/cam2/day1"),
+                base.join("site2/cam1/day1")
+            ]
+        );
+        // Below the recordings there are only files to descend into.
+        assert!(selection_dirs(4).is_empty());
+    }
+
+    #[test]
+    fn check_base_dir_refuses_roots_and_shallow_paths() {
+        let dir = TempDir::new("base-depth");
+        let mut config = Config::for_test(dir.path(), "");
+        let tree = MemoryFileSystem::new();
+        tree.create_dir_all(Path::new("/srv/recordings")).unwrap();
+        tree.symlink(Path::new("/"), Path::new("/srv/recordings/root"))
+            .unwrap();
+
+        for base_dir in ["/", "/srv", "/srv/recordings/root", "/srv/recordings/.."] {
+            let err = check_base_dir(&config, &tree, base_dir).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{}", base_dir);
+        }
+        check_base_dir(&config, &tree, "/srv/recordings").unwrap();
+        check_base_dir(&config, &tree, "/srv/recordings/cam1/day1").unwrap();
+
+        config.allow_shallow_base_dir = true;
+        check_base_dir(&config, &tree, "/").unwrap();
+    }
+
+    #[test]
+    fn confirm_deletion_shows_the_target_and_accepts_only_y() {
+        let path = Path::new("/srv/recordings/cam1/day1");
+        let confirm = |answer: &str| {
+            let mut output = Vec::new();
+            let confirmed =
+                confirm_deletion(&mut answer.as_bytes(), &mut output, path, 1536).unwrap();
+            (confirmed, String::from_utf8(output).unwrap())
+        };
+
+        let (confirmed, prompt) = confirm("y\n");
+        assert!(confirmed);
+        assert_eq!(
+            prompt,
+            "Delete /srv/recordings/cam1/day1 (1.50 KiB)? [y/N] "
+        );
+        assert!(confirm(" Y \n").0);
+        for answer in ["n\n", "yes\n", "\n", ""] {
+            assert!(!confirm(answer).0, "{:?}", answer);
+        }
+    }
+
+    /// Raises a real SIGTERM, so it holds the test clock to keep the other
+    /// `clean_disk` tests from seeing the stop request.
+    #[cfg(unix)]
+    #[test]
+    fn clean_disk_stops_cleanly_on_sigterm() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-sigterm");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        let disks = disk(&tree);
+        let config = Config::for_test(dir.path(), "stop_threshold = 25\ndepth = 1");
+
+        signal::install_handlers();
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+        assert!(signal::stop_requested());
+        assert!(!signal::sleep(Duration::from_secs(60)));
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None);
+        signal::clear_stop_request();
+
+        assert!(stats.unwrap().deleted.is_empty());
+        assert_eq!(tree.names(&base.join("cam1")), ["a", "b", "c"]);
+        let log = std::fs::read_to_string(dir.path().join("cleanup.log")).unwrap();
+        assert!(log.contains("Shutdown requested; stopping after current item"));
+    }
+
+    #[test]
+    fn dated_names_need_a_run_of_digits_that_looks_like_a_year() {
+        for name in ["2024-05-01", "20240501", "cam1_2024_05", "backup-1999"] {
+            assert!(is_dated_name(name), "{}", name);
+        }
+        for name in ["cam1", "run-123", "2100-01-01", "v1.20", "day_0501"] {
+            assert!(!is_dated_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn auto_depth_treats_a_base_of_dated_or_leaf_folders_as_flat() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let dir = TempDir::new("auto-depth");
+        let base = dir.path().join("base");
+        let base_dir = base.to_string_lossy().to_string();
+        let config = Config::for_test(dir.path(), "depth = \"auto\"");
+        let tree = MemoryFileSystem::new();
+        tree.create_dir_all(&base).unwrap();
+        assert_eq!(resolve_depth(&config, &tree, &base_dir).unwrap(), 2);
+
+        // Dated folders may have folders inside; undated ones may not.
+        tree.file(&base.join("2024-05-01/hour01/video"), 1, now, 0);
+        tree.file(&base.join("scratch/video"), 1, now, 0);
+        tree.file(&base.join("notes.txt"), 1, now, 0);
+        assert_eq!(resolve_depth(&config, &tree, &base_dir).unwrap(), 0);
+
+        tree.file(&base.join("cam1/day1/video"), 1, now, 0);
+        assert_eq!(resolve_depth(&config, &tree, &base_dir).unwrap(), 2);
+    }
+
+    #[test]
+    fn clean_disk_deletes_the_oldest_dated_folder_of_a_flat_base() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-flat");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        for (day, days_old) in [("2023-11-01", 13), ("2023-10-20", 25), ("2023-11-10", 4)] {
+            tree.file(&base.join(day).join("video"), 150, now, days_old);
+            tree.set_age(&base.join(day), now, days_old);
+        }
+        let disks = disk(&tree);
+        let config = Config::for_test(dir.path(), "stop_threshold = 24\ndepth = \"auto\"");
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        // 17.5% free to start with; the oldest day alone brings it to 25%.
+        assert_eq!(deleted_names(&stats), ["2023-10-20"]);
+        assert_eq!(tree.names(&base), ["2023-11-01", "2023-11-10"]);
+    }
+
+    #[test]
+    fn clean_disk_takes_priority_files_before_any_folder() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-priority");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        tree.file(&base.join("cam1/c/preview.tmp"), 150, now, 2);
+        tree.file(&base.join("cam2/f/upload.PARTIAL"), 200, now, 1);
+        let disks = MemoryDisk {
+            reserved: 850,
+            ..disk(&tree)
+        };
+        let config = Config::for_test(
+            dir.path(),
+            "stop_threshold = 25\ndepth = 1\nextensions_priority = \"tmp,partial\"",
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        assert_eq!(deleted_names(&stats), ["preview.tmp", "upload.PARTIAL"]);
+        assert_eq!(stats.bytes_freed, 350);
+        assert_eq!(tree.names(&base.join("cam1")), ["a", "b", "c"]);
+        assert_eq!(tree.names(&base.join("cam1/c")), ["video"]);
+    }
+
+    #[test]
+    fn dry_run_reports_one_pass_and_deletes_nothing() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-dry-run");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        let disks = disk(&tree);
+
+        // One pass would free 200 of the 301 bytes 25% needs.
+        let config = Config::for_test(dir.path(), "stop_threshold = 25\ndepth = 1\ndry_run = true");
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+        assert_eq!(deleted_names(&stats), ["a", "d"]);
+        assert_eq!(stats.bytes_freed, 200);
+        assert!(stats.bytes_freed < bytes_to_stop(&config, 2000, 200));
+        assert_eq!(tree.used_bytes(), 600);
+
+        // 14% needs 81 bytes, which the first folder alone would give.
+        let config = Config::for_test(
+            dir.path(),
+            "start_threshold = 12\nstop_threshold = 14\ndepth = 1\ndry_run = true",
+        );
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+        assert_eq!(deleted_names(&stats), ["a"]);
+        assert!(stats.bytes_freed >= bytes_to_stop(&config, 2000, 200));
+        assert_eq!(tree.used_bytes(), 600);
+    }
+
+    #[test]
+    fn trash_on_the_same_disk_moves_entries_without_freeing_space() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("clean-trash");
+        let base = dir.path().join("base");
+        let trash = dir.path().join("trash");
+        let tree = MemoryFileSystem::new();
+        recordings(&tree, &base, now);
+        let disks = disk(&tree);
+        let config = Config::for_test(
+            dir.path(),
+            &format!(
+                "stop_threshold = 25\ndepth = 1\nclean_mode = \"single\"\ntrash_dir = {:?}",
+                trash.to_string_lossy()
+            ),
+        );
+
+        let stats = clean_disk(&config, &disks, &tree, &base.to_string_lossy(), None).unwrap();
+
+        let secs = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert_eq!(deleted_names(&stats), ["a", "d"]);
+        assert_eq!(
+            tree.names(&trash),
+            [
+                format!(".trashed-{}-a", secs),
+                format!(".trashed-{}-a.meta.json", secs),
+                format!(".trashed-{}-d", secs),
+                format!(".trashed-{}-d.meta.json", secs),
+            ]
+        );
+        assert_eq!(
+            tree.names(&trash.join(format!(".trashed-{}-a", secs))),
+            ["video"]
+        );
+        // The sidecars even take a little more.
+        assert!(disks.available_bytes(&config, "").unwrap() <= 200);
+    }
+}
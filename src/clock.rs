@@ -0,0 +1,141 @@
+//! The wall clock behind every age and retention decision: `MIN_AGE_DAYS`,
+//! `ACTIVE_GRACE_SECONDS`, `TRASH_RETENTION_DAYS`, `MAX_RUNTIME_SECONDS`,
+//! the score strategy, the `LOG_EXPIRY_DAYS` wipe of `cleanup.log` and the
+//! timestamps written to logs and reports. It can be swapped for a `FixedClock` so that behaviour is reproducible.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+lazy_static! {
+    static ref CLOCK: RwLock<Box<dyn Clock>> = RwLock::new(Box::new(SystemClock));
+}
+
+/// Source of the current time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The operating system clock; used unless `set_clock` installs another.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that stands still at a chosen instant until moved. Clones share
+/// the instant, so a handle kept after `set_clock` still moves the
+/// installed clock:
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use util::{set_clock, Clock, FixedClock};
+///
+/// let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+/// let clock = FixedClock::new(start);
+/// set_clock(Box::new(clock.clone()));
+///
+/// // A folder modified at `start` is now eight days old for MIN_AGE_DAYS,
+/// // TRASH_RETENTION_DAYS and the other age checks.
+/// clock.advance(Duration::from_secs(8 * 24 * 60 * 60));
+/// assert_eq!(clock.now().duration_since(start).unwrap().as_secs(), 691_200);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FixedClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl FixedClock {
+    pub fn new(now: SystemTime) -> FixedClock {
+        FixedClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Moves the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *now += by;
+    }
+
+    /// Moves the clock to `now`, backwards included.
+    pub fn set(&self, now: SystemTime) {
+        *self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = now;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        *self
+            .now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Replaces the clock for the rest of the process.
+pub fn set_clock(clock: Box<dyn Clock>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+pub(crate) fn now() -> SystemTime {
+    CLOCK.read().unwrap().now()
+}
+
+/// How long ago `time` was by the installed clock; zero for times in the
+/// future.
+pub(crate) fn age(time: SystemTime) -> Duration {
+    now().duration_since(time).unwrap_or(Duration::ZERO)
+}
+
+#[cfg(test)]
+lazy_static! {
+    static ref TEST_CLOCK: Mutex<()> = Mutex::new(());
+}
+
+/// A `FixedClock` installed for one test. Tests that install one run one at
+/// a time, and dropping it puts the system clock back.
+#[cfg(test)]
+pub(crate) struct TestClock {
+    clock: FixedClock,
+    _serial: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl TestClock {
+    pub(crate) fn install(now: SystemTime) -> TestClock {
+        let serial = TEST_CLOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let clock = FixedClock::new(now);
+        set_clock(Box::new(clock.clone()));
+        TestClock {
+            clock,
+            _serial: serial,
+        }
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestClock {
+    type Target = FixedClock;
+
+    fn deref(&self) -> &FixedClock {
+        &self.clock
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestClock {
+    fn drop(&mut self) {
+        set_clock(Box::new(SystemClock));
+    }
+}
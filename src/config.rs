@@ -0,0 +1,1860 @@
+//! Command-line, environment and config-file settings.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use sysinfo::SystemExt;
+
+use crate::cleanup::{CleanMode, Depth, OnFailure};
+use crate::config_file::{self, Table, Value};
+use crate::logging::{LogFormat, LogLevel};
+use crate::regex::Regex;
+use crate::scan::{expand_dir_pattern, SortBy, Strategy, Target};
+use crate::size::{format_bytes, parse_size, SizeUnits};
+use crate::storage::{TargetDisk, ThresholdMode};
+use crate::webhook::HttpUrl;
+
+pub(crate) fn invalid_input(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+lazy_static! {
+    static ref HOSTNAME: Option<String> = sysinfo::System::new().host_name();
+}
+
+/// This machine's hostname, looked up on first use only: every config and
+/// override block defaults `INSTANCE_ID` to it, and a `Cleaner` reports it.
+pub(crate) fn hostname() -> Option<&'static str> {
+    HOSTNAME.as_deref()
+}
+
+pub(crate) fn parse_flag(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes")
+}
+
+pub(crate) fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Splits a `DIRPATH` value on commas and on the platform's `PATH` separator.
+pub(crate) fn parse_dir_list(value: &str) -> Vec<String> {
+    parse_list(value)
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|path| path.to_string_lossy().to_string())
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+/// `$<var>/disk-cleanup`, or `$HOME/<fallback>/disk-cleanup` when `var` is
+/// unset or not absolute, as the XDG base directory spec asks. Variables are
+/// read through `var_os`. Only Linux has these defaults; elsewhere every
+/// path must be configured.
+#[cfg(target_os = "linux")]
+fn xdg_dir(
+    var: &str,
+    fallback: &str,
+    var_os: impl Fn(&str) -> Option<OsString>,
+) -> Option<PathBuf> {
+    let base = var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| {
+            var_os("HOME")
+                .filter(|home| !home.is_empty())
+                .map(|home| Path::new(&home).join(fallback))
+        })?;
+    Some(base.join("disk-cleanup"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn xdg_dir(
+    _var: &str,
+    _fallback: &str,
+    _var_os: impl Fn(&str) -> Option<OsString>,
+) -> Option<PathBuf> {
+    None
+}
+
+/// `$XDG_CONFIG_HOME/disk-cleanup/config.toml`, read when `--config` is not
+/// given and the file exists.
+fn default_config_file() -> Option<String> {
+    xdg_dir("XDG_CONFIG_HOME", ".config", |name| env::var_os(name))
+        .map(|dir| dir.join("config.toml"))
+        .filter(|file| file.is_file())
+        .map(|file| file.to_string_lossy().to_string())
+}
+
+/// `$XDG_STATE_HOME/disk-cleanup`, the log directory when neither `LOGPATH`
+/// nor `LOG_FILE` is set. It is created on first use.
+fn default_log_dir(sources: &Sources) -> Option<String> {
+    let dir = xdg_dir("XDG_STATE_HOME", ".local/state", |name| {
+        sources.var_os(name)
+    })?;
+    std::fs::create_dir_all(&dir).ok()?;
+    Some(dir.to_string_lossy().to_string())
+}
+
+/// Reads a `MANIFEST` file: one folder name or glob pattern per line, with
+/// blank lines and `#` comments ignored.
+fn read_manifest(path: &str) -> std::io::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| invalid_input(format!("MANIFEST {} cannot be read: {}", path, err)))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+pub(crate) fn parse_threshold(name: &str, value: &str) -> std::io::Result<f64> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid_input(format!("{} must be a number, got {:?}", name, value)))
+}
+
+fn parse_weight(name: &str, value: &str) -> std::io::Result<f64> {
+    match value.trim().parse::<f64>() {
+        Ok(weight) if weight.is_finite() && weight >= 0.0 => Ok(weight),
+        _ => Err(invalid_input(format!(
+            "{} must be a non-negative number, got {:?}",
+            name, value
+        ))),
+    }
+}
+
+/// Parses a non-negative number of days, such as `MIN_AGE_DAYS`.
+fn parse_days(name: &str, value: &str) -> std::io::Result<Duration> {
+    let days = match value.trim().parse::<f64>() {
+        Ok(days) if days.is_finite() && days >= 0.0 => days,
+        _ => {
+            return Err(invalid_input(format!(
+                "{} must be a non-negative number, got {:?}",
+                name, value
+            )))
+        }
+    };
+    Duration::try_from_secs_f64(days * 24.0 * 60.0 * 60.0)
+        .map_err(|_| invalid_input(format!("{} is too large, got {:?}", name, value)))
+}
+
+/// `--version` output: crate version, git commit and target triple of the
+/// build.
+pub const VERSION: &str = concat!(
+    "util ",
+    env!("CARGO_PKG_VERSION"),
+    " (commit ",
+    env!("GIT_COMMIT"),
+    ", ",
+    env!("BUILD_TARGET"),
+    ")"
+);
+
+pub const USAGE: &str = "Usage: util [OPTIONS] [check]
+
+Commands:
+  check                       Report disk pressure without deleting anything, then exit
+                              with 0 (above stop), 1 (below stop), 2 (below start) or
+                              3 (disk not found), as a monitoring plugin
+
+Options:
+  --config <FILE>             Read settings from a TOML file (see cleanup.example.toml;
+                              default $XDG_CONFIG_HOME/disk-cleanup/config.toml if present)
+  --dir <PATH>[,<PATH>...]    Base directories to clean; `*` and `?` match (env: DIRPATH)
+  --log-path <PATH>           Directory holding cleanup.log (env: LOGPATH,
+                              default $XDG_STATE_HOME/disk-cleanup)
+  --log-file <FILE>           Log to this file instead, or `-` for stdout (env: LOG_FILE)
+  --start-threshold <PERCENT> Start cleanup below this free space (env: START_THRESHOLD, default 20;
+                              with THRESHOLD_MODE=used, above this used space, default 80)
+  --stop-threshold <PERCENT>  Stop cleanup above this free space (env: STOP_THRESHOLD, default 25;
+                              with THRESHOLD_MODE=used, below this used space, default 75)
+  --interval <SECONDS>        Keep running, checking every SECONDS (env: INTERVAL)
+  --force                     Delete one folder per subfolder even if free space is fine (env: FORCE)
+  --dry-run                   Log what would be deleted without deleting (env: DRYRUN)
+  --once                      Delete at most one folder per subfolder, then stop
+                              (env: CLEAN_MODE=single)
+  --plan                      Print the deletion order and projected free space, then exit
+  --stats                     Print a histogram of candidate ages and sizes, then exit
+  --interactive               Ask before each deletion (ignored when stdin is not a terminal)
+  --tui                       Show a live dashboard; c cleans, d dry-runs, q quits
+                              (needs the tui feature)
+  -V, --version               Print the version, git commit and target, then exit
+  -h, --help                  Print this help
+
+Exit codes (without `check`):
+  0  No cleanup was needed
+  1  Error
+  2  Cleanup ran and every directory is back within its targets
+  3  Cleanup ran but free space or a quota is still short of its target
+  4  Another instance holds the lock file";
+
+/// Options given on the command line. Anything left as `None` falls back to
+/// the environment, then to the config file and finally to the built-in
+/// default.
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    pub config: Option<String>,
+    pub dir: Option<String>,
+    pub log_path: Option<String>,
+    pub log_file: Option<String>,
+    pub start_threshold: Option<String>,
+    pub stop_threshold: Option<String>,
+    pub interval: Option<String>,
+    pub force: bool,
+    pub dry_run: bool,
+    /// `--once`: `CLEAN_MODE=single` for this run.
+    pub once: bool,
+    pub plan: bool,
+    pub stats: bool,
+    /// The `check` subcommand: report disk pressure and exit.
+    pub check: bool,
+    pub interactive: bool,
+    /// `--tui`: show the live dashboard instead of running once.
+    pub tui: bool,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl CliArgs {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> std::io::Result<CliArgs> {
+        let mut cli = CliArgs::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_string(), Some(value.to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+
+            let slot = match flag.as_str() {
+                "--config" => &mut cli.config,
+                "--dir" => &mut cli.dir,
+                "--log-path" => &mut cli.log_path,
+                "--log-file" => &mut cli.log_file,
+                "--start-threshold" => &mut cli.start_threshold,
+                "--stop-threshold" => &mut cli.stop_threshold,
+                "--interval" => &mut cli.interval,
+                "--force" => {
+                    cli.force = true;
+                    continue;
+                }
+                "--dry-run" => {
+                    cli.dry_run = true;
+                    continue;
+                }
+                "--once" => {
+                    cli.once = true;
+                    continue;
+                }
+                "--plan" => {
+                    cli.plan = true;
+                    continue;
+                }
+                "--stats" => {
+                    cli.stats = true;
+                    continue;
+                }
+                "--interactive" => {
+                    cli.interactive = true;
+                    continue;
+                }
+                "--tui" => {
+                    cli.tui = true;
+                    continue;
+                }
+                "check" => {
+                    cli.check = true;
+                    continue;
+                }
+                "-h" | "--help" => {
+                    cli.help = true;
+                    continue;
+                }
+                "-V" | "--version" => {
+                    cli.version = true;
+                    continue;
+                }
+                _ => return Err(invalid_input(format!("Unknown argument: {}", arg))),
+            };
+
+            let value = match inline_value {
+                Some(value) => value,
+                None => args
+                    .next()
+                    .ok_or_else(|| invalid_input(format!("{} requires a value", flag)))?,
+            };
+            *slot = Some(value);
+        }
+
+        Ok(cli)
+    }
+}
+
+/// Effective settings for a run, resolved as CLI > environment > config file >
+/// default.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Directories to clean, with `DIRPATH` glob patterns already expanded
+    /// and symlinks resolved.
+    pub base_dirs: Vec<String>,
+    /// `DIRPATH` glob patterns that matched no directory at startup.
+    pub unmatched_dir_patterns: Vec<String>,
+    /// `DIRPATH` entries that were rewritten when resolved, such as a
+    /// symlink, with the directory each one resolved to.
+    pub resolved_dirs: Vec<(String, String)>,
+    pub log_path: String,
+    /// Full log file path, or `-` for stdout; overrides `log_path`.
+    pub log_file: Option<String>,
+    /// File receiving one JSON record per completed deletion.
+    pub audit_log: Option<String>,
+    /// How the percentage thresholds were given. `start_threshold` and
+    /// `stop_threshold` hold free-space percentages either way.
+    pub threshold_mode: ThresholdMode,
+    /// Cleanup starts when the free-space percentage drops below this.
+    pub start_threshold: f64,
+    /// Cleanup stops once the free-space percentage rises above this.
+    pub stop_threshold: f64,
+    /// Cleanup starts when fewer bytes than this are free; replaces
+    /// `start_threshold` when set.
+    pub start_free_bytes: Option<u64>,
+    /// Cleanup stops once more bytes than this are free; replaces
+    /// `stop_threshold` when set.
+    pub stop_free_bytes: Option<u64>,
+    pub sort_by: SortBy,
+    pub strategy: Strategy,
+    /// Whether `clean_disk` stops after one pass or at the targets.
+    pub clean_mode: CleanMode,
+    /// Exponent applied to a candidate's age in days by `STRATEGY=score`.
+    pub score_age_weight: f64,
+    /// Exponent applied to a candidate's size in bytes by `STRATEGY=score`.
+    pub score_size_weight: f64,
+    /// Whether folders, regular files or both are deletion candidates.
+    pub target: Target,
+    /// Directory levels below each base directory where candidates are
+    /// picked; `2` selects among the children of `<base>/<folder>/<subfolder>`.
+    pub depth: Depth,
+    pub exclude: Vec<String>,
+    /// File extensions, lowercase and without the dot, whose loose files are
+    /// deleted oldest first before any folder is touched.
+    pub extensions_priority: Vec<String>,
+    /// When set, only folders whose names match are deletion candidates.
+    pub include_pattern: Option<Regex>,
+    /// Folder names or glob patterns read from the `MANIFEST` file; when
+    /// set, only matching entries may be deleted.
+    pub manifest: Option<Vec<String>>,
+    /// Never pick dot-prefixed folders for deletion.
+    pub skip_hidden: bool,
+    /// Only delete inside directories holding a `.cleanup-ok` file.
+    pub require_marker: bool,
+    /// Never descend into or delete entries on another filesystem than the
+    /// base directory, such as a disk mounted below it.
+    pub stay_on_device: bool,
+    /// Delete only the contents of a chosen folder and keep the folder.
+    pub empty_instead_of_delete: bool,
+    /// Remove folders left empty once their contents were deleted.
+    pub prune_empty: bool,
+    pub trash_dir: Option<String>,
+    /// Trash entries older than this are purged at the start of each run;
+    /// `None` keeps them forever.
+    pub trash_retention: Option<Duration>,
+    /// Folders are written to `<archive_dir>/<name>.tar.gz` before removal.
+    pub archive_dir: Option<String>,
+    /// How often a removal failing with a transient error is retried.
+    pub delete_retries: u32,
+    /// Wait before the first retry; doubled after each further attempt.
+    pub delete_backoff: Duration,
+    /// Log progress every this many files while removing a large folder;
+    /// `None` removes every folder in a single call.
+    pub progress_every: Option<u64>,
+    /// Folders smaller than this are removed in a single call even when
+    /// `progress_every` is set.
+    pub progress_min_bytes: u64,
+    /// Upper bound on folders removed by one `clean_disk` call; `None` means
+    /// unlimited.
+    pub max_deletions: Option<usize>,
+    /// Time one `clean_disk` call may spend before it stops between folders,
+    /// even short of its targets; `None` means unlimited.
+    pub max_runtime: Option<Duration>,
+    /// Folders younger than this are never deleted.
+    pub min_age: Option<Duration>,
+    /// Candidates smaller than this are never deleted; they would free too
+    /// little to be worth it.
+    pub min_folder_bytes: Option<u64>,
+    /// Folders holding anything modified this recently are treated as in use
+    /// and skipped.
+    pub active_grace: Option<Duration>,
+    /// Number of newest folders per subfolder that are never deleted.
+    pub keep_recent: Option<usize>,
+    /// Size quota for each base directory; exceeding it starts cleanup even
+    /// when the disk has enough free space.
+    pub max_dir_bytes: Option<u64>,
+    pub webhook_url: Option<String>,
+    /// Shell command run before each directory's cleanup; a non-zero exit
+    /// aborts that cleanup.
+    pub pre_hook: Option<String>,
+    /// Shell command run after each directory's cleanup, even a failed one;
+    /// its failure is only logged.
+    pub post_hook: Option<String>,
+    /// What to do when cleanup leaves a directory short of its targets.
+    pub on_failure: OnFailure,
+    /// Shell command run for `ON_FAILURE=hook`; its failure is only logged.
+    pub on_failure_hook: Option<String>,
+    /// `smtp://host[:port]` relay for low-space alerts. Only honoured when
+    /// built with the `email` feature.
+    pub smtp_url: Option<String>,
+    pub alert_to: Vec<String>,
+    pub alert_from: Option<String>,
+    /// Units for sizes in log messages, reports and alerts.
+    pub size_units: SizeUnits,
+    pub log_format: LogFormat,
+    pub log_level: LogLevel,
+    pub log_max_bytes: Option<u64>,
+    pub log_keep: usize,
+    /// Age past which `cleanup.log` is wiped when `log_max_bytes` is unset.
+    pub log_expiry: Duration,
+    /// Identifies this machine in JSON log lines, reports and notifications:
+    /// `INSTANCE_ID` when set, the hostname otherwise.
+    pub instance_id: String,
+    /// Also prefix plain-text log lines with `instance_id`.
+    pub log_instance: bool,
+    /// Where a JSON summary of each run is written; `None` writes none.
+    pub report_path: Option<String>,
+    /// Collect every run in a JSON array at `report_path` instead of
+    /// replacing the previous report.
+    pub report_append: bool,
+    /// Use the disk with this device name instead of matching `base_dir`
+    /// against mount points.
+    pub disk_name: Option<String>,
+    /// Use the disk mounted here instead of matching `base_dir` against
+    /// mount points.
+    pub mount_point: Option<String>,
+    /// Which disk behind a union filesystem gates cleanup.
+    pub target_disk: TargetDisk,
+    /// Exclusive lock held for the whole run so overlapping runs, e.g. from
+    /// cron, do not clean the same folders at once.
+    pub lock_file: PathBuf,
+    /// Time between checks in watch mode; `None` runs a single check.
+    pub interval: Option<Duration>,
+    /// Up to this much is added at random to every watch-mode sleep, so a
+    /// fleet sharing one interval does not check in lockstep.
+    pub jitter: Option<Duration>,
+    /// After a cleanup reaches its targets, further cleanups of the same
+    /// directory by the same `Cleaner` are suppressed for this long.
+    pub cooldown: Option<Duration>,
+    /// Address for the Prometheus endpoint in watch mode, e.g. `0.0.0.0:9184`.
+    /// Only honoured when built with the `metrics` feature.
+    pub metrics_addr: Option<String>,
+    /// Allow cleaning a filesystem root or a directory fewer than
+    /// `MIN_BASE_DEPTH` levels below it. Only read from the environment.
+    pub allow_shallow_base_dir: bool,
+    /// Ask on the terminal before each deletion (`--interactive`).
+    pub interactive: bool,
+    /// Run a single rotation pass without checking free space first.
+    pub force: bool,
+    pub dry_run: bool,
+    /// Effective settings of the base directories that have an
+    /// `[overrides."<dir>"]` block in the config file, keyed by the
+    /// `base_dirs` entry they apply to. See `for_dir`.
+    pub dir_overrides: BTreeMap<String, Config>,
+}
+
+/// Keys accepted in the config file, each paired with the environment
+/// variable that overrides it.
+const SETTINGS: &[(&str, &str)] = &[
+    ("DIRPATH", "dirs"),
+    ("LOGPATH", "log.path"),
+    ("AUDIT_LOG", "log.audit"),
+    ("LOG_FILE", "log.file"),
+    ("THRESHOLD_MODE", "threshold_mode"),
+    ("START_THRESHOLD", "start_threshold"),
+    ("STOP_THRESHOLD", "stop_threshold"),
+    ("START_FREE_BYTES", "start_free_bytes"),
+    ("STOP_FREE_BYTES", "stop_free_bytes"),
+    ("SORT_BY", "sort_by"),
+    ("STRATEGY", "strategy"),
+    ("CLEAN_MODE", "clean_mode"),
+    ("SCORE_AGE_WEIGHT", "score_age_weight"),
+    ("SCORE_SIZE_WEIGHT", "score_size_weight"),
+    ("TARGET", "target"),
+    ("DEPTH", "depth"),
+    ("EXCLUDE", "exclude"),
+    ("EXTENSIONS_PRIORITY", "extensions_priority"),
+    ("INCLUDE_PATTERN", "include_pattern"),
+    ("MANIFEST", "manifest"),
+    ("SKIP_HIDDEN", "skip_hidden"),
+    ("REQUIRE_MARKER", "require_marker"),
+    ("PRUNE_EMPTY", "prune_empty"),
+    ("EMPTY_INSTEAD_OF_DELETE", "empty_instead_of_delete"),
+    ("STAY_ON_DEVICE", "stay_on_device"),
+    ("TRASH_DIR", "trash_dir"),
+    ("TRASH_RETENTION_DAYS", "trash_retention_days"),
+    ("ARCHIVE_DIR", "archive_dir"),
+    ("DELETE_RETRIES", "delete_retries"),
+    ("DELETE_BACKOFF_MS", "delete_backoff_ms"),
+    ("PROGRESS_EVERY", "progress_every"),
+    ("PROGRESS_MIN_BYTES", "progress_min_bytes"),
+    ("MAX_DELETIONS", "max_deletions"),
+    ("MAX_RUNTIME_SECONDS", "max_runtime_seconds"),
+    ("MIN_AGE_DAYS", "min_age_days"),
+    ("MIN_FOLDER_BYTES", "min_folder_bytes"),
+    ("ACTIVE_GRACE_SECONDS", "active_grace_seconds"),
+    ("KEEP_RECENT", "keep_recent"),
+    ("MAX_DIR_BYTES", "max_dir_bytes"),
+    ("WEBHOOK_URL", "webhook_url"),
+    ("PRE_HOOK", "pre_hook"),
+    ("ON_FAILURE", "on_failure"),
+    ("ON_FAILURE_HOOK", "on_failure_hook"),
+    ("POST_HOOK", "post_hook"),
+    ("SMTP_URL", "alert.smtp_url"),
+    ("ALERT_TO", "alert.to"),
+    ("ALERT_FROM", "alert.from"),
+    ("SIZE_UNITS", "size_units"),
+    ("LOG_FORMAT", "log.format"),
+    ("LOG_LEVEL", "log.level"),
+    ("LOG_MAX_BYTES", "log.max_bytes"),
+    ("LOG_KEEP", "log.keep"),
+    ("LOG_EXPIRY_DAYS", "log.expiry_days"),
+    ("INSTANCE_ID", "instance_id"),
+    ("LOG_INSTANCE", "log.instance"),
+    ("REPORT_PATH", "report.path"),
+    ("REPORT_APPEND", "report.append"),
+    ("DISK_NAME", "disk_name"),
+    ("MOUNT_POINT", "mount_point"),
+    ("TARGET_DISK", "target_disk"),
+    ("LOCK_FILE", "lock_file"),
+    ("INTERVAL", "interval"),
+    ("JITTER_SECONDS", "jitter_seconds"),
+    ("COOLDOWN_SECONDS", "cooldown_seconds"),
+    ("METRICS_ADDR", "metrics_addr"),
+    ("FORCE", "force"),
+    ("DRYRUN", "dry_run"),
+];
+
+/// Config-file keys a `[overrides."<dir>"]` block may set; everything else
+/// applies to the whole run.
+const OVERRIDABLE: &[&str] = &[
+    "threshold_mode",
+    "start_threshold",
+    "stop_threshold",
+    "start_free_bytes",
+    "stop_free_bytes",
+    "sort_by",
+    "strategy",
+    "clean_mode",
+    "score_age_weight",
+    "score_size_weight",
+    "target",
+    "depth",
+    "exclude",
+    "extensions_priority",
+    "include_pattern",
+    "manifest",
+    "skip_hidden",
+    "require_marker",
+    "prune_empty",
+    "empty_instead_of_delete",
+    "max_deletions",
+    "max_runtime_seconds",
+    "min_age_days",
+    "min_folder_bytes",
+    "active_grace_seconds",
+    "keep_recent",
+    "cooldown_seconds",
+    "max_dir_bytes",
+    "target_disk",
+    "on_failure",
+];
+
+/// Raw setting values: the environment first, then the config file. When
+/// loading the settings of one overridden directory, its override block
+/// comes before both.
+struct Sources {
+    file: Table,
+    overrides: Table,
+    dir: Option<Table>,
+    /// The environment variables to read, or `None` for the process
+    /// environment. Tests pass their own so they neither depend on nor
+    /// change the real one.
+    env: Option<BTreeMap<String, String>>,
+}
+
+impl Sources {
+    fn load(path: Option<&str>) -> std::io::Result<Sources> {
+        let mut file = match path {
+            Some(path) => config_file::load(Path::new(path))?,
+            None => Table::new(),
+        };
+        let overrides = match file.remove("overrides") {
+            Some(Value::Table(overrides)) => overrides,
+            Some(_) => {
+                return Err(invalid_input(
+                    "`overrides` in config file must hold `[overrides.\"<dir>\"]` tables"
+                        .to_string(),
+                ))
+            }
+            None => Table::new(),
+        };
+        check_keys(&file, "")?;
+        for (dir, block) in &overrides {
+            let Value::Table(block) = block else {
+                return Err(invalid_input(format!(
+                    "`overrides.\"{}\"` in config file must be a table",
+                    dir
+                )));
+            };
+            if let Some(key) = block
+                .keys()
+                .find(|key| !OVERRIDABLE.contains(&key.as_str()))
+            {
+                return Err(invalid_input(format!(
+                    "Key `{}` cannot be overridden per directory (in `overrides.\"{}\"`)",
+                    key, dir
+                )));
+            }
+        }
+        Ok(Sources {
+            file,
+            overrides,
+            dir: None,
+            env: None,
+        })
+    }
+
+    fn var_os(&self, name: &str) -> Option<OsString> {
+        match &self.env {
+            Some(vars) => vars.get(name).map(OsString::from),
+            None => env::var_os(name),
+        }
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        match &self.env {
+            Some(vars) => vars.get(name).cloned(),
+            None => env::var(name).ok(),
+        }
+    }
+
+    /// Reads `name` from the environment, trying the prefixed form first so
+    /// `CLEANUP_DIRPATH` beats `DIRPATH`. The prefix is `CLEANUP_` unless
+    /// `ENV_PREFIX` names another; an empty `ENV_PREFIX` leaves only the bare
+    /// names.
+    fn env_var(&self, name: &str) -> Option<String> {
+        let prefix = self
+            .var("ENV_PREFIX")
+            .unwrap_or_else(|| "CLEANUP_".to_string());
+        if !prefix.is_empty() {
+            if let Some(value) = self.var(&format!("{}{}", prefix, name)) {
+                return Some(value);
+            }
+        }
+        self.var(name)
+    }
+
+    fn get(&self, env_name: &str) -> Option<String> {
+        let key = SETTINGS
+            .iter()
+            .find(|(name, _)| *name == env_name)
+            .map(|(_, key)| *key);
+        if let Some(value) = key
+            .zip(self.dir.as_ref())
+            .and_then(|(key, dir)| config_file::lookup(dir, key))
+        {
+            return value.to_setting();
+        }
+        self.env_var(env_name)
+            .or_else(|| config_file::lookup(&self.file, key?).and_then(Value::to_setting))
+    }
+
+    /// Whether the override block being loaded sets `env_name`'s key.
+    fn overridden(&self, env_name: &str) -> bool {
+        SETTINGS
+            .iter()
+            .find(|(name, _)| *name == env_name)
+            .zip(self.dir.as_ref())
+            .is_some_and(|((_, key), dir)| dir.contains_key(*key))
+    }
+}
+
+/// Rejects config-file keys that no setting reads, so typos surface at startup
+/// instead of being silently ignored.
+fn check_keys(table: &Table, prefix: &str) -> std::io::Result<()> {
+    for (name, value) in table {
+        let key = format!("{}{}", prefix, name);
+        match value {
+            Value::Table(inner) => check_keys(inner, &format!("{}.", key))?,
+            _ if SETTINGS.iter().any(|(_, known)| *known == key) => {}
+            _ => {
+                return Err(invalid_input(format!(
+                    "Unknown key `{}` in config file",
+                    key
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Loads the settings from the command line, the environment, `.env` and
+    /// the `--config` file (by default `$XDG_CONFIG_HOME/disk-cleanup/config.toml`
+    /// on Linux, when it exists), then resolves the effective settings of every
+    /// base directory with an `[overrides."<dir>"]` block. An override beats
+    /// every global source for its directory, command-line flags included;
+    /// keys it leaves out are inherited.
+    pub fn load(cli: CliArgs) -> std::io::Result<Config> {
+        let config_file = cli.config.clone().or_else(default_config_file);
+        let sources = Sources::load(config_file.as_deref())?;
+        Config::with_overrides(cli, sources)
+    }
+
+    /// The global settings from `sources`, plus those of every base
+    /// directory with an override block.
+    fn with_overrides(cli: CliArgs, mut sources: Sources) -> std::io::Result<Config> {
+        let mut config = Config::from_sources(cli.clone(), &sources)?;
+
+        for (dir, block) in std::mem::take(&mut sources.overrides) {
+            let Some(base_dir) = config
+                .base_dirs
+                .iter()
+                .find(|base_dir| {
+                    Path::new(base_dir.as_str()) == Path::new(&dir)
+                        || std::fs::canonicalize(&dir)
+                            .is_ok_and(|resolved| resolved == Path::new(base_dir.as_str()))
+                })
+                .cloned()
+            else {
+                return Err(invalid_input(format!(
+                    "`overrides.\"{}\"` in config file matches no DIRPATH entry",
+                    dir
+                )));
+            };
+            let Value::Table(block) = block else {
+                unreachable!("checked by Sources::load");
+            };
+            sources.dir = Some(block);
+            let mut dir_cli = cli.clone();
+            dir_cli.dir = Some(base_dir.clone());
+            if sources.overridden("START_THRESHOLD") {
+                dir_cli.start_threshold = None;
+            }
+            if sources.overridden("STOP_THRESHOLD") {
+                dir_cli.stop_threshold = None;
+            }
+            if sources.overridden("CLEAN_MODE") {
+                dir_cli.once = false;
+            }
+            let mut dir_config = Config::from_sources(dir_cli, &sources)
+                .map_err(|err| invalid_input(format!("overrides.\"{}\": {}", dir, err)))?;
+            dir_config.base_dirs = vec![base_dir.clone()];
+            dir_config.unmatched_dir_patterns.clear();
+            dir_config.resolved_dirs.clear();
+            config.dir_overrides.insert(base_dir, dir_config);
+        }
+        Ok(config)
+    }
+
+    /// The settings that apply to `base_dir`: its override when it has one,
+    /// `self` otherwise.
+    pub fn for_dir(&self, base_dir: &str) -> &Config {
+        self.dir_overrides.get(base_dir).unwrap_or(self)
+    }
+
+    fn from_sources(cli: CliArgs, sources: &Sources) -> std::io::Result<Config> {
+        let base_dirs = cli
+            .dir
+            .or_else(|| sources.get("DIRPATH"))
+            .map(|value| parse_dir_list(&value))
+            .filter(|dirs| !dirs.is_empty())
+            .ok_or_else(|| {
+                invalid_input(
+                    "DIRPATH not set (use --dir, the environment, .env or --config)".to_string(),
+                )
+            })?;
+        let mut unmatched_dir_patterns = Vec::new();
+        let base_dirs: Vec<PathBuf> = base_dirs
+            .into_iter()
+            .flat_map(|pattern| {
+                let dirs = expand_dir_pattern(&pattern);
+                if dirs.is_empty() {
+                    unmatched_dir_patterns.push(pattern);
+                }
+                dirs
+            })
+            .collect();
+        // Resolved once, so disk detection and traversal agree on the real
+        // directory behind a symlink. An entry that cannot be resolved is
+        // kept as given and rejected by `validate_config`.
+        let mut resolved_dirs = Vec::new();
+        let base_dirs = base_dirs
+            .into_iter()
+            .map(|dir| {
+                let given = dir.to_string_lossy().to_string();
+                match std::fs::canonicalize(&dir) {
+                    Ok(resolved) if resolved != dir => {
+                        let resolved = resolved.to_string_lossy().to_string();
+                        resolved_dirs.push((given, resolved.clone()));
+                        resolved
+                    }
+                    _ => given,
+                }
+            })
+            .collect();
+        let log_file = cli
+            .log_file
+            .or_else(|| sources.get("LOG_FILE"))
+            .filter(|file| !file.is_empty());
+        let audit_log = sources.get("AUDIT_LOG").filter(|file| !file.is_empty());
+        // LOGPATH is only needed when LOG_FILE does not name the file.
+        let log_path = cli
+            .log_path
+            .or_else(|| sources.get("LOGPATH"))
+            .or_else(|| log_file.as_ref().map(|_| String::from(".")))
+            .or_else(|| default_log_dir(sources))
+            .ok_or_else(|| {
+                invalid_input(
+                    "LOGPATH not set (use --log-path or --log-file, the environment, .env or --config)"
+                        .to_string(),
+                )
+            })?;
+
+        let threshold_mode = match sources.get("THRESHOLD_MODE") {
+            Some(value) => ThresholdMode::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "THRESHOLD_MODE must be free or used, got {:?}",
+                    value
+                ))
+            })?,
+            None => ThresholdMode::Free,
+        };
+        let used = threshold_mode == ThresholdMode::Used;
+        let start_threshold = match cli.start_threshold {
+            Some(value) => parse_threshold("--start-threshold", &value)?,
+            None => match sources.get("START_THRESHOLD") {
+                Some(value) => parse_threshold("START_THRESHOLD", &value)?,
+                None if used => 80.0,
+                None => 20.0,
+            },
+        };
+        let stop_threshold = match cli.stop_threshold {
+            Some(value) => parse_threshold("--stop-threshold", &value)?,
+            None => match sources.get("STOP_THRESHOLD") {
+                Some(value) => parse_threshold("STOP_THRESHOLD", &value)?,
+                None if used => 75.0,
+                None => 25.0,
+            },
+        };
+
+        if used && stop_threshold >= start_threshold {
+            return Err(invalid_input(format!(
+                "With THRESHOLD_MODE=used, the stop threshold ({}% used) must be less than the start threshold ({}% used)",
+                stop_threshold, start_threshold
+            )));
+        }
+        if !used && stop_threshold <= start_threshold {
+            return Err(invalid_input(format!(
+                "Stop threshold ({}) must be greater than start threshold ({})",
+                stop_threshold, start_threshold
+            )));
+        }
+        // From here on every threshold is a free-space percentage.
+        let (start_threshold, stop_threshold) = if used {
+            (100.0 - start_threshold, 100.0 - stop_threshold)
+        } else {
+            (start_threshold, stop_threshold)
+        };
+
+        let start_free_bytes = match sources.get("START_FREE_BYTES") {
+            Some(value) => Some(
+                parse_size(&value)
+                    .map_err(|err| invalid_input(format!("START_FREE_BYTES: {}", err)))?,
+            ),
+            None => None,
+        };
+        let stop_free_bytes = match sources.get("STOP_FREE_BYTES") {
+            Some(value) => Some(
+                parse_size(&value)
+                    .map_err(|err| invalid_input(format!("STOP_FREE_BYTES: {}", err)))?,
+            ),
+            None => None,
+        };
+        if let (Some(start), Some(stop)) = (start_free_bytes, stop_free_bytes) {
+            if stop <= start {
+                return Err(invalid_input(format!(
+                    "STOP_FREE_BYTES ({}) must be greater than START_FREE_BYTES ({})",
+                    stop, start
+                )));
+            }
+        }
+
+        let sort_by = match sources.get("SORT_BY") {
+            Some(value) => SortBy::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "SORT_BY must be created, modified, accessed or auto, got {:?}",
+                    value
+                ))
+            })?,
+            None => SortBy::Auto,
+        };
+
+        let strategy = match sources.get("STRATEGY") {
+            Some(value) => Strategy::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "STRATEGY must be oldest, largest, score or dedup, got {:?}",
+                    value
+                ))
+            })?,
+            None => Strategy::Oldest,
+        };
+        let clean_mode = match sources.get("CLEAN_MODE") {
+            _ if cli.once => CleanMode::Single,
+            Some(value) => CleanMode::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "CLEAN_MODE must be single or until-threshold, got {:?}",
+                    value
+                ))
+            })?,
+            None => CleanMode::UntilThreshold,
+        };
+        let score_age_weight = match sources.get("SCORE_AGE_WEIGHT") {
+            Some(value) => parse_weight("SCORE_AGE_WEIGHT", &value)?,
+            None => 1.0,
+        };
+        let score_size_weight = match sources.get("SCORE_SIZE_WEIGHT") {
+            Some(value) => parse_weight("SCORE_SIZE_WEIGHT", &value)?,
+            None => 1.0,
+        };
+
+        let target = match sources.get("TARGET") {
+            Some(value) => Target::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "TARGET must be folders, files or both, got {:?}",
+                    value
+                ))
+            })?,
+            None => Target::Folders,
+        };
+
+        let depth = match sources.get("DEPTH") {
+            Some(value) => Depth::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "DEPTH must be a whole number or auto, got {:?}",
+                    value
+                ))
+            })?,
+            None => Depth::Levels(2),
+        };
+
+        let exclude = sources
+            .get("EXCLUDE")
+            .map(|value| parse_list(&value))
+            .unwrap_or_default();
+        let extensions_priority = sources
+            .get("EXTENSIONS_PRIORITY")
+            .map(|value| {
+                parse_list(&value)
+                    .iter()
+                    .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                    .filter(|extension| !extension.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let include_pattern = match sources.get("INCLUDE_PATTERN").filter(|p| !p.is_empty()) {
+            Some(value) => Some(Regex::new(&value).map_err(|err| {
+                invalid_input(format!("INCLUDE_PATTERN {:?} is invalid: {}", value, err))
+            })?),
+            None => None,
+        };
+
+        let manifest = match sources.get("MANIFEST").filter(|path| !path.is_empty()) {
+            Some(path) => Some(read_manifest(&path)?),
+            None => None,
+        };
+
+        let skip_hidden = sources
+            .get("SKIP_HIDDEN")
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false);
+        let require_marker = sources
+            .get("REQUIRE_MARKER")
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false);
+        let prune_empty = sources
+            .get("PRUNE_EMPTY")
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false);
+        let stay_on_device = sources
+            .get("STAY_ON_DEVICE")
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false);
+        let empty_instead_of_delete = sources
+            .get("EMPTY_INSTEAD_OF_DELETE")
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false);
+
+        let trash_dir = sources.get("TRASH_DIR").filter(|dir| !dir.is_empty());
+        let archive_dir = sources.get("ARCHIVE_DIR").filter(|dir| !dir.is_empty());
+        if trash_dir.is_some() && archive_dir.is_some() {
+            return Err(invalid_input(
+                "TRASH_DIR and ARCHIVE_DIR cannot both be set".to_string(),
+            ));
+        }
+
+        let trash_retention = match sources.get("TRASH_RETENTION_DAYS") {
+            Some(value) => Some(parse_days("TRASH_RETENTION_DAYS", &value)?),
+            None => None,
+        };
+
+        let delete_retries = match sources.get("DELETE_RETRIES") {
+            Some(value) => value.trim().parse().map_err(|_| {
+                invalid_input(format!(
+                    "DELETE_RETRIES must be a whole number, got {:?}",
+                    value
+                ))
+            })?,
+            None => 3,
+        };
+        let delete_backoff = match sources.get("DELETE_BACKOFF_MS") {
+            Some(value) => Duration::from_millis(value.trim().parse().map_err(|_| {
+                invalid_input(format!(
+                    "DELETE_BACKOFF_MS must be a number of milliseconds, got {:?}",
+                    value
+                ))
+            })?),
+            None => Duration::from_millis(200),
+        };
+
+        let progress_every = match sources.get("PROGRESS_EVERY") {
+            Some(value) => Some(
+                value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|every: &u64| *every > 0)
+                    .ok_or_else(|| {
+                        invalid_input(format!(
+                            "PROGRESS_EVERY must be a positive number of files, got {:?}",
+                            value
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        let progress_min_bytes = match sources.get("PROGRESS_MIN_BYTES") {
+            Some(value) => parse_size(&value)
+                .map_err(|err| invalid_input(format!("PROGRESS_MIN_BYTES: {}", err)))?,
+            None => 1 << 30,
+        };
+
+        let max_deletions = match sources.get("MAX_DELETIONS") {
+            Some(value) => Some(value.trim().parse().map_err(|_| {
+                invalid_input(format!(
+                    "MAX_DELETIONS must be a whole number, got {:?}",
+                    value
+                ))
+            })?),
+            None => None,
+        };
+
+        let max_runtime = match sources.get("MAX_RUNTIME_SECONDS") {
+            Some(value) => Some(Duration::from_secs(value.trim().parse().map_err(|_| {
+                invalid_input(format!(
+                    "MAX_RUNTIME_SECONDS must be a number of seconds, got {:?}",
+                    value
+                ))
+            })?)),
+            None => None,
+        };
+
+        let min_age = match sources.get("MIN_AGE_DAYS") {
+            Some(value) => Some(parse_days("MIN_AGE_DAYS", &value)?),
+            None => None,
+        };
+
+        let active_grace = match sources.get("ACTIVE_GRACE_SECONDS") {
+            Some(value) => Some(Duration::from_secs(value.trim().parse().map_err(|_| {
+                invalid_input(format!(
+                    "ACTIVE_GRACE_SECONDS must be a number of seconds, got {:?}",
+                    value
+                ))
+            })?)),
+            None => None,
+        };
+
+        let keep_recent = match sources.get("KEEP_RECENT") {
+            Some(value) => Some(value.trim().parse().map_err(|_| {
+                invalid_input(format!(
+                    "KEEP_RECENT must be a whole number, got {:?}",
+                    value
+                ))
+            })?),
+            None => None,
+        };
+
+        let min_folder_bytes = match sources.get("MIN_FOLDER_BYTES") {
+            Some(value) => Some(
+                parse_size(&value)
+                    .map_err(|err| invalid_input(format!("MIN_FOLDER_BYTES: {}", err)))?,
+            ),
+            None => None,
+        };
+
+        let max_dir_bytes = match sources.get("MAX_DIR_BYTES") {
+            Some(value) => Some(
+                parse_size(&value)
+                    .map_err(|err| invalid_input(format!("MAX_DIR_BYTES: {}", err)))?,
+            ),
+            None => None,
+        };
+
+        let webhook_url = sources.get("WEBHOOK_URL").filter(|url| !url.is_empty());
+        if let Some(url) = &webhook_url {
+            HttpUrl::parse(url)?;
+        }
+        let pre_hook = sources.get("PRE_HOOK").filter(|hook| !hook.is_empty());
+        let post_hook = sources.get("POST_HOOK").filter(|hook| !hook.is_empty());
+        let on_failure = match sources.get("ON_FAILURE") {
+            Some(value) => OnFailure::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "ON_FAILURE must be log, exit-error or hook, got {:?}",
+                    value
+                ))
+            })?,
+            None => OnFailure::Log,
+        };
+        let on_failure_hook = sources
+            .get("ON_FAILURE_HOOK")
+            .filter(|hook| !hook.is_empty());
+        if on_failure == OnFailure::Hook && on_failure_hook.is_none() {
+            return Err(invalid_input(
+                "ON_FAILURE=hook needs ON_FAILURE_HOOK".to_string(),
+            ));
+        }
+
+        let smtp_url = sources.get("SMTP_URL").filter(|url| !url.is_empty());
+        let alert_to = sources
+            .get("ALERT_TO")
+            .map(|value| parse_list(&value))
+            .unwrap_or_default();
+        let alert_from = sources.get("ALERT_FROM").filter(|from| !from.is_empty());
+        if smtp_url.is_some() && (alert_to.is_empty() || alert_from.is_none()) {
+            return Err(invalid_input(
+                "SMTP_URL needs both ALERT_TO and ALERT_FROM".to_string(),
+            ));
+        }
+        #[cfg(feature = "email")]
+        if let (Some(url), Some(from)) = (&smtp_url, &alert_from) {
+            crate::email::check_settings(url, from, &alert_to)?;
+        }
+
+        let size_units = match sources.get("SIZE_UNITS") {
+            Some(value) => SizeUnits::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "SIZE_UNITS must be binary or decimal, got {:?}",
+                    value
+                ))
+            })?,
+            None => SizeUnits::Binary,
+        };
+
+        let log_format = match sources.get("LOG_FORMAT") {
+            Some(value) => LogFormat::parse(&value).ok_or_else(|| {
+                invalid_input(format!("LOG_FORMAT must be text or json, got {:?}", value))
+            })?,
+            None => LogFormat::Text,
+        };
+
+        let log_level = match sources.get("LOG_LEVEL") {
+            Some(value) => LogLevel::parse(&value).ok_or_else(|| {
+                invalid_input(format!(
+                    "LOG_LEVEL must be debug, info, warn or error, got {:?}",
+                    value
+                ))
+            })?,
+            None => LogLevel::Info,
+        };
+
+        let log_max_bytes = match sources.get("LOG_MAX_BYTES") {
+            Some(value) => Some(
+                parse_size(&value)
+                    .map_err(|err| invalid_input(format!("LOG_MAX_BYTES: {}", err)))?,
+            ),
+            None => None,
+        };
+        let log_keep = match sources.get("LOG_KEEP") {
+            Some(value) => value.trim().parse().map_err(|_| {
+                invalid_input(format!("LOG_KEEP must be a whole number, got {:?}", value))
+            })?,
+            None => 5,
+        };
+        let log_expiry = match sources.get("LOG_EXPIRY_DAYS") {
+            Some(value) => match parse_days("LOG_EXPIRY_DAYS", &value)? {
+                expiry if expiry.is_zero() => {
+                    return Err(invalid_input(format!(
+                        "LOG_EXPIRY_DAYS must be a positive number, got {:?}",
+                        value
+                    )))
+                }
+                expiry => expiry,
+            },
+            None => Duration::from_secs(7 * 24 * 60 * 60),
+        };
+
+        let instance_id = sources
+            .get("INSTANCE_ID")
+            .filter(|id| !id.is_empty())
+            .or_else(|| hostname().map(str::to_string))
+            .unwrap_or_else(|| "unknown".to_string());
+        let log_instance = sources
+            .get("LOG_INSTANCE")
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false);
+
+        let report_path = sources.get("REPORT_PATH").filter(|path| !path.is_empty());
+        let report_append = sources
+            .get("REPORT_APPEND")
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false);
+
+        let disk_name = sources.get("DISK_NAME").filter(|name| !name.is_empty());
+        let mount_point = sources.get("MOUNT_POINT").filter(|path| !path.is_empty());
+        if disk_name.is_some() && mount_point.is_some() {
+            return Err(invalid_input(
+                "DISK_NAME and MOUNT_POINT cannot both be set".to_string(),
+            ));
+        }
+        let target_disk = sources
+            .get("TARGET_DISK")
+            .map(|value| TargetDisk::parse(&value))
+            .unwrap_or(TargetDisk::Primary);
+        if target_disk != TargetDisk::Primary && (disk_name.is_some() || mount_point.is_some()) {
+            return Err(invalid_input(
+                "TARGET_DISK cannot be combined with DISK_NAME or MOUNT_POINT".to_string(),
+            ));
+        }
+
+        let lock_file = sources
+            .get("LOCK_FILE")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| env::temp_dir().join("disk-cleanup.lock"));
+
+        let interval = match cli.interval.or_else(|| sources.get("INTERVAL")) {
+            Some(value) => Some(
+                value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|seconds: &u64| *seconds > 0)
+                    .map(Duration::from_secs)
+                    .ok_or_else(|| {
+                        invalid_input(format!(
+                            "INTERVAL must be a positive number of seconds, got {:?}",
+                            value
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+
+        let jitter = match sources.get("JITTER_SECONDS") {
+            Some(value) => Some(Duration::from_secs(value.trim().parse().map_err(|_| {
+                invalid_input(format!(
+                    "JITTER_SECONDS must be a number of seconds, got {:?}",
+                    value
+                ))
+            })?)),
+            None => None,
+        };
+
+        let cooldown = match sources.get("COOLDOWN_SECONDS") {
+            Some(value) => Some(Duration::from_secs(value.trim().parse().map_err(|_| {
+                invalid_input(format!(
+                    "COOLDOWN_SECONDS must be a number of seconds, got {:?}",
+                    value
+                ))
+            })?)),
+            None => None,
+        };
+
+        let metrics_addr = sources.get("METRICS_ADDR").filter(|addr| !addr.is_empty());
+
+        // Deliberately absent from SETTINGS: a config file shared between
+        // hosts must not be able to switch the safety check off.
+        let allow_shallow_base_dir = sources
+            .get("I_KNOW_WHAT_IM_DOING")
+            .map(|v| parse_flag(&v))
+            .unwrap_or(false);
+
+        let force = cli.force
+            || sources
+                .get("FORCE")
+                .map(|v| parse_flag(&v))
+                .unwrap_or(false);
+
+        let dry_run = cli.dry_run
+            || sources
+                .get("DRYRUN")
+                .map(|v| parse_flag(&v))
+                .unwrap_or(false);
+
+        Ok(Config {
+            base_dirs,
+            unmatched_dir_patterns,
+            resolved_dirs,
+            log_path,
+            log_file,
+            audit_log,
+            threshold_mode,
+            start_threshold,
+            stop_threshold,
+            start_free_bytes,
+            stop_free_bytes,
+            sort_by,
+            strategy,
+            clean_mode,
+            score_age_weight,
+            score_size_weight,
+            target,
+            depth,
+            exclude,
+            extensions_priority,
+            include_pattern,
+            manifest,
+            skip_hidden,
+            require_marker,
+            stay_on_device,
+            empty_instead_of_delete,
+            prune_empty,
+            trash_dir,
+            trash_retention,
+            archive_dir,
+            delete_retries,
+            delete_backoff,
+            progress_every,
+            progress_min_bytes,
+            max_deletions,
+            max_runtime,
+            min_age,
+            min_folder_bytes,
+            active_grace,
+            keep_recent,
+            max_dir_bytes,
+            webhook_url,
+            pre_hook,
+            post_hook,
+            on_failure,
+            on_failure_hook,
+            smtp_url,
+            alert_to,
+            alert_from,
+            size_units,
+            log_format,
+            log_level,
+            log_max_bytes,
+            log_keep,
+            log_expiry,
+            instance_id,
+            log_instance,
+            report_path,
+            report_append,
+            disk_name,
+            mount_point,
+            target_disk,
+            lock_file,
+            interval,
+            jitter,
+            cooldown,
+            metrics_addr,
+            allow_shallow_base_dir,
+            interactive: cli.interactive,
+            force,
+            dry_run,
+            dir_overrides: BTreeMap::new(),
+        })
+    }
+
+    /// The start trigger as shown in log lines, e.g. `20%` or `10.00 GiB`.
+    pub(crate) fn start_label(&self) -> String {
+        match self.start_free_bytes {
+            Some(bytes) => format_bytes(bytes),
+            None => format!("{}%", self.start_threshold),
+        }
+    }
+
+    /// The stop target as shown in log lines, e.g. `25%` or `15.00 GiB`.
+    pub(crate) fn stop_label(&self) -> String {
+        match self.stop_free_bytes {
+            Some(bytes) => format_bytes(bytes),
+            None => format!("{}%", self.stop_threshold),
+        }
+    }
+}
+
+/// Checks that `config` can work before anything is cleaned: every base
+/// directory exists and is a directory, the audit log can be written, both
+/// thresholds are percentages and the report path has a parent directory.
+/// All problems are reported together in one error.
+pub fn validate_config(config: &Config) -> std::io::Result<()> {
+    let mut problems = Vec::new();
+
+    for dir in &config.base_dirs {
+        match std::fs::metadata(dir) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => problems.push(format!(
+                "DIRPATH entry {} is not a directory; point it at the folder to clean",
+                dir
+            )),
+            Err(err) => problems.push(format!(
+                "DIRPATH entry {} cannot be read ({}); check that it exists",
+                dir, err
+            )),
+        }
+    }
+
+    if let Some(audit_log) = &config.audit_log {
+        if let Err(err) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(audit_log)
+        {
+            problems.push(format!(
+                "Audit log {} is not writable ({}); fix AUDIT_LOG or its permissions",
+                audit_log, err
+            ));
+        }
+    }
+
+    let overrides = config
+        .dir_overrides
+        .iter()
+        .map(|(dir, config)| (format!(" for {}", dir), config));
+    for (scope, config) in std::iter::once((String::new(), config)).chain(overrides) {
+        for (name, value) in [
+            ("START_THRESHOLD", config.start_threshold),
+            ("STOP_THRESHOLD", config.stop_threshold),
+        ] {
+            if !(0.0..=100.0).contains(&value) {
+                let (value, kind) = match config.threshold_mode {
+                    ThresholdMode::Free => (value, "free-space"),
+                    ThresholdMode::Used => (100.0 - value, "used-space"),
+                };
+                problems.push(format!(
+                    "{}{} is {}%; it must be a {} percentage between 0 and 100",
+                    name, scope, value, kind
+                ));
+            }
+        }
+    }
+
+    if let Some(report_path) = &config.report_path {
+        let parent = Path::new(report_path)
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        if !parent.is_dir() {
+            problems.push(format!(
+                "REPORT_PATH {} is in a directory that does not exist; create {} first",
+                report_path,
+                parent.to_string_lossy()
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+    Err(invalid_input(format!(
+        "Invalid configuration:\n  - {}",
+        problems.join("\n  - ")
+    )))
+}
+
+#[cfg(test)]
+impl Config {
+    /// Settings for a unit test: `settings` in config-file syntax, with
+    /// `<root>/base` as the base directory and `root` holding the log. The
+    /// process environment is not read.
+    pub(crate) fn for_test(root: &Path, settings: &str) -> Config {
+        Config::try_for_test(root, settings).unwrap()
+    }
+
+    /// Like `for_test`, returning the error a bad setting causes.
+    pub(crate) fn try_for_test(root: &Path, settings: &str) -> std::io::Result<Config> {
+        let sources = Sources {
+            file: Table::new(),
+            overrides: Table::new(),
+            dir: Some(config_file::parse(settings).unwrap()),
+            env: Some(BTreeMap::new()),
+        };
+        let cli = CliArgs {
+            dir: Some(root.join("base").to_string_lossy().to_string()),
+            log_path: Some(root.to_string_lossy().to_string()),
+            ..CliArgs::default()
+        };
+        Config::from_sources(cli, &sources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn parse_days_converts_fractional_days() {
+        assert_eq!(
+            parse_days("MIN_AGE_DAYS", "1.5").unwrap(),
+            Duration::from_secs(36 * 60 * 60)
+        );
+        assert_eq!(parse_days("MIN_AGE_DAYS", " 0 ").unwrap(), Duration::ZERO);
+    }
+
+    #[test]
+    fn parse_days_rejects_values_that_cannot_be_a_duration() {
+        for value in ["-1", "inf", "NaN", "1e300", "soon", ""] {
+            let err = parse_days("MIN_AGE_DAYS", value).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn validate_config_accepts_a_working_setup() {
+        let dir = TempDir::new("validate-ok");
+        dir.dir("base");
+        let mut config = Config::for_test(dir.path(), "");
+        config.audit_log = Some(dir.path().join("audit.log").to_string_lossy().to_string());
+        config.report_path = Some(dir.path().join("report.json").to_string_lossy().to_string());
+        validate_config(&config).unwrap();
+    }
+
+    #[test]
+    fn validate_config_reports_every_problem_at_once() {
+        let dir = TempDir::new("validate-bad");
+        let file = dir.file("not-a-dir", b"", std::time::SystemTime::now(), 0);
+        let missing = dir.path().join("missing");
+        let mut config = Config::for_test(dir.path(), "");
+        config.base_dirs = vec![
+            file.to_string_lossy().to_string(),
+            missing.to_string_lossy().to_string(),
+        ];
+        config.audit_log = Some(missing.join("audit.log").to_string_lossy().to_string());
+        config.start_threshold = -5.0;
+        config.stop_threshold = 120.0;
+        config.report_path = Some(missing.join("report.json").to_string_lossy().to_string());
+
+        let err = validate_config(&config).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        let message = err.to_string();
+        for problem in [
+            "is not a directory",
+            "cannot be read",
+            "Audit log",
+            "START_THRESHOLD is -5%",
+            "STOP_THRESHOLD is 120%",
+            "REPORT_PATH",
+        ] {
+            assert!(message.contains(problem), "{:?} in {}", problem, message);
+        }
+        assert_eq!(message.lines().count(), 7);
+    }
+
+    /// Loads `settings` in config-file syntax, keeping the error that
+    /// `Config::for_test` would panic on.
+    fn load(settings: &str) -> std::io::Result<Config> {
+        let sources = Sources {
+            file: Table::new(),
+            overrides: Table::new(),
+            dir: Some(config_file::parse(settings).unwrap()),
+            env: Some(env_vars(&[("LOGPATH", "/var/log/disk-cleanup")])),
+        };
+        let cli = CliArgs {
+            dir: Some("/srv/recordings".to_string()),
+            ..CliArgs::default()
+        };
+        Config::from_sources(cli, &sources)
+    }
+
+    fn env_vars(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Sources holding only the environment variables `vars`.
+    fn environment(vars: &[(&str, &str)]) -> Sources {
+        Sources {
+            file: Table::new(),
+            overrides: Table::new(),
+            dir: None,
+            env: Some(env_vars(vars)),
+        }
+    }
+
+    #[test]
+    fn prefixed_environment_variables_beat_bare_ones() {
+        let both = environment(&[("CLEANUP_DIRPATH", "/srv/a"), ("DIRPATH", "/srv/b")]);
+        assert_eq!(both.get("DIRPATH").as_deref(), Some("/srv/a"));
+        let bare = environment(&[("DIRPATH", "/srv/b")]);
+        assert_eq!(bare.get("DIRPATH").as_deref(), Some("/srv/b"));
+        let prefixed = environment(&[("CLEANUP_DIRPATH", "/srv/a")]);
+        assert_eq!(prefixed.get("DIRPATH").as_deref(), Some("/srv/a"));
+        assert_eq!(environment(&[]).get("DIRPATH"), None);
+    }
+
+    #[test]
+    fn env_prefix_picks_the_prefix() {
+        let custom = environment(&[
+            ("ENV_PREFIX", "DC_"),
+            ("DC_DIRPATH", "/srv/dc"),
+            ("CLEANUP_DIRPATH", "/srv/a"),
+            ("DIRPATH", "/srv/b"),
+        ]);
+        assert_eq!(custom.get("DIRPATH").as_deref(), Some("/srv/dc"));
+        let custom_bare = environment(&[
+            ("ENV_PREFIX", "DC_"),
+            ("CLEANUP_DIRPATH", "/srv/a"),
+            ("DIRPATH", "/srv/b"),
+        ]);
+        assert_eq!(custom_bare.get("DIRPATH").as_deref(), Some("/srv/b"));
+        let none = environment(&[
+            ("ENV_PREFIX", ""),
+            ("CLEANUP_DIRPATH", "/srv/a"),
+            ("DIRPATH", "/srv/b"),
+        ]);
+        assert_eq!(none.get("DIRPATH").as_deref(), Some("/srv/b"));
+    }
+
+    /// `LOGPATH` as resolved from `vars` alone.
+    fn log_path_from(vars: &[(&str, &str)]) -> std::io::Result<String> {
+        let cli = CliArgs {
+            dir: Some("/srv/recordings".to_string()),
+            ..CliArgs::default()
+        };
+        Config::from_sources(cli, &environment(vars)).map(|config| config.log_path)
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn the_log_dir_defaults_to_the_xdg_state_dir() {
+        let dir = TempDir::new("xdg-state");
+        let state = dir.path().join("state");
+        let home = dir.path().join("home");
+        let state_dir = state.to_string_lossy();
+        let home_dir = home.to_string_lossy();
+
+        let log_path =
+            log_path_from(&[("XDG_STATE_HOME", &state_dir), ("HOME", &home_dir)]).unwrap();
+        assert_eq!(Path::new(&log_path), state.join("disk-cleanup"));
+        assert!(state.join("disk-cleanup").is_dir(), "created on first use");
+
+        let log_path = log_path_from(&[("HOME", &home_dir)]).unwrap();
+        assert_eq!(Path::new(&log_path), home.join(".local/state/disk-cleanup"));
+        // A relative XDG_STATE_HOME is ignored, as the spec asks.
+        let log_path = log_path_from(&[("XDG_STATE_HOME", "state"), ("HOME", &home_dir)]).unwrap();
+        assert_eq!(Path::new(&log_path), home.join(".local/state/disk-cleanup"));
+
+        let err = log_path_from(&[]).unwrap_err();
+        assert!(err.to_string().starts_with("LOGPATH not set"), "{}", err);
+    }
+
+    #[test]
+    fn an_explicit_log_path_beats_the_default() {
+        let dir = TempDir::new("xdg-state-explicit");
+        let state = dir.path().join("state");
+        let state_dir = state.to_string_lossy();
+
+        let log_path =
+            log_path_from(&[("XDG_STATE_HOME", &state_dir), ("LOGPATH", "/var/log/dc")]).unwrap();
+        assert_eq!(log_path, "/var/log/dc");
+        let log_path = log_path_from(&[
+            ("XDG_STATE_HOME", &state_dir),
+            ("LOG_FILE", "/var/log/dc.log"),
+        ])
+        .unwrap();
+        assert_eq!(log_path, ".");
+        assert!(!state.exists());
+    }
+
+    #[test]
+    fn overrides_beat_the_defaults_and_inherit_the_rest() {
+        let dir = TempDir::new("overrides");
+        let busy = dir.dir("busy").to_string_lossy().to_string();
+        let quiet = dir.dir("quiet").to_string_lossy().to_string();
+        let mut file = config_file::parse(&format!(
+            "start_threshold = 15\nstop_threshold = 30\nmin_age_days = 3\n\
+             [overrides.{:?}]\nstop_threshold = 40\nkeep_recent = 2",
+            busy
+        ))
+        .unwrap();
+        let Some(Value::Table(overrides)) = file.remove("overrides") else {
+            panic!("no overrides table");
+        };
+        let sources = Sources {
+            file,
+            overrides,
+            dir: None,
+            env: Some(env_vars(&[("CLEANUP_STOP_THRESHOLD", "35")])),
+        };
+        let cli = CliArgs {
+            dir: Some(format!("{},{}", busy, quiet)),
+            log_path: Some(dir.path().to_string_lossy().to_string()),
+            ..CliArgs::default()
+        };
+
+        let config = Config::with_overrides(cli, sources).unwrap();
+
+        let busy = config.for_dir(&busy);
+        assert_eq!(
+            busy.stop_threshold, 40.0,
+            "the override beats the environment"
+        );
+        assert_eq!(busy.keep_recent, Some(2));
+        assert_eq!(busy.start_threshold, 15.0, "inherited from the file");
+        assert_eq!(busy.min_age, Some(Duration::from_secs(3 * 24 * 60 * 60)));
+        let quiet = config.for_dir(&quiet);
+        assert_eq!(quiet.stop_threshold, 35.0);
+        assert_eq!(quiet.keep_recent, None);
+        assert_eq!(config.dir_overrides.len(), 1);
+    }
+
+    #[test]
+    fn the_config_file_comes_after_the_environment() {
+        let mut sources = environment(&[("CLEANUP_START_THRESHOLD", "15")]);
+        sources.file = config_file::parse("start_threshold = 10\nstop_threshold = 30").unwrap();
+        assert_eq!(sources.get("START_THRESHOLD").as_deref(), Some("15"));
+        assert_eq!(sources.get("STOP_THRESHOLD").as_deref(), Some("30"));
+    }
+
+    #[test]
+    fn used_mode_thresholds_behave_like_their_free_equivalents() {
+        let free = load("start_threshold = 20\nstop_threshold = 25").unwrap();
+        let used =
+            load("threshold_mode = \"used\"\nstart_threshold = 80\nstop_threshold = 75").unwrap();
+        assert_eq!(used.threshold_mode, ThresholdMode::Used);
+        assert_eq!(
+            (used.start_threshold, used.stop_threshold),
+            (free.start_threshold, free.stop_threshold)
+        );
+        let defaults = load("threshold_mode = \"used\"").unwrap();
+        assert_eq!(
+            (defaults.start_threshold, defaults.stop_threshold),
+            (20.0, 25.0)
+        );
+
+        for percentage in [10.0, 20.0, 22.5, 25.0, 30.0] {
+            let space = crate::storage::FreeSpace {
+                percentage,
+                available: 0,
+            };
+            assert_eq!(
+                space.below_start(&used),
+                space.below_start(&free),
+                "{}",
+                percentage
+            );
+            assert_eq!(
+                space.above_stop(&used),
+                space.above_stop(&free),
+                "{}",
+                percentage
+            );
+        }
+    }
+
+    #[test]
+    fn used_mode_stops_below_where_it_starts() {
+        for settings in [
+            "threshold_mode = \"used\"\nstart_threshold = 70\nstop_threshold = 80",
+            "threshold_mode = \"used\"\nstart_threshold = 75\nstop_threshold = 75",
+        ] {
+            let err = load(settings).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(
+                err.to_string().starts_with("With THRESHOLD_MODE=used"),
+                "{}",
+                err
+            );
+        }
+        // The same numbers are fine as free-space percentages.
+        load("start_threshold = 70\nstop_threshold = 80").unwrap();
+    }
+
+    #[test]
+    fn dir_patterns_that_match_nothing_are_kept_for_the_warning() {
+        let dir = TempDir::new("unmatched-dir-pattern");
+        dir.dir("base/cam1");
+        let base = dir.path().join("base");
+        let cli = CliArgs {
+            dir: Some(format!(
+                "{},{}",
+                base.join("cam*").display(),
+                base.join("nvr*").display()
+            )),
+            log_path: Some(dir.path().to_string_lossy().to_string()),
+            ..CliArgs::default()
+        };
+
+        let config = Config::from_sources(cli, &environment(&[])).unwrap();
+
+        let cam1 = std::fs::canonicalize(base.join("cam1")).unwrap();
+        assert_eq!(config.base_dirs, [cam1.to_string_lossy()]);
+        assert_eq!(
+            config.unmatched_dir_patterns,
+            [base.join("nvr*").to_string_lossy()]
+        );
+    }
+
+    #[test]
+    fn a_pattern_that_does_not_compile_fails_the_load() {
+        let err = load("include_pattern = \"cam(1\"").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(err
+            .to_string()
+            .starts_with("INCLUDE_PATTERN \"cam(1\" is invalid"));
+    }
+
+    #[test]
+    fn an_unsupported_webhook_url_fails_the_load() {
+        let err = load("webhook_url = \"https://hooks.example.com/cleanup\"").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        load("webhook_url = \"http://monitoring.local:8080/cleanup\"").unwrap();
+    }
+
+    #[cfg(feature = "email")]
+    #[test]
+    fn unsupported_alert_settings_fail_the_load() {
+        let alert = |url: &str, from: &str| {
+            load(&format!(
+                "[alert]\nsmtp_url = \"{}\"\nto = [\"ops@example.com\"]\nfrom = \"{}\"",
+                url, from
+            ))
+        };
+        alert("smtp://relay.local", "nvr@example.com").unwrap();
+        for (url, from) in [
+            ("smtps://smtp.example.com", "nvr@example.com"),
+            ("smtp://relay.local", "NVR <nvr@example.com>"),
+        ] {
+            let err = alert(url, from).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                std::io::ErrorKind::InvalidInput,
+                "{} {}",
+                url,
+                from
+            );
+        }
+    }
+}
@@ -0,0 +1,534 @@
+//! A small reader for the TOML subset used by `cleanup.toml`:
+//!
+//! - `[table]` headers, each defined once, and bare, quoted or dotted keys
+//! - basic strings with the `\\ \" \n \t \r \uXXXX \UXXXXXXXX` escapes,
+//!   and literal `'strings'`
+//! - decimal integers and floats, with `_` between digits
+//! - `true` and `false`
+//! - arrays of any of these, which may span lines
+//!
+//! Anything else is an error naming the line rather than a guess: inline
+//! tables, arrays of tables, multi-line strings, dates and times, hex, octal
+//! and binary integers, and `inf`/`nan`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+pub(crate) type Table = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    /// Renders a scalar or array in the same textual form the matching
+    /// environment variable would use, so both sources share one parser.
+    /// Arrays become comma-separated lists; tables have no such form.
+    pub(crate) fn to_setting(&self) -> Option<String> {
+        match self {
+            Value::String(value) => Some(value.clone()),
+            Value::Integer(value) => Some(value.to_string()),
+            Value::Float(value) => Some(value.to_string()),
+            Value::Boolean(value) => Some(value.to_string()),
+            Value::Array(items) => Some(
+                items
+                    .iter()
+                    .filter_map(Value::to_setting)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            Value::Table(_) => None,
+        }
+    }
+}
+
+/// Looks up a dotted key such as `log.format`.
+pub(crate) fn lookup<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let mut parts = key.split('.');
+    let mut value = table.get(parts.next()?)?;
+    for part in parts {
+        match value {
+            Value::Table(inner) => value = inner.get(part)?,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+pub(crate) fn load(path: &Path) -> std::io::Result<Table> {
+    let text = fs::read_to_string(path)?;
+    parse(&text).map_err(|message| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", path.to_string_lossy(), message),
+        )
+    })
+}
+
+pub(crate) fn parse(text: &str) -> Result<Table, String> {
+    let mut root = Table::new();
+    let mut current: Vec<String> = Vec::new();
+    let mut headers = BTreeSet::new();
+    let mut lines = text.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let line_no = index + 1;
+        let mut line = strip_comment(line).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with("[[") {
+            return Err(format!(
+                "line {}: arrays of tables are not supported",
+                line_no
+            ));
+        }
+        if line.starts_with('[') {
+            let header = line
+                .strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+                .ok_or_else(|| format!("line {}: malformed table header", line_no))?;
+            current = parse_key(header).map_err(|err| format!("line {}: {}", line_no, err))?;
+            if !headers.insert(current.clone()) {
+                return Err(format!(
+                    "line {}: table `{}` is defined twice",
+                    line_no,
+                    current.join(".")
+                ));
+            }
+            table_at(&mut root, &current).map_err(|err| format!("line {}: {}", line_no, err))?;
+            continue;
+        }
+
+        // Arrays may span several lines; keep reading until brackets balance.
+        while bracket_depth(&line) > 0 {
+            match lines.next() {
+                Some((_, next)) => {
+                    line.push(' ');
+                    line.push_str(strip_comment(next).trim());
+                }
+                None => return Err(format!("line {}: unterminated array", line_no)),
+            }
+        }
+
+        let (key, value) = split_assignment(&line)
+            .ok_or_else(|| format!("line {}: expected `key = value`", line_no))?;
+        let mut path = current.clone();
+        path.extend(parse_key(key).map_err(|err| format!("line {}: {}", line_no, err))?);
+        let value =
+            parse_value(value.trim()).map_err(|err| format!("line {}: {}", line_no, err))?;
+
+        let (name, parents) = path.split_last().expect("keys are never empty");
+        let table =
+            table_at(&mut root, parents).map_err(|err| format!("line {}: {}", line_no, err))?;
+        if table.insert(name.clone(), value).is_some() {
+            return Err(format!("line {}: duplicate key `{}`", line_no, name));
+        }
+    }
+
+    Ok(root)
+}
+
+fn table_at<'a>(root: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    let mut table = root;
+    for part in path {
+        let entry = table
+            .entry(part.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        table = match entry {
+            Value::Table(inner) => inner,
+            _ => return Err(format!("`{}` is not a table", part)),
+        };
+    }
+    Ok(table)
+}
+
+/// The characters of `text` outside string literals, with their offsets.
+/// Quotes themselves count as inside.
+fn unquoted(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    text.char_indices().filter(move |&(_, c)| {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => return true,
+        }
+        false
+    })
+}
+
+/// Removes a trailing `#` comment that is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    match unquoted(line).find(|&(_, c)| c == '#') {
+        Some((index, _)) => &line[..index],
+        None => line,
+    }
+}
+
+fn bracket_depth(text: &str) -> i32 {
+    unquoted(text)
+        .map(|(_, c)| match c {
+            '[' => 1,
+            ']' => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+fn split_assignment(line: &str) -> Option<(&str, &str)> {
+    let (index, _) = unquoted(line).find(|&(_, c)| c == '=')?;
+    Some((&line[..index], &line[index + 1..]))
+}
+
+/// Splits a possibly dotted key (`a.b`, `dirs."/srv/x"`) into its parts.
+fn parse_key(key: &str) -> Result<Vec<String>, String> {
+    let mut parts = Vec::new();
+    let mut rest = key.trim();
+
+    loop {
+        let (part, remainder) = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').ok_or("unterminated quoted key")?;
+            (quoted[..end].to_string(), &quoted[end + 1..])
+        } else if let Some(quoted) = rest.strip_prefix('\'') {
+            let end = quoted.find('\'').ok_or("unterminated quoted key")?;
+            (quoted[..end].to_string(), &quoted[end + 1..])
+        } else {
+            let end = rest.find('.').unwrap_or(rest.len());
+            let part = rest[..end].trim();
+            if part.is_empty()
+                || !part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(format!("invalid key `{}`", key.trim()));
+            }
+            (part.to_string(), &rest[end..])
+        };
+        parts.push(part);
+
+        let remainder = remainder.trim();
+        if remainder.is_empty() {
+            return Ok(parts);
+        }
+        rest = remainder
+            .strip_prefix('.')
+            .ok_or_else(|| format!("invalid key `{}`", key.trim()))?
+            .trim();
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    if text.starts_with("\"\"\"") || text.starts_with("'''") {
+        return Err("multi-line strings are not supported".to_string());
+    }
+    if let Some(inner) = text.strip_prefix('"') {
+        return parse_basic_string(inner).map(Value::String);
+    }
+    if let Some(inner) = text.strip_prefix('\'') {
+        let (inner, rest) = inner.split_once('\'').ok_or("unterminated string")?;
+        if !rest.is_empty() {
+            return Err(format!("unexpected `{}` after a string", rest));
+        }
+        return Ok(Value::String(inner.to_string()));
+    }
+    if text.starts_with('{') {
+        return Err("inline tables are not supported; use a [table] header".to_string());
+    }
+    if let Some(inner) = text.strip_prefix('[') {
+        let inner = inner.strip_suffix(']').ok_or("unterminated array")?;
+        return split_array(inner)?
+            .into_iter()
+            .map(parse_value)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array);
+    }
+    match text {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+
+    parse_number(text).ok_or_else(|| {
+        if is_date(text) {
+            format!("dates and times are not supported; quote `{}`", text)
+        } else {
+            format!("unsupported value `{}`", text)
+        }
+    })
+}
+
+/// Parses a decimal integer or float. `_` must sit between two digits, and
+/// a `.` needs digits on both sides, as in TOML.
+fn parse_number(text: &str) -> Option<Value> {
+    let bytes = text.as_bytes();
+    let grouped = bytes.iter().enumerate().all(|(index, &b)| match b {
+        b'0'..=b'9' | b'+' | b'-' | b'e' | b'E' => true,
+        b'_' | b'.' => {
+            index > 0
+                && bytes[index - 1].is_ascii_digit()
+                && bytes.get(index + 1).is_some_and(u8::is_ascii_digit)
+        }
+        _ => false,
+    });
+    if !grouped {
+        return None;
+    }
+
+    let number = text.replace('_', "");
+    // No leading zeros: `007` is not a TOML number.
+    let whole = number
+        .trim_start_matches(['+', '-'])
+        .split(['.', 'e', 'E'])
+        .next()
+        .unwrap_or("");
+    if whole.len() > 1 && whole.starts_with('0') {
+        return None;
+    }
+    if let Ok(value) = number.parse::<i64>() {
+        return Some(Value::Integer(value));
+    }
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .map(Value::Float)
+}
+
+/// Whether `text` starts like a TOML date, `YYYY-MM-DD`.
+fn is_date(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() >= 10
+        && bytes[4] == b'-'
+        && bytes[7] == b'-'
+        && [0, 1, 2, 3, 5, 6, 8, 9]
+            .iter()
+            .all(|&index| bytes[index].is_ascii_digit())
+}
+
+fn split_array(inner: &str) -> Result<Vec<&str>, String> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (index, c) in unquoted(inner) {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(inner[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let last = inner[start..].trim();
+    if !last.is_empty() {
+        items.push(last);
+    }
+    if items.iter().any(|item| item.is_empty()) {
+        return Err("empty array element".to_string());
+    }
+    Ok(items)
+}
+
+/// Reads a basic string from just after its opening quote, which must be
+/// closed at the end of `text`.
+fn parse_basic_string(text: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => {
+                let rest = &text[index + 1..];
+                if !rest.is_empty() {
+                    return Err(format!("unexpected `{}` after a string", rest));
+                }
+                return Ok(result);
+            }
+            '\\' => {}
+            _ => {
+                result.push(c);
+                continue;
+            }
+        }
+        match chars.next().map(|(_, c)| c) {
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(kind @ ('u' | 'U')) => {
+                let len = if kind == 'u' { 4 } else { 8 };
+                let hex: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                let c = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == len)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid escape `\\{}{}`", kind, hex))?;
+                result.push(c);
+            }
+            Some(other) => return Err(format!("unsupported escape `\\{}`", other)),
+            None => break,
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Value {
+        parse(&format!("key = {}", text))
+            .unwrap()
+            .remove("key")
+            .unwrap()
+    }
+
+    fn string(text: &str) -> Value {
+        Value::String(text.to_string())
+    }
+
+    #[test]
+    fn scalars_parse_to_their_types() {
+        assert_eq!(value("\"a \\\"b\\\" \\\\ \\t\""), string("a \"b\" \\ \t"));
+        assert_eq!(value("\"caf\\u00e9 \\U0001F4BE\""), string("café 💾"));
+        assert_eq!(value("'C:\\logs\\cleanup'"), string("C:\\logs\\cleanup"));
+        assert_eq!(
+            value("\"# not a comment\" # a comment"),
+            string("# not a comment")
+        );
+        assert_eq!(value("42"), Value::Integer(42));
+        assert_eq!(value("-7"), Value::Integer(-7));
+        assert_eq!(value("1_000_000"), Value::Integer(1_000_000));
+        assert_eq!(value("0"), Value::Integer(0));
+        assert_eq!(value("12.5"), Value::Float(12.5));
+        assert_eq!(value("0.25"), Value::Float(0.25));
+        assert_eq!(value("1e3"), Value::Float(1000.0));
+        assert_eq!(value("true"), Value::Boolean(true));
+        assert_eq!(value("false"), Value::Boolean(false));
+    }
+
+    #[test]
+    fn arrays_may_nest_and_span_lines() {
+        let table =
+            parse("exclude = [\n  \"keep*\", # pinned\n  'tmp,old',\n  [1, 2],\n]\nempty = []\n")
+                .unwrap();
+        assert_eq!(
+            table["exclude"],
+            Value::Array(vec![
+                string("keep*"),
+                string("tmp,old"),
+                Value::Array(vec![Value::Integer(1), Value::Integer(2)]),
+            ])
+        );
+        assert_eq!(table["empty"], Value::Array(Vec::new()));
+        assert_eq!(
+            value("[\"a]\", \"b\\\"]\"]"),
+            Value::Array(vec![string("a]"), string("b\"]")])
+        );
+    }
+
+    #[test]
+    fn tables_and_dotted_keys_nest() {
+        let table = parse(
+            "stop_threshold = 30\n\
+             log.format = \"json\"\n\
+             [dirs.\"/srv/cam.1\"]\n\
+             min_age_days = 2\n\
+             [alert]\n\
+             'smtp-url' = \"smtp://relay\"\n",
+        )
+        .unwrap();
+        assert_eq!(lookup(&table, "stop_threshold"), Some(&Value::Integer(30)));
+        assert_eq!(lookup(&table, "log.format"), Some(&string("json")));
+        assert_eq!(
+            lookup(&table, "alert.smtp-url"),
+            Some(&string("smtp://relay"))
+        );
+        // A quoted key keeps its dots.
+        let Some(Value::Table(dirs)) = lookup(&table, "dirs") else {
+            panic!("dirs is not a table");
+        };
+        let Value::Table(dir) = &dirs["/srv/cam.1"] else {
+            panic!("the directory is not a table");
+        };
+        assert_eq!(dir["min_age_days"], Value::Integer(2));
+        assert_eq!(lookup(&table, "stop_threshold.x"), None);
+    }
+
+    #[test]
+    fn settings_render_like_environment_values() {
+        assert_eq!(
+            value("[\"a\", 2, true]").to_setting().as_deref(),
+            Some("a,2,true")
+        );
+        assert_eq!(value("1.5").to_setting().as_deref(), Some("1.5"));
+        assert_eq!(parse("[t]\nx = 1").unwrap()["t"].to_setting(), None);
+    }
+
+    #[test]
+    fn the_example_config_stays_within_the_subset() {
+        parse(include_str!("../cleanup.example.toml")).unwrap();
+    }
+
+    #[test]
+    fn unsupported_syntax_is_rejected_with_its_line() {
+        for (text, message) in [
+            ("a = { b = 1 }", "line 1: inline tables are not supported"),
+            ("\n[[dirs]]", "line 2: arrays of tables are not supported"),
+            (
+                "a = \"\"\"long\"\"\"",
+                "line 1: multi-line strings are not supported",
+            ),
+            (
+                "a = '''long'''",
+                "line 1: multi-line strings are not supported",
+            ),
+            (
+                "a = 2024-05-01",
+                "line 1: dates and times are not supported",
+            ),
+            ("a = 0x1F", "line 1: unsupported value `0x1F`"),
+            ("a = 0o17", "line 1: unsupported value"),
+            ("a = inf", "line 1: unsupported value `inf`"),
+            ("a = nan", "line 1: unsupported value"),
+            ("a = 007", "line 1: unsupported value"),
+            ("a = 1__0", "line 1: unsupported value"),
+            ("a = .5", "line 1: unsupported value"),
+            ("a = 1.", "line 1: unsupported value"),
+            (
+                "a = \"x\" \"y\"",
+                "line 1: unexpected ` \"y\"` after a string",
+            ),
+            ("a = 'x' y", "line 1: unexpected ` y` after a string"),
+            ("a = \"open", "line 1: unterminated string"),
+            ("a = \"\\x\"", "line 1: unsupported escape `\\x`"),
+            ("a = \"\\u12\"", "line 1: invalid escape `\\u12\"`"),
+            ("a = [1,\n2", "line 1: unterminated array"),
+            ("a = [1,,2]", "line 1: empty array element"),
+            ("a b = 1", "line 1: invalid key `a b`"),
+            ("a = 1\na = 2", "line 2: duplicate key `a`"),
+            ("[t]\n[t]", "line 2: table `t` is defined twice"),
+            ("a = 1\n[a]", "line 2: `a` is not a table"),
+            ("just text", "line 1: expected `key = value`"),
+            ("a =", "line 1: unsupported value ``"),
+        ] {
+            let err = parse(text).unwrap_err();
+            assert!(err.starts_with(message), "{:?}: {}", text, err);
+        }
+    }
+}
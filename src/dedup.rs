@@ -0,0 +1,307 @@
+//! Finding byte-identical folders for `STRATEGY=dedup`, which deletes
+//! redundant copies before any unique data. A hash of each tree only sorts
+//! candidates into groups that might match; folders count as copies once a
+//! byte-for-byte comparison agrees, so a hash collision never deletes data.
+
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::config::Config;
+use crate::filesystem::FileSystem;
+use crate::logging::{log_message, LogLevel};
+use crate::scan::skip_entry;
+
+/// Modification time, size and content hash of an entry.
+type CachedHash = (SystemTime, u64, u64);
+
+thread_local! {
+    /// Content hashes computed during the current `clean_disk` call on this
+    /// thread, with the modification time and size each was computed at;
+    /// `None` outside one.
+    static HASH_CACHE: RefCell<Option<HashMap<PathBuf, CachedHash>>> =
+        const { RefCell::new(None) };
+}
+
+/// Starts an empty cache for `content_hash`, dropping any left over from an
+/// earlier call that failed.
+pub(crate) fn start_hash_cache() {
+    HASH_CACHE.with(|cache| *cache.borrow_mut() = Some(HashMap::new()));
+}
+
+pub(crate) fn stop_hash_cache() {
+    HASH_CACHE.with(|cache| *cache.borrow_mut() = None);
+}
+
+/// Names of the entries in the directory `path`, sorted.
+fn sorted_names(fs: &dyn FileSystem, path: &Path) -> std::io::Result<Vec<std::ffi::OsString>> {
+    let mut names: Vec<_> = fs
+        .read_dir(path)?
+        .into_iter()
+        .filter_map(|entry| entry.file_name().map(|name| name.to_os_string()))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Feeds `path` into `hasher`: its kind, and for a file its length and
+/// bytes, for a symlink its target, for a directory every entry in name
+/// order with its name. Two trees hash alike only when they hold the same
+/// names with the same contents.
+fn hash_tree(fs: &dyn FileSystem, path: &Path, hasher: &mut DefaultHasher) -> std::io::Result<()> {
+    let metadata = fs.symlink_metadata(path)?;
+    if metadata.is_symlink() {
+        hasher.write_u8(b'l');
+        hasher.write(fs.read_link(path)?.as_os_str().as_encoded_bytes());
+    } else if metadata.is_dir() {
+        hasher.write_u8(b'd');
+        let names = sorted_names(fs, path)?;
+        hasher.write_usize(names.len());
+        for name in names {
+            let name_bytes = name.as_encoded_bytes();
+            hasher.write_usize(name_bytes.len());
+            hasher.write(name_bytes);
+            hash_tree(fs, &path.join(name), hasher)?;
+        }
+    } else {
+        hasher.write_u8(b'f');
+        hasher.write_u64(metadata.len);
+        let mut file = fs.open(path)?;
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.write(&buffer[..read]);
+        }
+    }
+    Ok(())
+}
+
+/// Reads into `buffer` until it is full or `reader` ends, returning the
+/// number of bytes read.
+fn read_full(reader: &mut dyn Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+/// Whether `a` and `b` hold the same tree: the same kinds of entry under the
+/// same names, with the same symlink targets and file bytes.
+fn same_tree(fs: &dyn FileSystem, a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (metadata_a, metadata_b) = (fs.symlink_metadata(a)?, fs.symlink_metadata(b)?);
+    if metadata_a.kind != metadata_b.kind {
+        return Ok(false);
+    }
+    if metadata_a.is_symlink() {
+        return Ok(fs.read_link(a)? == fs.read_link(b)?);
+    }
+    if metadata_a.is_dir() {
+        let names = sorted_names(fs, a)?;
+        if names != sorted_names(fs, b)? {
+            return Ok(false);
+        }
+        for name in names {
+            if !same_tree(fs, &a.join(&name), &b.join(&name))? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+    if metadata_a.len != metadata_b.len {
+        return Ok(false);
+    }
+    let (mut file_a, mut file_b) = (fs.open(a)?, fs.open(b)?);
+    let (mut buffer_a, mut buffer_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let read = read_full(&mut file_a, &mut buffer_a)?;
+        if read_full(&mut file_b, &mut buffer_b)? != read || buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+        if read == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Hash of everything in `path`. Within one `clean_disk` call a result is
+/// reused while the entry's modification time and size are unchanged.
+pub(crate) fn content_hash(fs: &dyn FileSystem, path: &Path, size: u64) -> std::io::Result<u64> {
+    let modified = fs.symlink_metadata(path)?.modified()?;
+    let cached = HASH_CACHE.with(|cache| {
+        cache
+            .borrow()
+            .as_ref()
+            .and_then(|cache| cache.get(path))
+            .filter(|(time, bytes, _)| *time == modified && *bytes == size)
+            .map(|(_, _, hash)| *hash)
+    });
+    if let Some(hash) = cached {
+        return Ok(hash);
+    }
+    let mut hasher = DefaultHasher::new();
+    hash_tree(fs, path, &mut hasher)?;
+    let hash = hasher.finish();
+    HASH_CACHE.with(|cache| {
+        if let Some(cache) = cache.borrow_mut().as_mut() {
+            cache.insert(path.to_path_buf(), (modified, size, hash));
+        }
+    });
+    Ok(hash)
+}
+
+/// Groups `candidates` (timestamp, size, path) into sets of identical
+/// copies and returns every copy but the newest of each set. Only entries
+/// sharing a size with another one are hashed, only those sharing a hash are
+/// compared, and empty ones never count as duplicates.
+pub(crate) fn redundant_copies(
+    config: &Config,
+    fs: &dyn FileSystem,
+    candidates: &[(SystemTime, u64, PathBuf)],
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut by_size: BTreeMap<u64, Vec<&(SystemTime, u64, PathBuf)>> = BTreeMap::new();
+    for candidate in candidates.iter().filter(|(_, size, _)| *size > 0) {
+        by_size.entry(candidate.1).or_default().push(candidate);
+    }
+
+    let mut redundant = Vec::new();
+    for same_size in by_size.into_values().filter(|group| group.len() > 1) {
+        let mut by_hash: HashMap<u64, Vec<&(SystemTime, u64, PathBuf)>> = HashMap::new();
+        for candidate in same_size {
+            let (_, size, path) = candidate;
+            if let Some(hash) = skip_entry(config, path, content_hash(fs, path, *size))? {
+                by_hash.entry(hash).or_default().push(candidate);
+            }
+        }
+        for same_hash in by_hash.into_values().filter(|group| group.len() > 1) {
+            let mut sets: Vec<Vec<&(SystemTime, u64, PathBuf)>> = Vec::new();
+            for candidate in same_hash {
+                let mut placed = false;
+                for set in &mut sets {
+                    let path = &candidate.2;
+                    if skip_entry(config, path, same_tree(fs, &set[0].2, path))? == Some(true) {
+                        set.push(candidate);
+                        placed = true;
+                        break;
+                    }
+                }
+                if !placed {
+                    sets.push(vec![candidate]);
+                }
+            }
+            redundant.extend(older_copies(config, sets)?);
+        }
+    }
+    Ok(redundant)
+}
+
+/// Every copy but the newest of each set of identical folders.
+fn older_copies(
+    config: &Config,
+    sets: Vec<Vec<&(SystemTime, u64, PathBuf)>>,
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut redundant = Vec::new();
+    for mut copies in sets.into_iter().filter(|set| set.len() > 1) {
+        copies.sort_by_key(|(time, _, _)| *time);
+        let Some((_, _, newest)) = copies.pop() else {
+            continue;
+        };
+        for (_, _, path) in copies {
+            log_message(
+                &config.log_path,
+                LogLevel::Debug,
+                &format!(
+                    "Duplicate of {}: {}",
+                    newest.to_string_lossy(),
+                    path.to_string_lossy()
+                ),
+            )?;
+            redundant.push(path.clone());
+        }
+    }
+    Ok(redundant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::test_support::{MemoryFileSystem, TempDir};
+
+    /// Writes `files` (relative path, contents) under `folder`.
+    fn tree(fs: &MemoryFileSystem, folder: &Path, files: &[(&str, &[u8])]) {
+        for (name, contents) in files {
+            let path = folder.join(name);
+            fs.create_dir_all(path.parent().unwrap()).unwrap();
+            fs.write(&path, contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn same_tree_compares_names_kinds_and_bytes() {
+        let fs = MemoryFileSystem::new();
+        let base = Path::new("/base");
+        tree(&fs, &base.join("a"), &[("x", b"one"), ("sub/y", b"two")]);
+        tree(&fs, &base.join("b"), &[("x", b"one"), ("sub/y", b"two")]);
+        tree(&fs, &base.join("c"), &[("x", b"one"), ("sub/y", b"tw0")]);
+        tree(&fs, &base.join("d"), &[("x", b"one"), ("sub/z", b"two")]);
+        tree(&fs, &base.join("e"), &[("x", b"one"), ("sub", b"two")]);
+        fs.create_dir_all(&base.join("f")).unwrap();
+        fs.symlink(Path::new("x"), &base.join("f/l")).unwrap();
+        fs.create_dir_all(&base.join("g")).unwrap();
+        fs.symlink(Path::new("y"), &base.join("g/l")).unwrap();
+
+        let same = |a: &str, b: &str| same_tree(&fs, &base.join(a), &base.join(b)).unwrap();
+        assert!(same("a", "b"));
+        assert!(!same("a", "c"));
+        assert!(!same("a", "d"));
+        assert!(!same("a", "e"));
+        assert!(same("f", "f"));
+        assert!(!same("f", "g"));
+    }
+
+    #[test]
+    fn redundant_copies_returns_older_identical_copies_only() {
+        let dir = TempDir::new("dedup");
+        let config = Config::for_test(dir.path(), "");
+        let fs = MemoryFileSystem::new();
+        let base = Path::new("/base");
+        tree(
+            &fs,
+            &base.join("old"),
+            &[("video", b"frames"), ("meta/info", b"cam1")],
+        );
+        tree(
+            &fs,
+            &base.join("new"),
+            &[("video", b"frames"), ("meta/info", b"cam1")],
+        );
+        // Same size and layout, different bytes.
+        tree(
+            &fs,
+            &base.join("other"),
+            &[("video", b"frames"), ("meta/info", b"cam2")],
+        );
+        let time = |days: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86_400);
+        let candidates = vec![
+            (time(3), 10, base.join("new")),
+            (time(1), 10, base.join("old")),
+            (time(2), 10, base.join("other")),
+        ];
+
+        let redundant = redundant_copies(&config, &fs, &candidates).unwrap();
+
+        assert_eq!(redundant, [base.join("old")]);
+    }
+}
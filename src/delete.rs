@@ -0,0 +1,945 @@
+//! Removing a chosen folder or file, archiving it first, or moving it to the
+//! trash directory.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::archive::archive_folder;
+use crate::audit::{audit_deletion, AuditRecord};
+use crate::clock;
+use crate::config::Config;
+use crate::filesystem::{FileSystem, Metadata};
+use crate::logging::{
+    format_timestamp, json_escape, log_event, log_message, span, LogEvent, LogLevel,
+};
+use crate::scan::{folder_timestamp, skip_entry};
+use crate::size::{format_bytes, path_size};
+
+/// Copies `from` to `to`: folders with everything below them, files by
+/// content, and symlinks as links to the same target, not to copies of it.
+fn copy_tree(fs: &dyn FileSystem, from: &Path, to: &Path) -> std::io::Result<()> {
+    let metadata = fs.symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        fs.symlink(&fs.read_link(from)?, to)
+    } else if metadata.is_dir() {
+        fs.create_dir(to)?;
+        for entry in fs.read_dir(from)? {
+            let Some(name) = entry.file_name() else {
+                continue;
+            };
+            copy_tree(fs, &entry, &to.join(name))?;
+        }
+        Ok(())
+    } else {
+        fs.copy(from, to).map(|_| ())
+    }
+}
+
+/// Moves `from` to `to` by copying and then removing it, for a rename that
+/// would cross filesystems. A copy that fails partway removes what it had
+/// written and leaves `from` untouched.
+fn copy_and_remove(fs: &dyn FileSystem, from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Err(err) = copy_tree(fs, from, to) {
+        if fs.exists(to) {
+            let _ = remove_path(fs, to);
+        }
+        return Err(err);
+    }
+    remove_path(fs, from)
+}
+
+/// Starts the name of everything cleanup moves into `TRASH_DIR`.
+const TRASH_PREFIX: &str = ".trashed-";
+
+/// Picks a destination inside `trash_dir` named
+/// `.trashed-<unix-seconds>-<name>`, adding a numeric suffix if that name is
+/// already taken. The prefix keeps a user's own `2024_clip` in the trash
+/// from passing for a cleanup entry.
+pub(crate) fn trash_destination(
+    fs: &dyn FileSystem,
+    trash_dir: &Path,
+    folder: &Path,
+) -> std::path::PathBuf {
+    let name = folder
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let secs = clock::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+
+    let mut destination = trash_dir.join(format!("{}{}-{}", TRASH_PREFIX, secs, name));
+    let mut suffix = 1;
+    while fs.exists(&destination) {
+        destination = trash_dir.join(format!("{}{}-{}.{}", TRASH_PREFIX, secs, name, suffix));
+        suffix += 1;
+    }
+    destination
+}
+
+pub(crate) fn move_to_trash(
+    fs: &dyn FileSystem,
+    folder: &Path,
+    trash_dir: &Path,
+) -> std::io::Result<std::path::PathBuf> {
+    fs.create_dir_all(trash_dir)?;
+    let destination = trash_destination(fs, trash_dir, folder);
+    move_path(fs, folder, &destination)?;
+    Ok(destination)
+}
+
+/// Moves the contents of `folder` into a new trash entry named like
+/// `move_to_trash` would name the folder itself, leaving `folder` empty.
+fn move_contents_to_trash(
+    fs: &dyn FileSystem,
+    folder: &Path,
+    trash_dir: &Path,
+) -> std::io::Result<std::path::PathBuf> {
+    let destination = trash_destination(fs, trash_dir, folder);
+    fs.create_dir_all(&destination)?;
+    for entry in fs.read_dir(folder)? {
+        let Some(name) = entry.file_name() else {
+            continue;
+        };
+        move_path(fs, &entry, &destination.join(name))?;
+    }
+    Ok(destination)
+}
+
+/// Renames `from` to `to`, copying and removing it instead when they are on
+/// different filesystems.
+fn move_path(fs: &dyn FileSystem, from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs.rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            copy_and_remove(fs, from, to)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes a folder with everything below it, or a single file.
+fn remove_path(fs: &dyn FileSystem, path: &Path) -> std::io::Result<()> {
+    if fs.symlink_metadata(path)?.is_dir() {
+        fs.remove_dir_all(path)
+    } else {
+        fs.remove_file(path)
+    }
+}
+
+/// Removes everything inside `dir` but keeps `dir` itself.
+fn empty_dir(fs: &dyn FileSystem, dir: &Path) -> std::io::Result<()> {
+    for entry in fs.read_dir(dir)? {
+        match remove_path(fs, &entry) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// Returns when a trash entry was moved there, from the
+/// `.trashed-<unix-seconds>-` prefix `trash_destination` gives it and its
+/// `.meta.json` sidecar shares.
+fn trashed_at(name: &str) -> Option<SystemTime> {
+    let (secs, _) = name.strip_prefix(TRASH_PREFIX)?.split_once('-')?;
+    if secs.is_empty() || !secs.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let secs: u64 = secs.parse().ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Deletes everything in `TRASH_DIR` that was moved there longer than
+/// `TRASH_RETENTION_DAYS` ago. Entries without the `.trashed-` prefix were not
+/// put there by cleanup and are left alone.
+pub(crate) fn purge_trash(config: &Config, fs: &dyn FileSystem) -> std::io::Result<()> {
+    let (Some(trash_dir), Some(retention)) = (&config.trash_dir, config.trash_retention) else {
+        return Ok(());
+    };
+    let entries = match fs.read_dir(Path::new(trash_dir)) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        result => result?,
+    };
+
+    for path in entries {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let Some(trashed_at) = trashed_at(&name) else {
+            continue;
+        };
+        if clock::age(trashed_at) < retention {
+            continue;
+        }
+
+        let path_str = path.to_string_lossy();
+        let Some(size) = skip_entry(config, &path, path_size(fs, &path))? else {
+            continue;
+        };
+        let message = if config.dry_run {
+            format!(
+                "[DRY-RUN] Would purge from trash: {} ({})",
+                path_str,
+                format_bytes(size)
+            )
+        } else {
+            if skip_entry(
+                config,
+                &path,
+                with_retries(config, &path_str, || remove_path(fs, &path)),
+            )?
+            .is_none()
+            {
+                continue;
+            }
+            format!("Purged from trash: {} ({})", path_str, format_bytes(size))
+        };
+        log_event(
+            &config.log_path,
+            &LogEvent::new(LogLevel::Info, "purge", message)
+                .path(&path_str)
+                .freed_bytes(size),
+        )?;
+    }
+    Ok(())
+}
+
+/// Longest stretch without a progress line while removing a large folder.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Files removed so far by `remove_with_progress`.
+struct Progress<'a> {
+    config: &'a Config,
+    fs: &'a dyn FileSystem,
+    root: &'a Path,
+    every: u64,
+    removed: u64,
+    last_report: Instant,
+}
+
+impl Progress<'_> {
+    fn file_removed(&mut self) -> std::io::Result<()> {
+        self.removed += 1;
+        if self.removed.is_multiple_of(self.every)
+            || self.last_report.elapsed() >= PROGRESS_INTERVAL
+        {
+            self.last_report = Instant::now();
+            log_message(
+                &self.config.log_path,
+                LogLevel::Info,
+                &format!(
+                    "Removing {}: {} files removed so far",
+                    self.root.to_string_lossy(),
+                    self.removed
+                ),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn ignore_missing(result: std::io::Result<()>) -> std::io::Result<()> {
+    match result {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Removes `dir` depth-first one entry at a time so progress can be
+/// reported. Entries that vanish meanwhile are not an error.
+fn remove_tree(dir: &Path, progress: &mut Progress) -> std::io::Result<()> {
+    remove_contents(dir, progress)?;
+    ignore_missing(progress.fs.remove_dir(dir))
+}
+
+/// Removes everything inside `dir` like `remove_tree`, but keeps `dir`.
+fn remove_contents(dir: &Path, progress: &mut Progress) -> std::io::Result<()> {
+    let fs = progress.fs;
+    let entries = match fs.read_dir(dir) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        result => result?,
+    };
+    for entry in entries {
+        let is_dir = match fs.symlink_metadata(&entry) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            result => result?.is_dir(),
+        };
+        if is_dir {
+            remove_tree(&entry, progress)?;
+        } else {
+            ignore_missing(fs.remove_file(&entry))?;
+            progress.file_removed()?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes `path` like `remove_path`, or only its contents with
+/// `EMPTY_INSTEAD_OF_DELETE`. Folders of at least `PROGRESS_MIN_BYTES` are
+/// removed incrementally with a progress line every `PROGRESS_EVERY` files
+/// (and at least every few seconds) when progress reporting is enabled;
+/// everything else takes the single-call fast path.
+fn remove_entry(
+    config: &Config,
+    fs: &dyn FileSystem,
+    path: &Path,
+    size: u64,
+) -> std::io::Result<()> {
+    let is_dir = fs.metadata(path).is_ok_and(|metadata| metadata.is_dir());
+    let keep_root = config.empty_instead_of_delete && is_dir;
+    let every = match config.progress_every {
+        Some(every) if size >= config.progress_min_bytes && is_dir => every,
+        _ if keep_root => return empty_dir(fs, path),
+        _ => return remove_path(fs, path),
+    };
+
+    let mut progress = Progress {
+        config,
+        fs,
+        root: path,
+        every,
+        removed: 0,
+        last_report: Instant::now(),
+    };
+    if keep_root {
+        remove_contents(path, &mut progress)?;
+    } else {
+        remove_tree(path, &mut progress)?;
+    }
+    log_message(
+        &config.log_path,
+        LogLevel::Info,
+        &format!(
+            "Removed {}: {} files in total",
+            path.to_string_lossy(),
+            progress.removed
+        ),
+    )
+}
+
+/// Errors that network filesystems report while a file is briefly in use and
+/// that are worth retrying. `NotFound` and `PermissionDenied` are not among
+/// them: waiting will not change either.
+fn is_transient(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        err.kind(),
+        ErrorKind::ResourceBusy
+            | ErrorKind::ExecutableFileBusy
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+    )
+}
+
+/// Errors a process holding `path` open can cause, the only ones worth the
+/// `/proc` walk that names such processes.
+fn may_be_held_open(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        err.kind(),
+        ErrorKind::PermissionDenied | ErrorKind::ResourceBusy | ErrorKind::ExecutableFileBusy
+    )
+}
+
+/// Runs `operation` on `path`, retrying transient failures up to
+/// `DELETE_RETRIES` times with exponential backoff starting at
+/// `DELETE_BACKOFF_MS`.
+pub(crate) fn with_retries<T>(
+    config: &Config,
+    path: &str,
+    mut operation: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let mut backoff = config.delete_backoff;
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(err) if is_transient(&err) && attempt < config.delete_retries => {
+                attempt += 1;
+                log_message(
+                    &config.log_path,
+                    LogLevel::Warn,
+                    &format!(
+                        "Removing {} failed: {}; retrying in {}ms ({}/{})",
+                        path,
+                        err,
+                        backoff.as_millis(),
+                        attempt,
+                        config.delete_retries
+                    ),
+                )?;
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(err) => {
+                if attempt > 0 {
+                    log_message(
+                        &config.log_path,
+                        LogLevel::Error,
+                        &format!(
+                            "Giving up on {} after {} attempts: {}",
+                            path,
+                            attempt + 1,
+                            err
+                        ),
+                    )?;
+                }
+                if may_be_held_open(&err) {
+                    let holders = open_handles(Path::new(path));
+                    if !holders.is_empty() {
+                        log_message(
+                            &config.log_path,
+                            LogLevel::Warn,
+                            &format!("{} is held open by {}", path, format_holders(&holders)),
+                        )?;
+                    }
+                }
+                return Err(err);
+            }
+            Ok(value) => return Ok(value),
+        }
+    }
+}
+
+/// Processes with a file descriptor or working directory at or below `path`,
+/// as `(pid, name)` pairs sorted by pid. Best effort: processes that exit or
+/// cannot be inspected (other users' without privileges) are left out.
+#[cfg(target_os = "linux")]
+fn open_handles(path: &Path) -> Vec<(u32, String)> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let Ok(processes) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut holders = Vec::new();
+    for process in processes.flatten() {
+        let Some(pid) = process
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let proc_dir = process.path();
+        let fds = fs::read_dir(proc_dir.join("fd"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|fd| fd.path());
+        let holds = std::iter::once(proc_dir.join("cwd"))
+            .chain(fds)
+            .filter_map(|link| fs::read_link(link).ok())
+            .any(|target| target.starts_with(&path));
+        if holds {
+            let name = fs::read_to_string(proc_dir.join("comm"))
+                .map(|name| name.trim().to_string())
+                .unwrap_or_else(|_| "?".to_string());
+            holders.push((pid, name));
+        }
+    }
+    holders.sort();
+    holders
+}
+
+/// Only Linux exposes other processes' handles through the filesystem.
+#[cfg(not(target_os = "linux"))]
+fn open_handles(_path: &Path) -> Vec<(u32, String)> {
+    Vec::new()
+}
+
+/// Formats `holders` as `recorder (pid 812), sh (pid 901)`.
+fn format_holders(holders: &[(u32, String)]) -> String {
+    holders
+        .iter()
+        .map(|(pid, name)| format!("{} (pid {})", name, pid))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Writes `<item>.meta.json` next to an archive or trash entry, recording
+/// where it came from, its timestamps and size, so it can be understood and
+/// restored later. `TRASH_RETENTION_DAYS` purges it with its entry, as both
+/// names carry the same timestamp prefix. A sidecar that cannot be written
+/// is only logged: the item itself is already safely moved.
+fn write_sidecar(
+    config: &Config,
+    fs: &dyn FileSystem,
+    item: &Path,
+    original: &str,
+    kind: &str,
+    metadata: Option<&Metadata>,
+    size: u64,
+) -> std::io::Result<()> {
+    let original =
+        std::path::absolute(original).unwrap_or_else(|_| Path::new(original).to_path_buf());
+    let timestamp = |time: Option<SystemTime>| {
+        time.map(|time| json_escape(&format_timestamp(time)))
+            .unwrap_or_else(|| "null".to_string())
+    };
+    let json = format!(
+        "{{\"original_path\":{},\"kind\":{},\"created\":{},\"modified\":{},\"bytes\":{},\"moved_at\":{}}}\n",
+        json_escape(&original.to_string_lossy()),
+        json_escape(kind),
+        timestamp(metadata.and_then(|metadata| metadata.created().ok())),
+        timestamp(metadata.and_then(|metadata| metadata.modified().ok())),
+        size,
+        timestamp(Some(clock::now()))
+    );
+    let mut sidecar = item.as_os_str().to_os_string();
+    sidecar.push(".meta.json");
+    if let Err(err) = fs.write(Path::new(&sidecar), json.as_bytes()) {
+        log_message(
+            &config.log_path,
+            LogLevel::Warn,
+            &format!(
+                "Could not write {}: {}",
+                Path::new(&sidecar).to_string_lossy(),
+                err
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// Deletes, archives or trashes the folder or file at `path` and returns the
+/// bytes it held.
+pub(crate) fn delete_entry(
+    path: &str,
+    config: &Config,
+    fs: &dyn FileSystem,
+    policy: &str,
+) -> std::io::Result<u64> {
+    let log_path = config.log_path.as_str();
+    let mut span = span("delete", || vec![("path", path.to_string())]);
+    let size = path_size(fs, Path::new(path))?;
+    let is_dir = fs
+        .metadata(Path::new(path))
+        .is_ok_and(|metadata| metadata.is_dir());
+    let emptying = config.empty_instead_of_delete && is_dir;
+    let kind = if emptying {
+        "folder contents"
+    } else if is_dir {
+        "folder"
+    } else {
+        "file"
+    };
+    span.record("kind", kind);
+    // Emptying touches the folder's modification time; it is put back so
+    // the folder keeps its place in the timestamp order downstream. Without
+    // a recorded access time there is none to keep, and the modification
+    // time stands in for it.
+    let times = match fs.metadata(Path::new(path)) {
+        Ok(metadata) if emptying => metadata
+            .modified()
+            .ok()
+            .map(|modified| (metadata.accessed().unwrap_or(modified), modified)),
+        _ => None,
+    };
+    let metadata = fs.symlink_metadata(Path::new(path)).ok();
+    let age = metadata
+        .as_ref()
+        .and_then(|metadata| folder_timestamp(metadata, config.sort_by).ok())
+        .map(clock::age);
+    let mut action = "delete";
+    if config.dry_run {
+        let message = format!(
+            "[DRY-RUN] Would delete {}: {} ({})",
+            kind,
+            path,
+            format_bytes(size)
+        );
+        log_event(
+            log_path,
+            &LogEvent::new(LogLevel::Info, "dry_run_delete", message)
+                .path(path)
+                .freed_bytes(size),
+        )?;
+    } else if let Some(archive_dir) = &config.archive_dir {
+        // Only remove the original once the archive is safely written.
+        let (archive, archive_size) = archive_folder(fs, Path::new(path), Path::new(archive_dir))?;
+        write_sidecar(config, fs, &archive, path, kind, metadata.as_ref(), size)?;
+        let message = format!(
+            "Archived {}: {} -> {} ({}, compressed {})",
+            kind,
+            path,
+            archive.to_string_lossy(),
+            format_bytes(size),
+            format_bytes(archive_size)
+        );
+        log_event(
+            log_path,
+            &LogEvent::new(LogLevel::Info, "archive", message)
+                .path(path)
+                .freed_bytes(size),
+        )?;
+        with_retries(config, path, || {
+            remove_entry(config, fs, Path::new(path), size)
+        })?;
+        action = "archive";
+    } else if let Some(trash_dir) = &config.trash_dir {
+        let destination = with_retries(config, path, || {
+            if emptying {
+                move_contents_to_trash(fs, Path::new(path), Path::new(trash_dir))
+            } else {
+                move_to_trash(fs, Path::new(path), Path::new(trash_dir))
+            }
+        })?;
+        write_sidecar(
+            config,
+            fs,
+            &destination,
+            path,
+            kind,
+            metadata.as_ref(),
+            size,
+        )?;
+        let message = format!(
+            "Moved {} to trash: {} -> {} ({})",
+            kind,
+            path,
+            destination.to_string_lossy(),
+            format_bytes(size)
+        );
+        log_event(
+            log_path,
+            &LogEvent::new(LogLevel::Info, "trash", message)
+                .path(path)
+                .freed_bytes(size),
+        )?;
+        action = "trash";
+    } else {
+        let message = format!("Deleting {}: {} ({})", kind, path, format_bytes(size));
+        log_event(
+            log_path,
+            &LogEvent::new(LogLevel::Info, "delete", message)
+                .path(path)
+                .freed_bytes(size),
+        )?;
+        with_retries(config, path, || {
+            remove_entry(config, fs, Path::new(path), size)
+        })?;
+    }
+    if let Some((accessed, modified)) = times.filter(|_| !config.dry_run) {
+        if let Err(err) = fs.set_times(Path::new(path), accessed, modified) {
+            log_message(
+                log_path,
+                LogLevel::Debug,
+                &format!("Could not restore the timestamps of {}: {}", path, err),
+            )?;
+        }
+    }
+    if !config.dry_run {
+        audit_deletion(
+            config,
+            &AuditRecord {
+                path,
+                kind,
+                action,
+                bytes: size,
+                age,
+                policy,
+            },
+        )?;
+    }
+    span.record("bytes_freed", size);
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::filesystem::RealFileSystem;
+    use crate::test_support::{MemoryFileSystem, TempDir};
+    use std::cell::Cell;
+    use std::io::Read;
+    use std::rc::Rc;
+
+    #[test]
+    fn only_busy_or_interrupted_errors_are_transient() {
+        use std::io::{Error, ErrorKind};
+        for kind in [
+            ErrorKind::ResourceBusy,
+            ErrorKind::ExecutableFileBusy,
+            ErrorKind::TimedOut,
+        ] {
+            assert!(is_transient(&Error::from(kind)), "{:?}", kind);
+        }
+        for kind in [
+            ErrorKind::NotFound,
+            ErrorKind::PermissionDenied,
+            ErrorKind::StorageFull,
+        ] {
+            assert!(!is_transient(&Error::from(kind)), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn only_denied_or_busy_errors_look_for_open_handles() {
+        use std::io::{Error, ErrorKind};
+        for kind in [
+            ErrorKind::PermissionDenied,
+            ErrorKind::ResourceBusy,
+            ErrorKind::ExecutableFileBusy,
+        ] {
+            assert!(may_be_held_open(&Error::from(kind)), "{:?}", kind);
+        }
+        for kind in [
+            ErrorKind::NotFound,
+            ErrorKind::IsADirectory,
+            ErrorKind::StorageFull,
+            ErrorKind::CrossesDevices,
+        ] {
+            assert!(!may_be_held_open(&Error::from(kind)), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn format_holders_lists_each_process_with_its_pid() {
+        assert_eq!(format_holders(&[]), "");
+        assert_eq!(
+            format_holders(&[(812, "recorder".to_string())]),
+            "recorder (pid 812)"
+        );
+        assert_eq!(
+            format_holders(&[
+                (812, "recorder".to_string()),
+                (901, "sh".to_string()),
+                (1204, "?".to_string()),
+            ]),
+            "recorder (pid 812), sh (pid 901), ? (pid 1204)"
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn open_handles_finds_this_process_holding_a_file() {
+        let dir = TempDir::new("open-handles");
+        let held = dir.path().join("held");
+        let _file = fs::File::create(&held).unwrap();
+
+        let holders = open_handles(dir.path());
+
+        assert!(
+            holders.iter().any(|(pid, _)| *pid == std::process::id()),
+            "{:?}",
+            holders
+        );
+        assert_eq!(open_handles(&dir.path().join("missing")), []);
+    }
+
+    /// An operation that fails with each of `errors` in turn, then succeeds,
+    /// and counts its attempts.
+    fn flaky(
+        errors: Vec<std::io::ErrorKind>,
+    ) -> (impl FnMut() -> std::io::Result<()>, Rc<Cell<u32>>) {
+        let attempts = Rc::new(Cell::new(0));
+        let counted = Rc::clone(&attempts);
+        let operation = move || {
+            let attempt = counted.get();
+            counted.set(attempt + 1);
+            match errors.get(attempt as usize) {
+                Some(kind) => Err(std::io::Error::from(*kind)),
+                None => Ok(()),
+            }
+        };
+        (operation, attempts)
+    }
+
+    #[test]
+    fn with_retries_retries_transient_failures_until_one_succeeds() {
+        let dir = TempDir::new("retries");
+        let config = Config::for_test(dir.path(), "delete_retries = 3\ndelete_backoff_ms = 1");
+        let busy = std::io::ErrorKind::ResourceBusy;
+
+        let (operation, attempts) = flaky(vec![busy, busy]);
+        with_retries(&config, "/data/run", operation).unwrap();
+        assert_eq!(attempts.get(), 3);
+
+        let (operation, attempts) = flaky(vec![busy; 4]);
+        let err = with_retries(&config, "/data/run", operation).unwrap_err();
+        assert_eq!(err.kind(), busy);
+        assert_eq!(attempts.get(), 4);
+    }
+
+    #[test]
+    fn with_retries_gives_up_at_once_on_permanent_failures() {
+        let dir = TempDir::new("no-retries");
+        let config = Config::for_test(dir.path(), "delete_retries = 3\ndelete_backoff_ms = 1");
+
+        let (operation, attempts) = flaky(vec![std::io::ErrorKind::NotFound]);
+        let err = with_retries(&config, "/data/run", operation).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn purge_trash_removes_entries_once_retention_has_passed() {
+        let trashed = 1_700_000_000;
+        let trashed_at = SystemTime::UNIX_EPOCH + Duration::from_secs(trashed);
+        let retention = Duration::from_secs(7 * 24 * 60 * 60);
+        let clock = TestClock::install(trashed_at + retention - Duration::from_secs(60));
+        let dir = TempDir::new("purge-trash");
+        let trash = dir.dir("trash");
+        fs::write(trash.join(format!(".trashed-{}-run", trashed)), b"trashed").unwrap();
+        fs::write(
+            trash.join(format!(".trashed-{}-run.meta.json", trashed)),
+            b"{}",
+        )
+        .unwrap();
+        fs::create_dir(trash.join(format!(".trashed-{}-later", trashed + 3600))).unwrap();
+        fs::write(trash.join("not-from-cleanup"), b"unknown").unwrap();
+        // A user's own file that merely starts with digits.
+        fs::write(trash.join(format!("{}_clip", trashed)), b"unknown").unwrap();
+        let config = Config::for_test(
+            dir.path(),
+            &format!(
+                "trash_dir = {:?}\ntrash_retention_days = 7",
+                trash.to_string_lossy()
+            ),
+        );
+        let left = || {
+            let mut names: Vec<_> = fs::read_dir(&trash)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        purge_trash(&config, &RealFileSystem).unwrap();
+        assert_eq!(left().len(), 5);
+
+        clock.advance(Duration::from_secs(120));
+        purge_trash(&config, &RealFileSystem).unwrap();
+        assert_eq!(
+            left(),
+            [
+                format!(".trashed-{}-later", trashed + 3600),
+                format!("{}_clip", trashed),
+                "not-from-cleanup".to_string()
+            ]
+        );
+
+        clock.advance(Duration::from_secs(3600));
+        purge_trash(&config, &RealFileSystem).unwrap();
+        assert_eq!(
+            left(),
+            [format!("{}_clip", trashed), "not-from-cleanup".to_string()]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deleting_a_folder_removes_links_but_not_their_targets() {
+        let dir = TempDir::new("delete-symlinks");
+        let outside = dir.dir("outside");
+        fs::write(outside.join("precious"), b"keep").unwrap();
+        let folder = dir.dir("base/a/sub/run");
+        fs::write(folder.join("data"), b"gone").unwrap();
+        std::os::unix::fs::symlink(&outside, folder.join("to_dir")).unwrap();
+        std::os::unix::fs::symlink(outside.join("precious"), folder.join("to_file")).unwrap();
+        let config = Config::for_test(dir.path(), "");
+
+        let freed = delete_entry(&folder.to_string_lossy(), &config, &RealFileSystem, "test");
+
+        // The links count by their own size, not their targets'.
+        assert!(freed.unwrap() < 1024);
+        assert!(!folder.exists());
+        assert_eq!(fs::read(outside.join("precious")).unwrap(), b"keep");
+    }
+
+    #[test]
+    fn trashed_and_archived_folders_get_a_sidecar() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("sidecar");
+        let tree = MemoryFileSystem::new();
+        let created = SystemTime::UNIX_EPOCH + Duration::from_secs(1_690_000_000);
+        for name in ["a", "b"] {
+            let folder = Path::new("/srv/cam1").join(name);
+            tree.file(&folder.join("video"), 300, now, 2);
+            tree.file(&folder.join("index"), 20, now, 2);
+            tree.set_age(&folder, now, 3);
+            tree.set_created(&folder, created);
+        }
+        let sidecar = |settings: &str, name: &str, store: &str| {
+            let config = Config::for_test(dir.path(), settings);
+            let freed = delete_entry(&format!("/srv/cam1/{}", name), &config, &tree, "oldest");
+            assert_eq!(freed.unwrap(), 320);
+            let sidecars: Vec<String> = tree
+                .names(Path::new(store))
+                .into_iter()
+                .filter(|name| name.ends_with(".meta.json"))
+                .collect();
+            assert_eq!(sidecars.len(), 1, "{:?}", sidecars);
+            let mut contents = String::new();
+            tree.open(&Path::new(store).join(&sidecars[0]))
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+
+        let trashed = sidecar("trash_dir = \"/srv/trash\"", "a", "/srv/trash");
+        let archived = sidecar("archive_dir = \"/srv/archive\"", "b", "/srv/archive");
+
+        let expected = |name: &str| {
+            format!(
+                "{{\"original_path\":\"/srv/cam1/{}\",\"kind\":\"folder\",\
+                 \"created\":\"2023-07-22T04:26:40Z\",\"modified\":\"2023-11-11T22:13:20Z\",\
+                 \"bytes\":320,\"moved_at\":\"2023-11-14T22:13:20Z\"}}\n",
+                name
+            )
+        };
+        assert_eq!(trashed, expected("a"));
+        assert_eq!(archived, expected("b"));
+        assert_eq!(tree.names(Path::new("/srv/cam1")), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_tree_recreates_symlinks_instead_of_following_them() {
+        let dir = TempDir::new("copy-symlinks");
+        let outside = dir.dir("outside");
+        fs::write(outside.join("big"), vec![7; 4096]).unwrap();
+        let from = dir.dir("from");
+        fs::write(from.join("file"), b"data").unwrap();
+        std::os::unix::fs::symlink(outside.join("big"), from.join("to_file")).unwrap();
+        std::os::unix::fs::symlink(&outside, from.join("to_dir")).unwrap();
+
+        let to = dir.path().join("to");
+        copy_tree(&RealFileSystem, &from, &to).unwrap();
+
+        assert_eq!(fs::read(to.join("file")).unwrap(), b"data");
+        for link in ["to_file", "to_dir"] {
+            assert!(fs::symlink_metadata(to.join(link))
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!(
+                fs::read_link(to.join(link)).unwrap(),
+                fs::read_link(from.join(link)).unwrap()
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_copy_removes_the_partial_destination() {
+        let dir = TempDir::new("copy-partial");
+        let from = dir.dir("from");
+        fs::write(from.join("a"), b"copied first").unwrap();
+        // A socket cannot be copied by content, so the copy fails after
+        // starting on the destination.
+        let _socket = std::os::unix::net::UnixListener::bind(from.join("socket")).unwrap();
+
+        let to = dir.path().join("to");
+        assert!(copy_and_remove(&RealFileSystem, &from, &to).is_err());
+        assert!(fs::symlink_metadata(&to).is_err());
+        assert_eq!(fs::read(from.join("a")).unwrap(), b"copied first");
+    }
+}
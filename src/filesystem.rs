@@ -0,0 +1,280 @@
+//! The filesystem operations scanning, sizing and deleting are built on.
+//! Everything cleanup does to the tree under a base directory goes through
+//! [`FileSystem`], so it can run against an in-memory tree as well as the
+//! real one behind [`RealFileSystem`]. Two lookups stay on the real
+//! filesystem: expanding `DIRPATH` patterns while the config loads, and the
+//! `/proc` walk that names the processes holding a folder open.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What kind of entry a [`Metadata`] describes. Symlinks are only reported
+/// by [`FileSystem::symlink_metadata`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// Sockets, FIFOs and devices.
+    Other,
+}
+
+/// The parts of an entry's metadata cleanup looks at. Timestamps a
+/// filesystem does not record are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: EntryKind,
+    /// Size in bytes; for a directory, whatever the filesystem reports.
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+    pub created: Option<SystemTime>,
+    /// Id of the filesystem holding the entry, compared by `STAY_ON_DEVICE`.
+    /// `None` where it is unknown, which never counts as another device.
+    pub device: Option<u64>,
+    /// Unix permission bits, recorded in archives.
+    pub mode: Option<u32>,
+}
+
+impl Metadata {
+    pub fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Dir
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == EntryKind::Symlink
+    }
+
+    /// Like `std::fs::Metadata::modified`: an `Unsupported` error when the
+    /// time is not recorded.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        recorded(self.modified, "modification")
+    }
+
+    pub fn accessed(&self) -> io::Result<SystemTime> {
+        recorded(self.accessed, "access")
+    }
+
+    pub fn created(&self) -> io::Result<SystemTime> {
+        recorded(self.created, "creation")
+    }
+}
+
+fn recorded(time: Option<SystemTime>, name: &str) -> io::Result<SystemTime> {
+    time.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} time is not available on this filesystem", name),
+        )
+    })
+}
+
+impl From<fs::Metadata> for Metadata {
+    fn from(metadata: fs::Metadata) -> Metadata {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        };
+        Metadata {
+            kind,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+            created: metadata.created().ok(),
+            device: device(&metadata),
+            mode: mode(&metadata),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn device(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+/// Windows exposes no stable device id through `std`.
+#[cfg(not(unix))]
+fn device(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// The filesystem a [`Cleaner`](crate::Cleaner) scans and deletes in. Each
+/// method behaves like its `std::fs` namesake, including the error kinds it
+/// returns, which cleanup relies on: `NotFound` for a vanished entry,
+/// `CrossesDevices` for a rename that needs a copy.
+pub trait FileSystem: Send + Sync {
+    /// Lists the entries of `path` as full paths, in no particular order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Metadata of `path`, following symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Metadata of `path` itself, without following a symlink.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>>;
+
+    /// Creates or truncates the file at `path` for writing.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>>;
+
+    /// Flushes what was written to the file at `path` to stable storage.
+    fn sync(&self, path: &Path) -> io::Result<()>;
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64>;
+
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Creates a symlink at `link` pointing to `target`.
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()>;
+
+    fn set_times(&self, path: &Path, accessed: SystemTime, modified: SystemTime) -> io::Result<()>;
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.create(path)?.write_all(contents)
+    }
+
+    /// Whether anything, even a dangling symlink, exists at `path`.
+    fn exists(&self, path: &Path) -> bool {
+        self.symlink_metadata(path).is_ok()
+    }
+}
+
+/// The operating system's filesystem, through `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path).map(Metadata::from)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(path).map(Metadata::from)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        Ok(Box::new(fs::File::create(path)?))
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        fs::File::open(path)?.sync_all()
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        fs::copy(from, to)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    /// Windows needs to know whether the link is to a folder; a target that
+    /// cannot be resolved is linked as a file.
+    #[cfg(windows)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        let resolved = link
+            .parent()
+            .map_or(target.to_path_buf(), |parent| parent.join(target));
+        if fs::metadata(resolved).is_ok_and(|metadata| metadata.is_dir()) {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn symlink(&self, _target: &Path, link: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Cannot create symlink {}", link.to_string_lossy()),
+        ))
+    }
+
+    fn set_times(&self, path: &Path, accessed: SystemTime, modified: SystemTime) -> io::Result<()> {
+        let times = fs::FileTimes::new()
+            .set_accessed(accessed)
+            .set_modified(modified);
+        fs::File::open(path)?.set_times(times)
+    }
+}
@@ -0,0 +1,225 @@
+//! `PRE_HOOK` and `POST_HOOK`: shell commands run around each directory's
+//! cleanup, for stopping a writer first or notifying a service afterwards,
+//! and `ON_FAILURE_HOOK` for escalating a disk cleanup could not free.
+
+use std::process::Command;
+
+use crate::cleanup::CleanupStats;
+use crate::config::Config;
+use crate::logging::{log_message, LogLevel};
+
+/// What a hook is told about the cleanup it surrounds.
+pub(crate) struct HookContext<'a> {
+    /// `pre`, `post` or `failure`.
+    pub(crate) phase: &'a str,
+    pub(crate) dir: &'a str,
+    pub(crate) free_percent: Option<f64>,
+    /// The finished cleanup's totals, or `None` when it failed; post- and
+    /// failure-hooks only.
+    pub(crate) result: Option<&'a CleanupStats>,
+}
+
+/// Runs `command` through the platform shell with the context in
+/// `CLEANUP_*` environment variables, logging its output. Fails when the
+/// command cannot be started or exits with a non-zero status.
+pub(crate) fn run_hook(
+    config: &Config,
+    command: &str,
+    context: &HookContext,
+) -> std::io::Result<()> {
+    let mut process = if cfg!(windows) {
+        let mut process = Command::new("cmd");
+        process.arg("/C").arg(command);
+        process
+    } else {
+        let mut process = Command::new("sh");
+        process.arg("-c").arg(command);
+        process
+    };
+    process
+        .env("CLEANUP_PHASE", context.phase)
+        .env("CLEANUP_BASE_DIR", context.dir)
+        .env("CLEANUP_DRY_RUN", config.dry_run.to_string())
+        .env(
+            "CLEANUP_FREE_PERCENT",
+            context
+                .free_percent
+                .map(|percentage| format!("{:.2}", percentage))
+                .unwrap_or_default(),
+        );
+    if context.phase == "post" {
+        process.env(
+            "CLEANUP_RESULT",
+            if context.result.is_some() {
+                "success"
+            } else {
+                "error"
+            },
+        );
+    }
+    if context.phase != "pre" {
+        let stats = context.result.cloned().unwrap_or_default();
+        process
+            .env("CLEANUP_FREED_BYTES", stats.bytes_freed.to_string())
+            .env("CLEANUP_FOLDERS_DELETED", stats.folders_deleted.to_string());
+    }
+
+    log_message(
+        &config.log_path,
+        LogLevel::Info,
+        &format!(
+            "[{}] Running {}-hook: {}",
+            context.dir, context.phase, command
+        ),
+    )?;
+    let output = process.output()?;
+    for line in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stderr).lines())
+    {
+        log_message(
+            &config.log_path,
+            LogLevel::Debug,
+            &format!("[{}] {}-hook: {}", context.dir, context.phase, line),
+        )?;
+    }
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(std::io::Error::other(format!(
+        "{}-hook `{}` failed with {}",
+        context.phase, command, output.status
+    )))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::{Duration, SystemTime};
+
+    use crate::clock::TestClock;
+    use crate::test_support::{MemoryDisk, MemoryFileSystem, TempDir};
+    use crate::{Cleaner, Outcome};
+
+    /// A cleaner for `dir`'s base directory holding three 100-byte
+    /// recordings, on a 2000-byte disk left 17.5% free.
+    fn cleaner(dir: &TempDir, now: SystemTime, settings: &str) -> (Cleaner, MemoryFileSystem) {
+        let tree = MemoryFileSystem::new();
+        let cam1 = dir.path().join("base/cam1");
+        for (recording, days_old) in [("a", 30), ("b", 20), ("c", 10)] {
+            tree.file(&cam1.join(recording).join("video"), 100, now, days_old);
+            tree.set_age(&cam1.join(recording), now, days_old);
+        }
+        let disks = MemoryDisk {
+            fs: tree.clone(),
+            total: 2000,
+            reserved: 1350,
+        };
+        let config = Config::for_test(dir.path(), &format!("depth = 1\n{}", settings));
+        let cleaner = Cleaner::with_backends(config, Box::new(disks), Box::new(tree.clone()));
+        (cleaner, tree)
+    }
+
+    fn hook_writing(out: &Path) -> String {
+        format!(
+            "echo \"$CLEANUP_PHASE ${{CLEANUP_FREED_BYTES:-none}} $CLEANUP_DRY_RUN\" >> '{}'",
+            out.to_string_lossy()
+        )
+    }
+
+    #[test]
+    fn hooks_see_the_cleanup_in_their_environment() {
+        let dir = TempDir::new("hook-env");
+        let out = dir.path().join("hook.out");
+        let config = Config::for_test(dir.path(), "");
+        let stats = CleanupStats {
+            folders_deleted: 2,
+            bytes_freed: 4096,
+            ..CleanupStats::default()
+        };
+
+        for (phase, result) in [
+            ("pre", None),
+            ("post", Some(&stats)),
+            ("failure", Some(&stats)),
+        ] {
+            let context = HookContext {
+                phase,
+                dir: "/data",
+                free_percent: Some(12.5),
+                result,
+            };
+            run_hook(&config, &hook_writing(&out), &context).unwrap();
+        }
+
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "pre none false\npost 4096 false\nfailure 4096 false\n"
+        );
+    }
+
+    #[test]
+    fn failing_hooks_report_their_exit_status() {
+        let dir = TempDir::new("hook-fails");
+        let config = Config::for_test(dir.path(), "");
+        let context = HookContext {
+            phase: "pre",
+            dir: "/data",
+            free_percent: None,
+            result: None,
+        };
+
+        let err = run_hook(&config, "exit 3", &context).unwrap_err();
+        assert!(
+            err.to_string().starts_with("pre-hook `exit 3` failed with"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn a_failing_pre_hook_skips_the_directory() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("hook-pre-fails");
+        let out = dir.path().join("hook.out");
+        let (cleaner, tree) = cleaner(
+            &dir,
+            now,
+            &format!(
+                "pre_hook = \"exit 1\"\npost_hook = {:?}",
+                hook_writing(&out)
+            ),
+        );
+
+        let err = cleaner.run().unwrap_err();
+
+        assert_eq!(err.to_string(), "Cleanup failed for 1 of 1 directories");
+        assert_eq!(tree.names(&dir.path().join("base/cam1")), ["a", "b", "c"]);
+        assert!(!out.exists(), "the post-hook does not run either");
+    }
+
+    #[test]
+    fn a_failing_post_hook_is_only_a_warning() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("hook-post-fails");
+        let out = dir.path().join("hook.out");
+        let (cleaner, tree) = cleaner(
+            &dir,
+            now,
+            &format!(
+                "pre_hook = {:?}\npost_hook = \"exit 1\"",
+                hook_writing(&out)
+            ),
+        );
+
+        assert_eq!(cleaner.run().unwrap(), Outcome::CleanedSufficient);
+
+        assert_eq!(tree.names(&dir.path().join("base/cam1")), ["c"]);
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "pre none false\n");
+        let log = std::fs::read_to_string(dir.path().join("cleanup.log")).unwrap();
+        assert!(log.contains("post-hook `exit 1` failed with"), "{}", log);
+    }
+}
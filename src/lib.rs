@@ -0,0 +1,1162 @@
+//! Frees disk space by deleting the oldest (or largest) folders under one or
+//! more base directories once free space drops below a threshold.
+//!
+//! The binary is a thin wrapper around [`Cleaner`]; embed it directly to run
+//! the same cleanup from another program:
+//!
+//! ```no_run
+//! use util::{CliArgs, Cleaner, Config};
+//!
+//! let config = Config::load(CliArgs::default())?;
+//! let outcome = Cleaner::new(config).run()?;
+//! println!("{:?}", outcome);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Log records go to `cleanup.log` unless another [`Logger`] is installed,
+//! for instance one forwarding to the host program's logging:
+//!
+//! ```
+//! use util::{set_logger, Logger, Record};
+//!
+//! struct Stderr;
+//!
+//! impl Logger for Stderr {
+//!     fn log(&self, record: &Record) -> std::io::Result<()> {
+//!         eprintln!("[{:?}] {}", record.level, record.message);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! set_logger(Box::new(Stderr));
+//! ```
+//!
+//! A logger also sees a [`Span`] around each base directory, each
+//! `clean_disk` call and each deletion, with the directory, free space and
+//! bytes freed as fields. The library does not depend on `tracing`, but a
+//! logger can forward spans to it, so `tracing-subscriber` (or any other
+//! subscriber) sees cleanup nested inside the host service's traces:
+//!
+//! ```ignore
+//! use std::cell::RefCell;
+//! use std::time::Duration;
+//!
+//! thread_local! {
+//!     // Spans end in the reverse order they start on each thread.
+//!     static OPEN: RefCell<Vec<tracing::span::EnteredSpan>> = RefCell::new(Vec::new());
+//! }
+//!
+//! struct Tracing;
+//!
+//! impl util::Logger for Tracing {
+//!     fn log(&self, record: &util::Record) -> std::io::Result<()> {
+//!         tracing::info!(event = record.event, path = record.path, "{}", record.message);
+//!         Ok(())
+//!     }
+//!
+//!     fn enter(&self, span: &util::Span) {
+//!         let fields = format!("{:?}", span.fields);
+//!         let entered = tracing::info_span!("cleanup", name = span.name, fields).entered();
+//!         OPEN.with(|open| open.borrow_mut().push(entered));
+//!     }
+//!
+//!     fn exit(&self, span: &util::Span, elapsed: Duration) {
+//!         tracing::debug!(name = span.name, fields = ?span.fields, ?elapsed, "span finished");
+//!         OPEN.with(|open| open.borrow_mut().pop());
+//!     }
+//! }
+//!
+//! tracing_subscriber::fmt().init();
+//! util::set_logger(Box::new(Tracing));
+//! ```
+
+#[macro_use]
+extern crate lazy_static;
+
+mod archive;
+mod audit;
+mod check;
+mod cleanup;
+mod clock;
+mod config;
+mod config_file;
+mod dedup;
+mod delete;
+#[cfg(feature = "email")]
+mod email;
+mod filesystem;
+mod hook;
+mod lock;
+mod logging;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod plan;
+mod regex;
+mod report;
+mod scan;
+mod signal;
+mod size;
+mod stats;
+mod storage;
+#[cfg(test)]
+mod test_support;
+#[cfg(feature = "tui")]
+mod tui;
+mod webhook;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+pub use check::Pressure;
+pub use cleanup::{CleanMode, CleanupStats, Depth, OnFailure, Outcome, SkipSummary};
+pub use clock::{set_clock, Clock, FixedClock, SystemClock};
+pub use config::{validate_config, CliArgs, Config, USAGE, VERSION};
+pub use filesystem::{EntryKind, FileSystem, Metadata, RealFileSystem};
+pub use lock::InstanceLock;
+pub use logging::{set_logger, LogFormat, LogLevel, Logger, Record, Span};
+pub use regex::Regex;
+pub use scan::{SortBy, Strategy, Target};
+pub use size::SizeUnits;
+pub use storage::{BackingDisk, DiskInfo, FreeSpace, TargetDisk, ThresholdMode};
+
+use cleanup::clean_disk;
+use delete::purge_trash;
+use hook::{run_hook, HookContext};
+use logging::{clean_log, log_message, set_log_settings, LogSettings};
+use report::{write_report, DirectoryReport, RunReport};
+use size::{folder_size, format_bytes, set_size_units};
+use storage::bytes_to_stop;
+use webhook::{send_webhook, CleanupNotification};
+
+/// Runs the free-space check and cleanup for every configured directory.
+pub struct Cleaner {
+    config: Config,
+    disks: Box<dyn DiskInfo>,
+    /// Everything below the base directories is read and deleted through
+    /// this.
+    fs: Box<dyn FileSystem>,
+    /// When each base directory last finished a cleanup that reached its
+    /// targets, for `COOLDOWN_SECONDS`.
+    last_cleanup: Mutex<BTreeMap<String, SystemTime>>,
+    /// This machine's hostname for webhooks and alerts, looked up once.
+    hostname: String,
+}
+
+impl Cleaner {
+    /// Creates a cleaner for the real disks and filesystem and applies its
+    /// logging options process-wide.
+    pub fn new(config: Config) -> Cleaner {
+        Cleaner::with_backends(
+            config,
+            Box::new(storage::Disks::new()),
+            Box::new(RealFileSystem),
+        )
+    }
+
+    /// Like [`Cleaner::new`], but free space comes from `disks` and the base
+    /// directories are scanned and cleaned through `fs`, for instance to run
+    /// cleanup against a simulated disk.
+    ///
+    /// ```no_run
+    /// use util::{CliArgs, Cleaner, Config, DiskInfo, RealFileSystem};
+    ///
+    /// /// A 1 TB disk that always reports 5% free.
+    /// struct AlmostFull;
+    ///
+    /// impl DiskInfo for AlmostFull {
+    ///     fn disk_space(&self, _config: &Config, _base_dir: &str) -> Option<(u64, u64)> {
+    ///         Some((1_000_000_000_000, 50_000_000_000))
+    ///     }
+    /// }
+    ///
+    /// let config = Config::load(CliArgs::default())?;
+    /// let cleaner = Cleaner::with_backends(config, Box::new(AlmostFull), Box::new(RealFileSystem));
+    /// cleaner.plan()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn with_backends(
+        config: Config,
+        disks: Box<dyn DiskInfo>,
+        fs: Box<dyn FileSystem>,
+    ) -> Cleaner {
+        set_log_settings(LogSettings {
+            format: config.log_format,
+            level: config.log_level,
+            max_bytes: config.log_max_bytes,
+            keep: config.log_keep,
+            file: config.log_file.clone(),
+            instance: config.instance_id.clone(),
+            log_instance: config.log_instance,
+        });
+        set_size_units(config.size_units);
+        Cleaner {
+            config,
+            disks,
+            fs,
+            last_cleanup: Mutex::new(BTreeMap::new()),
+            hostname: config::hostname().unwrap_or_default().to_string(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the free (not used) space of the disk holding `base_dir` as a
+    /// percentage of its capacity and in bytes, or `None` if no disk could be
+    /// matched. The thresholds are compared against this value.
+    pub fn check_storage(&self, base_dir: &str) -> Option<FreeSpace> {
+        self.disks
+            .check_storage(self.config.for_dir(base_dir), base_dir)
+    }
+
+    /// Returns the free space of every disk behind `base_dir` when it sits on
+    /// a union filesystem such as overlayfs or mergerfs, and nothing when it
+    /// is on a single disk. `TARGET_DISK` picks which of them
+    /// `check_storage` reports.
+    pub fn backing_disks(&self, base_dir: &str) -> Vec<BackingDisk> {
+        self.disks.backing_disks(base_dir)
+    }
+
+    /// Logs one line per base directory with its disk, total and available
+    /// bytes and free percentage, as a snapshot before anything is cleaned.
+    pub fn log_status_banner(&self) -> std::io::Result<()> {
+        // Writing the banner refreshes the log's modification time, so an
+        // expired log has to go first.
+        clean_log(&self.config.log_path, self.config.log_expiry)?;
+        for base_dir in &self.config.base_dirs {
+            let config = self.config.for_dir(base_dir);
+            let message = match self.disks.disk_space(config, base_dir) {
+                Some((total, available)) => format!(
+                    "[{}] {}: {} of {} available ({:.2}% free)",
+                    base_dir,
+                    self.disks
+                        .describe_disk(config, base_dir)
+                        .unwrap_or_else(|| "unknown disk".to_string()),
+                    format_bytes(available),
+                    format_bytes(total),
+                    storage::free_percentage(total, available).unwrap_or(100.0)
+                ),
+                None => format!("[{}] Disk not found for the base directory.", base_dir),
+            };
+            log_message(&self.config.log_path, LogLevel::Info, &message)?;
+            for disk in self.disks.backing_disks(base_dir) {
+                log_message(
+                    &self.config.log_path,
+                    LogLevel::Info,
+                    &format!(
+                        "[{}] Backed by {} at {}: {} free",
+                        base_dir,
+                        disk.name,
+                        disk.mount_point.to_string_lossy(),
+                        disk.free
+                    ),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the `LOCK_FILE` lock that keeps overlapping runs apart. Returns
+    /// `None`, after logging it, when another instance already holds it.
+    pub fn lock(&self) -> std::io::Result<Option<InstanceLock>> {
+        let lock = InstanceLock::acquire(&self.config.lock_file)?;
+        if lock.is_none() {
+            log_message(
+                &self.config.log_path,
+                LogLevel::Warn,
+                "Another instance is running; exiting",
+            )?;
+        }
+        Ok(lock)
+    }
+
+    /// Checks every base directory and cleans those below the start
+    /// threshold. A failure in one directory is logged and does not stop the
+    /// others; it is reported once all directories have been processed.
+    /// Otherwise the worst per-directory [`Outcome`] is returned.
+    pub fn run(&self) -> std::io::Result<Outcome> {
+        run(
+            &self.config,
+            self.disks.as_ref(),
+            self.fs.as_ref(),
+            &self.last_cleanup,
+            &self.hostname,
+        )
+    }
+
+    /// Same as [`Cleaner::run`] but only logs what would be deleted.
+    pub fn dry_run(&self) -> std::io::Result<Outcome> {
+        let config = Config {
+            dry_run: true,
+            ..self.config.clone()
+        };
+        run(
+            &config,
+            self.disks.as_ref(),
+            self.fs.as_ref(),
+            &self.last_cleanup,
+            &self.hostname,
+        )
+    }
+
+    /// Prints the folders a cleanup would delete, in order, with each one's
+    /// size and the projected free space after it. Nothing is deleted.
+    pub fn plan(&self) -> std::io::Result<()> {
+        plan::print_plan(&self.config, self.disks.as_ref(), self.fs.as_ref())
+    }
+
+    /// Logs every base directory's free space against its thresholds, prints
+    /// a one-line monitoring-plugin status and returns the worst
+    /// [`Pressure`] found. Nothing is deleted.
+    pub fn check(&self) -> std::io::Result<Pressure> {
+        check::check(&self.config, self.disks.as_ref())
+    }
+
+    /// Prints how the deletion candidates under each base directory are
+    /// spread across age buckets, with the count and total size of each.
+    /// Nothing is deleted.
+    pub fn stats(&self) -> std::io::Result<()> {
+        stats::print_stats(&self.config, self.fs.as_ref())
+    }
+
+    /// Shows the live dashboard until `q` is pressed: free space per base
+    /// directory, the latest deletions and the log, with `c` starting a
+    /// cleanup and `d` a dry run. Needs the `tui` feature and a terminal.
+    pub fn tui(&self) -> std::io::Result<()> {
+        #[cfg(feature = "tui")]
+        return tui::run(self);
+        #[cfg(not(feature = "tui"))]
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "--tui needs a build with the tui feature",
+        ))
+    }
+
+    /// Runs [`Cleaner::run`] every `interval` until SIGINT or SIGTERM
+    /// arrives. A signal received mid-cycle takes effect once the cycle
+    /// finishes, so no deletion is interrupted halfway. Failed cycles are
+    /// reported but do not stop the loop.
+    pub fn watch(&self, interval: Duration) -> std::io::Result<()> {
+        signal::install_handlers();
+        log_message(
+            &self.config.log_path,
+            LogLevel::Info,
+            &format!("Watch mode: checking every {}s", interval.as_secs()),
+        )?;
+        if let Some(addr) = &self.config.metrics_addr {
+            serve_metrics(&self.config.log_path, addr)?;
+        }
+
+        let mut rng = jitter_seed();
+        loop {
+            if let Err(err) = run(
+                &self.config,
+                self.disks.as_ref(),
+                self.fs.as_ref(),
+                &self.last_cleanup,
+                &self.hostname,
+            ) {
+                eprintln!("Cleanup cycle failed: {}", err);
+            }
+            if signal::stop_requested() {
+                break;
+            }
+            let delay = match self.config.jitter {
+                Some(jitter) => {
+                    let delay = jittered_delay(interval, jitter, &mut rng);
+                    log_message(
+                        &self.config.log_path,
+                        LogLevel::Info,
+                        &format!("Next check in {:.1}s", delay.as_secs_f64()),
+                    )?;
+                    delay
+                }
+                None => interval,
+            };
+            if !signal::sleep(delay) {
+                break;
+            }
+        }
+
+        log_message(
+            &self.config.log_path,
+            LogLevel::Info,
+            "Stop signal received. Exiting watch mode.",
+        )
+    }
+}
+
+/// Seeds the `JITTER_SECONDS` generator from the clock and process id, so
+/// machines started together still draw different delays.
+fn jitter_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_nanos() as u64;
+    nanos ^ (u64::from(std::process::id()) << 32)
+}
+
+/// Returns `interval` plus a uniformly drawn share of `jitter`, in
+/// `[interval, interval + jitter]`, advancing the splitmix64 `state`.
+fn jittered_delay(interval: Duration, jitter: Duration, state: &mut u64) -> Duration {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    let fraction = (z >> 11) as f64 / (1u64 << 53) as f64;
+    interval + jitter.mul_f64(fraction)
+}
+
+#[cfg(feature = "metrics")]
+fn serve_metrics(log_path: &str, addr: &str) -> std::io::Result<()> {
+    metrics::serve(addr)?;
+    log_message(
+        log_path,
+        LogLevel::Info,
+        &format!("Serving metrics on http://{}/metrics", addr),
+    )
+}
+
+#[cfg(not(feature = "metrics"))]
+fn serve_metrics(log_path: &str, _addr: &str) -> std::io::Result<()> {
+    log_message(
+        log_path,
+        LogLevel::Warn,
+        "METRICS_ADDR is set but this build lacks the metrics feature; not serving metrics",
+    )
+}
+
+#[cfg(feature = "email")]
+fn send_alert(
+    config: &Config,
+    hostname: &str,
+    base_dir: &str,
+    free_after: FreeSpace,
+    stats: &CleanupStats,
+) -> std::io::Result<()> {
+    let (Some(url), Some(from)) = (&config.smtp_url, &config.alert_from) else {
+        return Ok(());
+    };
+    let alert = email::Alert {
+        hostname,
+        instance: &config.instance_id,
+        dir: base_dir,
+        free_after,
+        stop_target: config.stop_label(),
+        stats,
+    };
+    match email::send_alert(url, from, &config.alert_to, &alert) {
+        Ok(()) => log_message(
+            &config.log_path,
+            LogLevel::Info,
+            &format!(
+                "[{}] Sent low-space alert to {}",
+                base_dir,
+                config.alert_to.join(", ")
+            ),
+        ),
+        Err(err) => log_message(
+            &config.log_path,
+            LogLevel::Warn,
+            &format!("[{}] Failed to send low-space alert: {}", base_dir, err),
+        ),
+    }
+}
+
+#[cfg(not(feature = "email"))]
+fn send_alert(
+    config: &Config,
+    _hostname: &str,
+    base_dir: &str,
+    _free_after: FreeSpace,
+    _stats: &CleanupStats,
+) -> std::io::Result<()> {
+    if config.smtp_url.is_none() {
+        return Ok(());
+    }
+    log_message(
+        &config.log_path,
+        LogLevel::Warn,
+        &format!(
+            "[{}] SMTP_URL is set but this build lacks the email feature; not sending alert",
+            base_dir
+        ),
+    )
+}
+
+fn run(
+    config: &Config,
+    disks: &dyn DiskInfo,
+    fs: &dyn FileSystem,
+    last_cleanup: &Mutex<BTreeMap<String, SystemTime>>,
+    hostname: &str,
+) -> std::io::Result<Outcome> {
+    let log_path = &config.log_path;
+    let started = clock::now();
+    #[cfg(feature = "metrics")]
+    metrics::record_run();
+
+    clean_log(log_path, config.log_expiry)?;
+    log_message(
+        log_path,
+        LogLevel::Info,
+        &format!(
+            "Thresholds: start cleanup below {} free, stop above {} free",
+            config.start_label(),
+            config.stop_label()
+        ),
+    )?;
+
+    for (given, resolved) in &config.resolved_dirs {
+        log_message(
+            log_path,
+            LogLevel::Info,
+            &format!("DIRPATH entry {} resolves to {}", given, resolved),
+        )?;
+    }
+    for pattern in &config.unmatched_dir_patterns {
+        log_message(
+            log_path,
+            LogLevel::Warn,
+            &format!("DIRPATH pattern {} matched no directories", pattern),
+        )?;
+    }
+    purge_trash(config, fs)?;
+
+    let mut failed = 0;
+    let mut outcome = Outcome::NoActionNeeded;
+    let mut directories = Vec::new();
+    for base_dir in &config.base_dirs {
+        if signal::stop_requested() {
+            log_message(
+                log_path,
+                LogLevel::Warn,
+                "Shutdown requested; skipping the remaining directories",
+            )?;
+            break;
+        }
+        let mut report = DirectoryReport::new(base_dir);
+        if let Some(dir_config) = config.dir_overrides.get(base_dir) {
+            log_message(
+                log_path,
+                LogLevel::Info,
+                &format!(
+                    "[{}] Per-directory thresholds: start cleanup below {} free, stop above {} free",
+                    base_dir,
+                    dir_config.start_label(),
+                    dir_config.stop_label()
+                ),
+            )?;
+        }
+        let dir_config = config.for_dir(base_dir);
+        // The lock is only taken to read or update the entry, so a slow hook,
+        // webhook or alert in one directory does not hold up another caller.
+        let last = last_cleanup
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(base_dir)
+            .copied();
+        let cooldown_left = dir_config
+            .cooldown
+            .zip(last)
+            .and_then(|(cooldown, last)| cooldown.checked_sub(clock::age(last)))
+            .filter(|left| !left.is_zero());
+        match process_directory(
+            dir_config,
+            disks,
+            fs,
+            hostname,
+            base_dir,
+            cooldown_left,
+            &mut report,
+        ) {
+            Ok(dir_outcome) => {
+                // A dry run deleted nothing, so it starts no cooldown.
+                if dir_outcome == Outcome::CleanedSufficient
+                    && dir_config.cooldown.is_some()
+                    && !dir_config.dry_run
+                {
+                    last_cleanup
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .insert(base_dir.clone(), clock::now());
+                }
+                outcome = outcome.max(dir_outcome)
+            }
+            Err(err) => {
+                failed += 1;
+                log_message(
+                    log_path,
+                    LogLevel::Error,
+                    &format!("[{}] Cleanup failed: {}", base_dir, err),
+                )?;
+                report.error = Some(err.to_string());
+            }
+        }
+        directories.push(report);
+    }
+
+    if let Some(report_path) = &config.report_path {
+        let report = RunReport {
+            started,
+            finished: clock::now(),
+            instance: &config.instance_id,
+            dry_run: config.dry_run,
+            directories,
+        };
+        if let Err(err) = write_report(report_path, &report, config.report_append) {
+            log_message(
+                log_path,
+                LogLevel::Warn,
+                &format!("Failed to write run report to {}: {}", report_path, err),
+            )?;
+        }
+    }
+
+    if failed > 0 {
+        return Err(std::io::Error::other(format!(
+            "Cleanup failed for {} of {} directories",
+            failed,
+            config.base_dirs.len()
+        )));
+    }
+
+    Ok(outcome)
+}
+
+/// Checks and, if needed, cleans one base directory, recording what happened
+/// in `report`. While `cooldown_left` is set, a cleanup the free space or
+/// quota would start is skipped; `--force` still runs.
+fn process_directory(
+    config: &Config,
+    disks: &dyn DiskInfo,
+    fs: &dyn FileSystem,
+    hostname: &str,
+    base_dir: &str,
+    cooldown_left: Option<Duration>,
+    report: &mut DirectoryReport,
+) -> std::io::Result<Outcome> {
+    let log_path = config.log_path.as_str();
+
+    let mut span = logging::span("process_directory", || vec![("dir", base_dir.to_string())]);
+    let free_space = disks.check_storage(config, base_dir);
+    let free_space_percentage = free_space.map(|free| free.percentage);
+    if let Some(percentage) = free_space_percentage {
+        span.record("free_space", format_args!("{:.2}", percentage));
+    }
+    report.free_before = free_space_percentage;
+    report.free_after = free_space_percentage;
+    #[cfg(feature = "metrics")]
+    if let Some(percentage) = free_space_percentage {
+        metrics::record_free_percent(base_dir, percentage);
+    }
+    match free_space {
+        Some(free) => log_message(
+            log_path,
+            LogLevel::Info,
+            &format!("[{}] Current free space: {}", base_dir, free),
+        )?,
+        None => {
+            if let Some(disk) = config.disk_name.as_ref().or(config.mount_point.as_ref()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!(
+                        "No disk matches the configured DISK_NAME/MOUNT_POINT {:?}",
+                        disk
+                    ),
+                ));
+            }
+            log_message(
+                log_path,
+                LogLevel::Warn,
+                &format!("[{}] Disk not found for the base directory.", base_dir),
+            )?
+        }
+    }
+
+    let dir_bytes = match config.max_dir_bytes {
+        Some(max_dir_bytes) => {
+            let dir_bytes = folder_size(fs, Path::new(base_dir))?;
+            log_message(
+                log_path,
+                LogLevel::Info,
+                &format!(
+                    "[{}] Directory size: {} (quota {})",
+                    base_dir,
+                    format_bytes(dir_bytes),
+                    format_bytes(max_dir_bytes)
+                ),
+            )?;
+            Some(dir_bytes)
+        }
+        None => None,
+    };
+
+    let low_space = free_space.is_some_and(|free| free.below_start(config));
+    let over_quota = dir_bytes
+        .zip(config.max_dir_bytes)
+        .is_some_and(|(dir_bytes, max_dir_bytes)| dir_bytes > max_dir_bytes);
+
+    if let Some(left) = cooldown_left.filter(|_| !config.force && (low_space || over_quota)) {
+        log_message(
+            log_path,
+            LogLevel::Info,
+            &format!(
+                "[{}] Cleanup needed but skipped: COOLDOWN_SECONDS has {}s left since the last cleanup",
+                base_dir,
+                left.as_secs()
+            ),
+        )?;
+        return Ok(Outcome::NoActionNeeded);
+    }
+
+    if config.force || low_space || over_quota {
+        let reason = if config.force {
+            "Forced run requested".to_string()
+        } else if low_space {
+            format!(
+                "Free space is below the {} start threshold",
+                config.start_label()
+            )
+        } else {
+            "Directory is over its MAX_DIR_BYTES quota".to_string()
+        };
+        log_message(
+            log_path,
+            LogLevel::Info,
+            &format!("[{}] {}. Starting cleanup...", base_dir, reason),
+        )?;
+        if let Some(pre_hook) = &config.pre_hook {
+            // Aborts this directory; the error is logged with the others.
+            run_hook(
+                config,
+                pre_hook,
+                &HookContext {
+                    phase: "pre",
+                    dir: base_dir,
+                    free_percent: free_space_percentage,
+                    result: None,
+                },
+            )?;
+        }
+        let result = clean_disk(config, disks, fs, base_dir, dir_bytes);
+        if let Some(post_hook) = &config.post_hook {
+            let context = HookContext {
+                phase: "post",
+                dir: base_dir,
+                free_percent: disks
+                    .check_storage(config, base_dir)
+                    .map(|free| free.percentage),
+                result: result.as_ref().ok(),
+            };
+            if let Err(err) = run_hook(config, post_hook, &context) {
+                log_message(log_path, LogLevel::Warn, &format!("[{}] {}", base_dir, err))?;
+            }
+        }
+        let stats = result?;
+        #[cfg(feature = "metrics")]
+        if !config.dry_run {
+            metrics::record_cleanup(&stats);
+        }
+        let free_after = disks.check_storage(config, base_dir);
+        let free_after_percentage = free_after.map(|free| free.percentage);
+        if let Some(percentage) = free_after_percentage {
+            span.record("free_space_after", format_args!("{:.2}", percentage));
+        }
+        span.record("bytes_freed", stats.bytes_freed);
+        send_webhook(
+            config,
+            &CleanupNotification {
+                hostname,
+                instance: &config.instance_id,
+                dir: base_dir,
+                free_before: free_space_percentage,
+                free_after: free_after_percentage,
+                stats: &stats,
+            },
+        )?;
+
+        if let Some(free_after) = free_after.filter(|free| !free.above_stop(config)) {
+            if !config.dry_run {
+                send_alert(config, hostname, base_dir, free_after, &stats)?;
+            }
+        }
+
+        // A dry run leaves the disk as it was; what it would have freed is
+        // measured against what the stop target needs instead.
+        let space_recovered = if config.dry_run {
+            disks
+                .disk_space(config, base_dir)
+                .is_none_or(|(total, available)| {
+                    stats.bytes_freed >= bytes_to_stop(config, total, available)
+                })
+        } else {
+            free_after.is_none_or(|free| free.above_stop(config))
+        };
+        let within_quota =
+            dir_bytes
+                .zip(config.max_dir_bytes)
+                .is_none_or(|(dir_bytes, max_dir_bytes)| {
+                    dir_bytes.saturating_sub(stats.bytes_freed) <= max_dir_bytes
+                });
+        report.free_after = free_after_percentage;
+        report.stats = stats;
+        if space_recovered && within_quota {
+            return Ok(Outcome::CleanedSufficient);
+        }
+        log_message(
+            log_path,
+            LogLevel::Warn,
+            &format!("[{}] Cleanup did not reach its targets.", base_dir),
+        )?;
+        if config.dry_run {
+            return Ok(Outcome::CleanedInsufficient);
+        }
+        match (config.on_failure, &config.on_failure_hook) {
+            (OnFailure::ExitError, _) => {
+                return Err(std::io::Error::other(
+                    "free space could not be recovered (ON_FAILURE=exit-error)",
+                ));
+            }
+            (OnFailure::Hook, Some(on_failure_hook)) => {
+                let context = HookContext {
+                    phase: "failure",
+                    dir: base_dir,
+                    free_percent: free_after_percentage,
+                    result: Some(&report.stats),
+                };
+                if let Err(err) = run_hook(config, on_failure_hook, &context) {
+                    log_message(log_path, LogLevel::Warn, &format!("[{}] {}", base_dir, err))?;
+                }
+            }
+            _ => {}
+        }
+        return Ok(Outcome::CleanedInsufficient);
+    }
+
+    if free_space_percentage.is_some() {
+        log_message(
+            log_path,
+            LogLevel::Info,
+            &format!("[{}] Sufficient free space. No cleanup needed.", base_dir),
+        )?;
+    }
+    Ok(Outcome::NoActionNeeded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clock::TestClock;
+    use crate::test_support::{MemoryDisk, MemoryFileSystem, TempDir};
+
+    /// A cleaner for `dir`'s base directory on a 2000-byte disk that is 1200
+    /// bytes full besides the tree.
+    fn cleaner(dir: &TempDir, tree: &MemoryFileSystem, settings: &str) -> Cleaner {
+        let disks = MemoryDisk {
+            fs: tree.clone(),
+            total: 2000,
+            reserved: 1200,
+        };
+        let config = Config::for_test(dir.path(), settings);
+        Cleaner::with_backends(config, Box::new(disks), Box::new(tree.clone()))
+    }
+
+    /// Adds 100-byte recordings under `base/cam1`; `(recording, days old)`.
+    fn recordings(tree: &MemoryFileSystem, base: &Path, now: SystemTime, ages: &[(&str, u64)]) {
+        for (recording, days_old) in ages {
+            let folder = base.join("cam1").join(recording);
+            tree.file(&folder.join("video"), 100, now, *days_old);
+            tree.set_age(&folder, now, *days_old);
+        }
+    }
+
+    fn log(dir: &TempDir) -> String {
+        std::fs::read_to_string(dir.path().join("cleanup.log")).unwrap_or_default()
+    }
+
+    #[test]
+    fn cooldown_skips_cleanups_until_it_has_passed() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = TestClock::install(now);
+        let dir = TempDir::new("cooldown");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(
+            &tree,
+            &base,
+            now,
+            &[("a", 30), ("b", 25), ("c", 20), ("d", 15), ("e", 10)],
+        );
+        let cleaner = cleaner(&dir, &tree, "depth = 1\ncooldown_seconds = 600");
+
+        // 15% free; three folders go to get above 25%.
+        assert_eq!(cleaner.run().unwrap(), Outcome::CleanedSufficient);
+        assert_eq!(tree.names(&base.join("cam1")), ["d", "e"]);
+
+        // Back down to 15% free a few minutes later.
+        clock.advance(Duration::from_secs(300));
+        recordings(&tree, &base, now, &[("f", 3), ("g", 2), ("h", 1)]);
+        assert_eq!(cleaner.run().unwrap(), Outcome::NoActionNeeded);
+        assert_eq!(tree.names(&base.join("cam1")), ["d", "e", "f", "g", "h"]);
+        assert!(log(&dir).contains("Cleanup needed but skipped: COOLDOWN_SECONDS has 300s left"));
+
+        clock.advance(Duration::from_secs(300));
+        assert_eq!(cleaner.run().unwrap(), Outcome::CleanedSufficient);
+        assert_eq!(tree.names(&base.join("cam1")), ["g", "h"]);
+    }
+
+    #[test]
+    fn dir_patterns_that_match_nothing_are_warned_about() {
+        let dir = TempDir::new("unmatched-dir-pattern-warning");
+        let tree = MemoryFileSystem::new();
+        tree.create_dir_all(&dir.path().join("base")).unwrap();
+        let disks = MemoryDisk {
+            fs: tree.clone(),
+            total: 2000,
+            reserved: 0,
+        };
+        let mut config = Config::for_test(dir.path(), "");
+        let pattern = dir.path().join("nvr*").to_string_lossy().to_string();
+        config.unmatched_dir_patterns = vec![pattern.clone()];
+        let cleaner = Cleaner::with_backends(config, Box::new(disks), Box::new(tree));
+
+        assert_eq!(cleaner.run().unwrap(), Outcome::NoActionNeeded);
+        assert!(log(&dir).contains(&format!(
+            "DIRPATH pattern {} matched no directories",
+            pattern
+        )));
+    }
+
+    #[test]
+    fn empty_selection_directories_end_cleanup_short_of_the_target() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("all-empty");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        for camera in ["cam1", "cam2"] {
+            tree.create_dir_all(&base.join(camera)).unwrap();
+        }
+        let disks = MemoryDisk {
+            fs: tree.clone(),
+            total: 2000,
+            reserved: 1800,
+        };
+        let config = Config::for_test(dir.path(), "depth = 1");
+        let cleaner = Cleaner::with_backends(config, Box::new(disks), Box::new(tree.clone()));
+
+        // 10% free and nothing to delete: one pass, then the loop gives up.
+        assert_eq!(cleaner.run().unwrap(), Outcome::CleanedInsufficient);
+        assert_eq!(tree.names(&base), ["cam1", "cam2"]);
+        let log = log(&dir);
+        assert_eq!(
+            log.matches("No more deletable folders; aborting cleanup")
+                .count(),
+            1
+        );
+        assert!(log.contains("did not reach its targets"), "{}", log);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlinked_base_dir_is_cleaned_through_its_real_path() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("symlinked-base");
+        let real = std::fs::canonicalize(dir.dir("real")).unwrap();
+        let link = dir.path().join("base");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let tree = MemoryFileSystem::new();
+        recordings(
+            &tree,
+            &real,
+            now,
+            &[("a", 30), ("b", 25), ("c", 20), ("d", 15), ("e", 10)],
+        );
+        let cleaner = cleaner(&dir, &tree, "depth = 1");
+
+        let config = cleaner.config();
+        let (link, real) = (link.to_string_lossy(), real.to_string_lossy());
+        assert_eq!(config.base_dirs, [real.to_string()]);
+        assert_eq!(config.resolved_dirs, [(link.to_string(), real.to_string())]);
+        validate_config(config).unwrap();
+
+        // 15% free; three folders go to get above 25%.
+        assert_eq!(cleaner.run().unwrap(), Outcome::CleanedSufficient);
+        assert!(log(&dir).contains(&format!("DIRPATH entry {} resolves to {}", link, real)));
+        assert_eq!(tree.names(&Path::new(&*real).join("cam1")), ["d", "e"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_base_dir_that_cannot_be_resolved_is_refused() {
+        let dir = TempDir::new("dangling-base");
+        let link = dir.path().join("base");
+        std::os::unix::fs::symlink(dir.path().join("missing"), &link).unwrap();
+
+        let config = Config::for_test(dir.path(), "");
+
+        assert_eq!(config.base_dirs, [link.to_string_lossy()]);
+        assert!(config.resolved_dirs.is_empty());
+        let err = validate_config(&config).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(
+            err.to_string()
+                .contains(&format!("DIRPATH entry {} cannot be read", link.display())),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn dry_runs_report_whether_the_deletions_would_be_enough() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("dry-run-outcome");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        recordings(
+            &tree,
+            &base,
+            now,
+            &[("a", 30), ("b", 25), ("c", 20), ("d", 15), ("e", 10)],
+        );
+
+        // 15% free; one pass takes a single folder, short of 25%.
+        let short = cleaner(&dir, &tree, "depth = 1\ndry_run = true");
+        assert_eq!(short.run().unwrap(), Outcome::CleanedInsufficient);
+        assert!(log(&dir).contains("did not reach its targets"));
+
+        std::fs::remove_file(dir.path().join("cleanup.log")).unwrap();
+        let enough = cleaner(
+            &dir,
+            &tree,
+            "depth = 1\ndry_run = true\nstart_threshold = 17\nstop_threshold = 19",
+        );
+        assert_eq!(enough.run().unwrap(), Outcome::CleanedSufficient);
+        assert!(!log(&dir).contains("did not reach its targets"));
+        assert_eq!(tree.names(&base.join("cam1")), ["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn jittered_delay_stays_within_the_jitter_window() {
+        let interval = Duration::from_secs(300);
+        let jitter = Duration::from_secs(60);
+        for seed in 0..200u64 {
+            let mut state = seed.wrapping_mul(0x2545_f491_4f6c_dd1d);
+            let mut previous = None;
+            for _ in 0..50 {
+                let delay = jittered_delay(interval, jitter, &mut state);
+                assert!(
+                    delay >= interval && delay <= interval + jitter,
+                    "{:?}",
+                    delay
+                );
+                assert_ne!(previous, Some(delay), "the state advances");
+                previous = Some(delay);
+            }
+        }
+        // Spread over the window, not bunched at one end.
+        let mut state = 7;
+        let delays: Vec<Duration> = (0..1000)
+            .map(|_| jittered_delay(interval, jitter, &mut state))
+            .collect();
+        assert!(delays.iter().any(|delay| *delay < interval + jitter / 10));
+        assert!(delays
+            .iter()
+            .any(|delay| *delay > interval + jitter * 9 / 10));
+    }
+
+    #[test]
+    fn jittered_delay_without_jitter_is_the_interval() {
+        for seed in [0, 1, u64::MAX] {
+            let mut state = seed;
+            assert_eq!(
+                jittered_delay(Duration::from_secs(30), Duration::ZERO, &mut state),
+                Duration::from_secs(30)
+            );
+        }
+    }
+
+    /// Recordings too young for `MIN_AGE_DAYS`, so the disk stays at 15%
+    /// free whatever cleanup does.
+    fn stuck_cleaner(
+        dir: &TempDir,
+        tree: &MemoryFileSystem,
+        now: SystemTime,
+        settings: &str,
+    ) -> Cleaner {
+        let base = dir.path().join("base");
+        recordings(
+            tree,
+            &base,
+            now,
+            &[("a", 5), ("b", 4), ("c", 3), ("d", 2), ("e", 1)],
+        );
+        cleaner(
+            dir,
+            tree,
+            &format!("depth = 1\nmin_age_days = 30\n{}", settings),
+        )
+    }
+
+    #[test]
+    fn on_failure_log_reports_the_shortfall() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("on-failure-log");
+        let tree = MemoryFileSystem::new();
+        let cleaner = stuck_cleaner(&dir, &tree, now, "on_failure = \"log\"");
+
+        let outcome = cleaner.run().unwrap();
+
+        assert_eq!(outcome, Outcome::CleanedInsufficient);
+        assert_eq!(outcome.exit_code(), std::process::ExitCode::from(3));
+        assert!(log(&dir).contains("Cleanup did not reach its targets."));
+    }
+
+    #[test]
+    fn on_failure_exit_error_fails_the_run() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("on-failure-exit-error");
+        let tree = MemoryFileSystem::new();
+        let cleaner = stuck_cleaner(&dir, &tree, now, "on_failure = \"exit-error\"");
+
+        let err = cleaner.run().unwrap_err();
+
+        // main turns any failed run into Outcome::Error, exit code 1.
+        assert_eq!(err.to_string(), "Cleanup failed for 1 of 1 directories");
+        assert_eq!(Outcome::Error.exit_code(), std::process::ExitCode::from(1));
+        assert!(log(&dir).contains("free space could not be recovered (ON_FAILURE=exit-error)"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn on_failure_hook_runs_the_hook_then_reports_the_shortfall() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("on-failure-hook");
+        let out = dir.path().join("hook.out");
+        let tree = MemoryFileSystem::new();
+        let hook = format!(
+            "echo \"$CLEANUP_PHASE $CLEANUP_FREE_PERCENT\" > '{}'",
+            out.to_string_lossy()
+        );
+        let cleaner = stuck_cleaner(
+            &dir,
+            &tree,
+            now,
+            &format!("on_failure = \"hook\"\non_failure_hook = {:?}", hook),
+        );
+
+        assert_eq!(cleaner.run().unwrap(), Outcome::CleanedInsufficient);
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "failure 15.00\n");
+    }
+}
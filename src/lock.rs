@@ -0,0 +1,54 @@
+//! The lock file that keeps two runs from cleaning at the same time.
+
+use std::fs::{self, File, TryLockError};
+use std::path::Path;
+
+/// An exclusive lock on `LOCK_FILE`, held until dropped. The operating system
+/// releases it when the process exits for any reason, including a panic or a
+/// kill, so a crashed run never leaves a stale lock behind.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Locks `path`, creating it if needed. Returns `None` when another
+    /// process already holds the lock.
+    pub(crate) fn acquire(path: &Path) -> std::io::Result<Option<InstanceLock>> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(InstanceLock { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn a_second_lock_is_refused_until_the_first_is_dropped() {
+        let dir = TempDir::new("lock");
+        let path = dir.path().join("run/disk-cleanup.lock");
+
+        let first = InstanceLock::acquire(&path).unwrap();
+        assert!(first.is_some());
+        assert!(InstanceLock::acquire(&path).unwrap().is_none());
+
+        drop(first);
+        assert!(InstanceLock::acquire(&path).unwrap().is_some());
+    }
+}
@@ -0,0 +1,805 @@
+//! Writing `cleanup.log` in text or JSON form, with levels and rotation.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::clock;
+
+lazy_static! {
+    static ref LOG_SETTINGS: RwLock<LogSettings> = RwLock::new(LogSettings {
+        format: LogFormat::Text,
+        level: LogLevel::Info,
+        max_bytes: None,
+        keep: 5,
+        file: None,
+        instance: String::new(),
+        log_instance: false,
+    });
+    static ref LOGGER: RwLock<Option<Box<dyn Logger>>> = RwLock::new(None);
+}
+
+/// A log record as handed to a `Logger`.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub level: LogLevel,
+    /// Short machine-readable kind, such as `delete` or `message`.
+    pub event: &'a str,
+    pub message: &'a str,
+    /// The file or folder the record is about, if any.
+    pub path: Option<&'a str>,
+    pub freed_bytes: Option<u64>,
+    /// The configured `LOGPATH`.
+    pub log_path: &'a str,
+}
+
+/// A unit of work reported to the `Logger` when it starts and again when it
+/// ends: `process_directory` for each base directory, `clean_disk` inside
+/// it, and `delete` for each removed entry. `id` pairs the two calls; spans
+/// nest on the thread that opened them.
+#[derive(Debug, Clone, Copy)]
+pub struct Span<'a> {
+    pub id: u64,
+    pub name: &'a str,
+    /// Fields such as `dir`, `path`, `free_space` and `bytes_freed`. When
+    /// the span ends this also holds the ones recorded while it ran.
+    pub fields: &'a [(&'a str, String)],
+}
+
+/// Destination for log records. The built-in logger appends to
+/// `cleanup.log`; a program embedding the library can install its own with
+/// `set_logger` to forward records to `log`, `tracing` or anything else.
+/// Records below `LOG_LEVEL` are dropped before they reach the logger.
+pub trait Logger: Send + Sync {
+    fn log(&self, record: &Record) -> std::io::Result<()>;
+
+    /// Called when `span` starts. Ignored unless overridden.
+    fn enter(&self, _span: &Span) {}
+
+    /// Called when `span` ends, `elapsed` after it started. Ignored unless
+    /// overridden.
+    fn exit(&self, _span: &Span, _elapsed: Duration) {}
+}
+
+static NEXT_SPAN_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Set once the log file has failed and lines are going to stderr instead.
+static STDERR_FALLBACK: AtomicBool = AtomicBool::new(false);
+
+/// An open `Span`; reports its end to the installed logger when dropped.
+/// Without a logger installed when it opened, it records nothing.
+pub(crate) struct SpanGuard(Option<OpenSpan>);
+
+struct OpenSpan {
+    id: u64,
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+    started: Instant,
+}
+
+impl SpanGuard {
+    /// Sets `name` to `value`, replacing an earlier value.
+    pub(crate) fn record(&mut self, name: &'static str, value: impl ToString) {
+        let Some(span) = &mut self.0 else {
+            return;
+        };
+        let value = value.to_string();
+        match span.fields.iter_mut().find(|(field, _)| *field == name) {
+            Some((_, existing)) => *existing = value,
+            None => span.fields.push((name, value)),
+        }
+    }
+}
+
+impl OpenSpan {
+    fn as_span(&self) -> Span<'_> {
+        Span {
+            id: self.id,
+            name: self.name,
+            fields: &self.fields,
+        }
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let Some(span) = &self.0 else {
+            return;
+        };
+        if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+            logger.exit(&span.as_span(), span.started.elapsed());
+        }
+    }
+}
+
+/// Opens a span. The built-in logger ignores spans, so without `set_logger`
+/// this does nothing: `fields` is never called and `record` is a no-op.
+pub(crate) fn span(
+    name: &'static str,
+    fields: impl FnOnce() -> Vec<(&'static str, String)>,
+) -> SpanGuard {
+    let logger = LOGGER.read().unwrap();
+    let Some(logger) = logger.as_ref() else {
+        return SpanGuard(None);
+    };
+    let span = OpenSpan {
+        id: NEXT_SPAN_ID.fetch_add(1, Ordering::Relaxed),
+        name,
+        fields: fields(),
+        started: Instant::now(),
+    };
+    logger.enter(&span.as_span());
+    SpanGuard(Some(span))
+}
+
+/// Replaces the built-in `cleanup.log` writer for the rest of the process.
+pub fn set_logger(logger: Box<dyn Logger>) {
+    *LOGGER.write().unwrap() = Some(logger);
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp, e.g. `2024-05-01T12:03:44Z`.
+pub(crate) fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let days = (secs / 86_400) as i64;
+    let secs_of_day = secs % 86_400;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Output format of `cleanup.log`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl LogFormat {
+    pub(crate) fn parse(value: &str) -> Option<LogFormat> {
+        match value.trim().to_lowercase().as_str() {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Severity of a log record. Records below the `LOG_LEVEL` threshold are
+/// dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub(crate) fn parse(value: &str) -> Option<LogLevel> {
+        match value.trim().to_lowercase().as_str() {
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "error" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+/// Process-wide logging options, set once from `Config` at startup.
+#[derive(Debug, Clone)]
+pub(crate) struct LogSettings {
+    pub(crate) format: LogFormat,
+    pub(crate) level: LogLevel,
+    /// Rotate `cleanup.log` once it grows past this many bytes.
+    pub(crate) max_bytes: Option<u64>,
+    /// Number of rotated `cleanup.log.N` files to retain.
+    pub(crate) keep: usize,
+    /// `LOG_FILE` override: a log file path, or `-` for stdout.
+    pub(crate) file: Option<String>,
+    /// `INSTANCE_ID`, added to every JSON line.
+    pub(crate) instance: String,
+    /// Also prefix text lines with the instance (`LOG_INSTANCE`).
+    pub(crate) log_instance: bool,
+}
+
+pub(crate) fn set_log_settings(settings: LogSettings) {
+    *LOG_SETTINGS.write().unwrap() = settings;
+}
+
+pub(crate) fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// A single log record. Both log formats are rendered from the same event so
+/// the text and JSON outputs never disagree about what happened.
+pub(crate) struct LogEvent<'a> {
+    level: LogLevel,
+    event: &'a str,
+    message: String,
+    path: Option<&'a str>,
+    freed_bytes: Option<u64>,
+}
+
+impl<'a> LogEvent<'a> {
+    pub(crate) fn new(level: LogLevel, event: &'a str, message: String) -> LogEvent<'a> {
+        LogEvent {
+            level,
+            event,
+            message,
+            path: None,
+            freed_bytes: None,
+        }
+    }
+
+    pub(crate) fn path(mut self, path: &'a str) -> LogEvent<'a> {
+        self.path = Some(path);
+        self
+    }
+
+    pub(crate) fn freed_bytes(mut self, freed_bytes: u64) -> LogEvent<'a> {
+        self.freed_bytes = Some(freed_bytes);
+        self
+    }
+
+    pub(crate) fn render(&self, settings: &LogSettings, time: SystemTime) -> String {
+        let ts = format_timestamp(time);
+        match settings.format {
+            LogFormat::Text if settings.log_instance => {
+                format!("{} [{}] {}", ts, settings.instance, self.message)
+            }
+            LogFormat::Text => format!("{} {}", ts, self.message),
+            LogFormat::Json => {
+                let mut line = format!(
+                    "{{\"ts\":{},\"level\":\"{}\",\"instance\":{},\"event\":{},\"message\":{}",
+                    json_escape(&ts),
+                    self.level.as_str(),
+                    json_escape(&settings.instance),
+                    json_escape(self.event),
+                    json_escape(&self.message)
+                );
+                if let Some(path) = self.path {
+                    line.push_str(&format!(",\"path\":{}", json_escape(path)));
+                }
+                if let Some(freed_bytes) = self.freed_bytes {
+                    line.push_str(&format!(",\"freed_bytes\":{}", freed_bytes));
+                }
+                line.push('}');
+                line
+            }
+        }
+    }
+}
+
+/// Returns the file log lines for `log_path` are appended to: the `LOG_FILE`
+/// override when set, `cleanup.log` inside `log_path` otherwise. `None` means
+/// standard output.
+fn log_file_path(log_path: &str, settings: &LogSettings) -> Option<PathBuf> {
+    match settings.file.as_deref() {
+        Some("-") => None,
+        Some(file) => Some(PathBuf::from(file)),
+        None => Some(Path::new(log_path).join("cleanup.log")),
+    }
+}
+
+pub(crate) fn log_event(log_path: &str, event: &LogEvent) -> std::io::Result<()> {
+    let settings = LOG_SETTINGS.read().unwrap().clone();
+    if event.level < settings.level {
+        return Ok(());
+    }
+
+    if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+        return logger.log(&Record {
+            level: event.level,
+            event: event.event,
+            message: &event.message,
+            path: event.path,
+            freed_bytes: event.freed_bytes,
+            log_path,
+        });
+    }
+
+    let mut line = event.render(&settings, clock::now());
+    line.push('\n');
+
+    let Some(log_file_path) = log_file_path(log_path, &settings) else {
+        return std::io::stdout().lock().write_all(line.as_bytes());
+    };
+    write_line(&log_file_path.to_string_lossy(), &line, &settings)
+}
+
+/// Appends `record` to the log file the built-in logger would use, so a
+/// `Logger` installed inside the crate can keep `cleanup.log` going. Nothing
+/// is written when the log goes to standard output.
+#[cfg(feature = "tui")]
+pub(crate) fn write_to_log_file(record: &Record) -> std::io::Result<()> {
+    let settings = LOG_SETTINGS.read().unwrap().clone();
+    let Some(log_file_path) = log_file_path(record.log_path, &settings) else {
+        return Ok(());
+    };
+    let event = LogEvent {
+        level: record.level,
+        event: record.event,
+        message: record.message.to_string(),
+        path: record.path,
+        freed_bytes: record.freed_bytes,
+    };
+    let mut line = event.render(&settings, clock::now());
+    line.push('\n');
+    write_line(&log_file_path.to_string_lossy(), &line, &settings)
+}
+
+fn write_line(log_file_path: &str, line: &str, settings: &LogSettings) -> std::io::Result<()> {
+    if let Err(err) = append_to_log_file(log_file_path, line, settings) {
+        // A log that cannot be written must not stop the cleanup, and the
+        // failure itself has nowhere else to go.
+        fall_back_to_stderr(log_file_path, &err);
+        return std::io::stderr().lock().write_all(line.as_bytes());
+    }
+    Ok(())
+}
+
+/// Prints a one-time warning that log lines now go to stderr.
+fn fall_back_to_stderr(log_file_path: &str, err: &std::io::Error) {
+    if !STDERR_FALLBACK.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "Warning: cannot write log file {} ({}); logging to stderr instead",
+            log_file_path, err
+        );
+    }
+}
+
+fn append_to_log_file(
+    log_file_path: &str,
+    line: &str,
+    settings: &LogSettings,
+) -> std::io::Result<()> {
+    if let Some(max_bytes) = settings.max_bytes {
+        // Another instance may be logging to the same file; hold an exclusive
+        // lock so only one of them shifts the rotated files at a time.
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(format!("{}.lock", log_file_path))?;
+        lock.lock()?;
+        rotate_log(log_file_path, max_bytes, settings.keep)?;
+    }
+
+    // O_APPEND plus a single write per line keeps lines from concurrent
+    // writers intact instead of interleaving them mid-line.
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file_path)?;
+    file.write_all(line.as_bytes())
+}
+
+pub(crate) fn log_message(log_path: &str, level: LogLevel, message: &str) -> std::io::Result<()> {
+    log_event(
+        log_path,
+        &LogEvent::new(level, "message", message.to_string()),
+    )
+}
+
+/// Renames `cleanup.log` to `cleanup.log.1` once it reaches `max_bytes`,
+/// shifting older `.N` files up and dropping anything beyond `keep`.
+pub(crate) fn rotate_log(log_file_path: &str, max_bytes: u64, keep: usize) -> std::io::Result<()> {
+    let size = match fs::metadata(log_file_path) {
+        Ok(metadata) => metadata.len(),
+        Err(_) => return Ok(()),
+    };
+    if size < max_bytes {
+        return Ok(());
+    }
+
+    if keep == 0 {
+        return fs::remove_file(log_file_path);
+    }
+
+    let oldest = format!("{}.{}", log_file_path, keep);
+    if Path::new(&oldest).exists() {
+        fs::remove_file(&oldest)?;
+    }
+    for n in (1..keep).rev() {
+        let from = format!("{}.{}", log_file_path, n);
+        if Path::new(&from).exists() {
+            fs::rename(&from, format!("{}.{}", log_file_path, n + 1))?;
+        }
+    }
+    fs::rename(log_file_path, format!("{}.1", log_file_path))
+}
+
+/// Removes `cleanup.log` once it was last written more than `expiry` ago.
+/// Size-based rotation already bounds the log, so this does nothing when
+/// `LOG_MAX_BYTES` is set.
+pub(crate) fn clean_log(log_path: &str, expiry: Duration) -> std::io::Result<()> {
+    let settings = LOG_SETTINGS.read().unwrap().clone();
+    if settings.max_bytes.is_some() {
+        return Ok(());
+    }
+    let Some(log_file_path) = log_file_path(log_path, &settings) else {
+        return Ok(());
+    };
+    if let Ok(metadata) = fs::metadata(&log_file_path) {
+        if let Ok(modified) = metadata.modified() {
+            if clock::age(modified) > expiry {
+                // The log itself may still be writable, so this is only
+                // worth a warning in it.
+                if let Err(err) = fs::remove_file(&log_file_path) {
+                    log_message(
+                        log_path,
+                        LogLevel::Warn,
+                        &format!(
+                            "Could not remove expired log {}: {}",
+                            log_file_path.to_string_lossy(),
+                            err
+                        ),
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::config::Config;
+    use crate::test_support::TempDir;
+
+    fn text_settings() -> LogSettings {
+        LogSettings {
+            format: LogFormat::Text,
+            level: LogLevel::Info,
+            max_bytes: None,
+            keep: 5,
+            file: None,
+            instance: String::new(),
+            log_instance: false,
+        }
+    }
+
+    fn at(secs: u64) -> String {
+        format_timestamp(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn format_timestamp_writes_rfc_3339_utc() {
+        assert_eq!(at(0), "1970-01-01T00:00:00Z");
+        assert_eq!(at(946_684_799), "1999-12-31T23:59:59Z");
+        assert_eq!(at(2_147_483_648), "2038-01-19T03:14:08Z");
+    }
+
+    #[test]
+    fn format_timestamp_follows_the_leap_year_rules() {
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(at(1_709_208_000), "2024-02-29T12:00:00Z");
+        // Divisible by 100 but not by 400: no February 29.
+        assert_eq!(at(4_107_542_399), "2100-02-28T23:59:59Z");
+        assert_eq!(at(4_107_542_400), "2100-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn format_timestamp_clamps_times_before_the_epoch() {
+        let before = SystemTime::UNIX_EPOCH - Duration::from_secs(60);
+        assert_eq!(format_timestamp(before), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn clean_log_wipes_the_log_a_week_after_its_last_write() {
+        let written = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let week = Duration::from_secs(7 * 24 * 60 * 60);
+        let clock = TestClock::install(written + week - Duration::from_secs(60));
+        let dir = TempDir::new("clean-log");
+        let log_file = dir.path().join("cleanup.log");
+        fs::write(&log_file, "old line\n").unwrap();
+        let times = fs::FileTimes::new().set_modified(written);
+        fs::File::options()
+            .append(true)
+            .open(&log_file)
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+
+        clean_log(dir.path().to_str().unwrap(), week).unwrap();
+        assert!(log_file.exists());
+        clock.advance(Duration::from_secs(120));
+        clean_log(dir.path().to_str().unwrap(), week).unwrap();
+        assert!(!log_file.exists());
+    }
+
+    #[test]
+    fn log_expiry_days_sets_the_wipe_window() {
+        let written = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let dir = TempDir::new("log-expiry");
+        let config = Config::for_test(dir.path(), "log.expiry_days = 2");
+        let log_file = dir.path().join("cleanup.log");
+        let write_log = || {
+            fs::write(&log_file, "old line\n").unwrap();
+            let times = fs::FileTimes::new().set_modified(written);
+            fs::File::options()
+                .append(true)
+                .open(&log_file)
+                .unwrap()
+                .set_times(times)
+                .unwrap();
+        };
+        let two_days = Duration::from_secs(2 * 24 * 60 * 60);
+
+        let clock = TestClock::install(written + two_days - Duration::from_secs(3600));
+        write_log();
+        clean_log(&config.log_path, config.log_expiry).unwrap();
+        assert!(log_file.exists(), "kept just under the window");
+        drop(clock);
+
+        let _clock = TestClock::install(written + two_days + Duration::from_secs(3600));
+        write_log();
+        clean_log(&config.log_path, config.log_expiry).unwrap();
+        assert!(!log_file.exists(), "wiped just over the window");
+    }
+
+    #[test]
+    fn unwritable_log_falls_back_to_stderr() {
+        let dir = TempDir::new("unwritable-log");
+        // A directory where the log file should be cannot be opened for
+        // appending, whoever runs the test.
+        let log_file = dir.dir("cleanup.log");
+        let path = log_file.to_string_lossy();
+
+        write_line(&path, "first\n", &text_settings()).unwrap();
+        assert!(STDERR_FALLBACK.load(Ordering::Relaxed));
+        write_line(&path, "second\n", &text_settings()).unwrap();
+        assert!(log_file.is_dir());
+    }
+
+    #[test]
+    fn writable_log_gets_the_line() {
+        let dir = TempDir::new("writable-log");
+        let log_file = dir.path().join("cleanup.log");
+
+        write_line(&log_file.to_string_lossy(), "a line\n", &text_settings()).unwrap();
+        assert_eq!(fs::read_to_string(&log_file).unwrap(), "a line\n");
+    }
+
+    #[test]
+    fn spans_cost_nothing_without_a_logger() {
+        assert!(LOGGER.read().unwrap().is_none());
+        let mut built = false;
+        let mut guard = span("delete", || {
+            built = true;
+            vec![("path", "/srv/cam1/a".to_string())]
+        });
+        guard.record("bytes_freed", 100);
+        assert!(guard.0.is_none());
+        drop(guard);
+        assert!(!built, "the fields are never formatted");
+    }
+
+    /// Has `threads` threads write `lines` lines each to `log_file`, then
+    /// checks that every line arrived whole, exactly once, across the log
+    /// and its rotated copies.
+    fn log_from_threads(log_file: &Path, settings: &LogSettings, threads: usize, lines: usize) {
+        std::thread::scope(|scope| {
+            for thread in 0..threads {
+                scope.spawn(move || {
+                    for line in 0..lines {
+                        let text = format!("thread {} line {} {}\n", thread, line, "x".repeat(64));
+                        write_line(&log_file.to_string_lossy(), &text, settings).unwrap();
+                    }
+                });
+            }
+        });
+
+        let mut seen = std::collections::BTreeSet::new();
+        let rotated =
+            (1..=settings.keep).map(|n| PathBuf::from(format!("{}.{}", log_file.display(), n)));
+        for file in std::iter::once(log_file.to_path_buf()).chain(rotated) {
+            let Ok(contents) = fs::read_to_string(&file) else {
+                continue;
+            };
+            assert!(contents.ends_with('\n'), "{} ends mid-line", file.display());
+            for text in contents.lines() {
+                let fields: Vec<&str> = text.split(' ').collect();
+                let [_, thread, _, line, padding] = fields[..] else {
+                    panic!("torn line {:?} in {}", text, file.display());
+                };
+                assert_eq!(padding, "x".repeat(64), "torn line {:?}", text);
+                let key: (usize, usize) = (thread.parse().unwrap(), line.parse().unwrap());
+                assert!(seen.insert(key), "{:?} logged twice", key);
+            }
+        }
+        assert_eq!(seen.len(), threads * lines);
+    }
+
+    #[test]
+    fn concurrent_writers_keep_lines_whole() {
+        let dir = TempDir::new("log-stress");
+        log_from_threads(&dir.path().join("cleanup.log"), &text_settings(), 8, 200);
+    }
+
+    #[test]
+    fn concurrent_writers_keep_lines_whole_across_rotations() {
+        let dir = TempDir::new("log-stress-rotated");
+        let log_file = dir.path().join("cleanup.log");
+        let settings = LogSettings {
+            max_bytes: Some(4096),
+            keep: 100,
+            ..text_settings()
+        };
+        log_from_threads(&log_file, &settings, 8, 200);
+        assert!(Path::new(&format!("{}.2", log_file.display())).exists());
+    }
+
+    /// A JSON log line read back.
+    #[derive(Debug, PartialEq)]
+    struct JsonLine {
+        ts: String,
+        level: String,
+        instance: String,
+        event: String,
+        message: String,
+        path: Option<String>,
+        freed_bytes: Option<u64>,
+    }
+
+    /// Reads a flat JSON object of string and unsigned integer values, which
+    /// is all the JSON log format writes.
+    fn parse_json_line(line: &str) -> JsonLine {
+        let mut chars = line.chars().peekable();
+        let mut fields = std::collections::BTreeMap::new();
+        let string = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            assert_eq!(chars.next(), Some('"'));
+            let mut value = String::new();
+            loop {
+                match chars.next().unwrap() {
+                    '"' => return value,
+                    '\\' => match chars.next().unwrap() {
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        'u' => {
+                            let hex: String = chars.by_ref().take(4).collect();
+                            value.push(
+                                char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap(),
+                            );
+                        }
+                        c => value.push(c),
+                    },
+                    c => value.push(c),
+                }
+            }
+        };
+        assert_eq!(chars.next(), Some('{'));
+        loop {
+            let key = string(&mut chars);
+            assert_eq!(chars.next(), Some(':'));
+            let value = if chars.peek() == Some(&'"') {
+                string(&mut chars)
+            } else {
+                let mut digits = String::new();
+                while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    digits.push(chars.next().unwrap());
+                }
+                digits
+            };
+            assert!(fields.insert(key, value).is_none(), "duplicate key");
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                other => panic!("unexpected {:?} in {}", other, line),
+            }
+        }
+        assert_eq!(chars.next(), None);
+        let mut take = |key: &str| fields.remove(key);
+        let parsed = JsonLine {
+            ts: take("ts").unwrap(),
+            level: take("level").unwrap(),
+            instance: take("instance").unwrap(),
+            event: take("event").unwrap(),
+            message: take("message").unwrap(),
+            path: take("path"),
+            freed_bytes: take("freed_bytes").map(|bytes| bytes.parse().unwrap()),
+        };
+        assert!(fields.is_empty(), "unexpected fields {:?}", fields);
+        parsed
+    }
+
+    #[test]
+    fn json_lines_read_back_to_the_event() {
+        let settings = LogSettings {
+            format: LogFormat::Json,
+            instance: "cam \"north\"".to_string(),
+            ..text_settings()
+        };
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let message = "Deleted \"old\" folder\nC:\\data\\cam1\t\u{1}".to_string();
+        let event = LogEvent::new(LogLevel::Warn, "delete", message.clone())
+            .path("/data/cam \"1\"/a\\b\n")
+            .freed_bytes(u64::MAX);
+
+        let line = event.render(&settings, time);
+
+        assert!(!line.contains('\n'));
+        assert_eq!(
+            parse_json_line(&line),
+            JsonLine {
+                ts: "2023-11-14T22:13:20Z".to_string(),
+                level: "warn".to_string(),
+                instance: "cam \"north\"".to_string(),
+                event: "delete".to_string(),
+                message,
+                path: Some("/data/cam \"1\"/a\\b\n".to_string()),
+                freed_bytes: Some(u64::MAX),
+            }
+        );
+        let bare =
+            LogEvent::new(LogLevel::Info, "message", "hi".to_string()).render(&settings, time);
+        assert_eq!(parse_json_line(&bare).path, None);
+        assert_eq!(parse_json_line(&bare).freed_bytes, None);
+    }
+
+    #[test]
+    fn text_is_the_default_log_format() {
+        let dir = TempDir::new("log-format-default");
+        let config = Config::for_test(dir.path(), "");
+        assert_eq!(config.log_format, LogFormat::Text);
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let event = LogEvent::new(LogLevel::Info, "message", "hello".to_string());
+        assert_eq!(
+            event.render(&text_settings(), time),
+            "2023-11-14T22:13:20Z hello"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Prometheus metrics served over HTTP in watch mode (`metrics` feature).
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::cleanup::CleanupStats;
+
+static RUNS: AtomicU64 = AtomicU64::new(0);
+static FOLDERS_DELETED: AtomicU64 = AtomicU64::new(0);
+static BYTES_FREED: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref FREE_PERCENT: Mutex<BTreeMap<String, f64>> = Mutex::new(BTreeMap::new());
+}
+
+pub(crate) fn record_run() {
+    RUNS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_cleanup(stats: &CleanupStats) {
+    FOLDERS_DELETED.fetch_add(stats.folders_deleted as u64, Ordering::Relaxed);
+    BYTES_FREED.fetch_add(stats.bytes_freed, Ordering::Relaxed);
+}
+
+pub(crate) fn record_free_percent(dir: &str, percentage: f64) {
+    if let Ok(mut gauges) = FREE_PERCENT.lock() {
+        gauges.insert(dir.to_string(), percentage);
+    }
+}
+
+fn label_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+pub(crate) fn render() -> String {
+    let mut out = String::new();
+    for (name, help, value) in [
+        ("cleanup_runs_total", "Cleanup cycles started.", &RUNS),
+        (
+            "folders_deleted_total",
+            "Folders deleted.",
+            &FOLDERS_DELETED,
+        ),
+        (
+            "bytes_freed_total",
+            "Bytes freed by deletions.",
+            &BYTES_FREED,
+        ),
+    ] {
+        out.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n",
+            value.load(Ordering::Relaxed)
+        ));
+    }
+
+    out.push_str("# HELP disk_free_percent Free space on the disk holding each directory.\n");
+    out.push_str("# TYPE disk_free_percent gauge\n");
+    if let Ok(gauges) = FREE_PERCENT.lock() {
+        for (dir, percentage) in gauges.iter() {
+            out.push_str(&format!(
+                "disk_free_percent{{dir=\"{}\"}} {}\n",
+                label_escape(dir),
+                percentage
+            ));
+        }
+    }
+    out
+}
+
+fn respond(stream: TcpStream) -> std::io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", render())
+    } else {
+        ("404 Not Found", "Not found\n".to_string())
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Starts serving `/metrics` on `addr` from a background thread.
+pub(crate) fn serve(addr: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = respond(stream);
+        }
+    });
+    Ok(())
+}
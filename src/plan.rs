@@ -0,0 +1,135 @@
+//! The `--plan` preview: the folders cleanup would delete, in order, and the
+//! free space each deletion would leave.
+
+use std::path::Path;
+
+use crate::cleanup::{find_selection_dirs, resolve_depth, CleanMode};
+use crate::config::Config;
+use crate::filesystem::FileSystem;
+use crate::scan::{ranked_candidates, skip_entry};
+use crate::size::{folder_size, format_bytes};
+use crate::storage::{free_percentage, DiskInfo, FreeSpace};
+
+pub(crate) fn print_plan(
+    config: &Config,
+    disks: &dyn DiskInfo,
+    fs: &dyn FileSystem,
+) -> std::io::Result<()> {
+    for base_dir in &config.base_dirs {
+        print_directory_plan(config.for_dir(base_dir), disks, fs, base_dir)?;
+    }
+    Ok(())
+}
+
+/// Replays `clean_disk` without deleting anything: each pass takes the next
+/// candidate from every selection directory, and passes continue until the
+/// projected free space rises above the stop threshold. Every deletion is
+/// assumed to give back its full size, which does not hold for `TRASH_DIR` on
+/// the same disk or for `ARCHIVE_DIR`.
+fn print_directory_plan(
+    config: &Config,
+    disks: &dyn DiskInfo,
+    fs: &dyn FileSystem,
+    base_dir: &str,
+) -> std::io::Result<()> {
+    let Some((total, mut available)) = disks.disk_space(config, base_dir) else {
+        println!("[{}] Disk not found for the base directory.", base_dir);
+        return Ok(());
+    };
+    let free = |available: u64| FreeSpace {
+        percentage: free_percentage(total, available).unwrap_or(100.0),
+        available,
+    };
+    let mut dir_bytes = match config.max_dir_bytes {
+        Some(_) => Some(folder_size(fs, Path::new(base_dir))?),
+        None => None,
+    };
+    let over_quota = |dir_bytes: Option<u64>| {
+        dir_bytes
+            .zip(config.max_dir_bytes)
+            .is_some_and(|(dir_bytes, max_dir_bytes)| dir_bytes > max_dir_bytes)
+    };
+    let targets_met = |available: u64, dir_bytes: Option<u64>| {
+        free(available).above_stop(config) && !over_quota(dir_bytes)
+    };
+
+    println!(
+        "[{}] Free space is {} (start below {}, stop above {})",
+        base_dir,
+        free(available),
+        config.start_label(),
+        config.stop_label()
+    );
+    if !config.force && !free(available).below_start(config) && !over_quota(dir_bytes) {
+        println!("  Cleanup would not start now; this is what it would delete once it does.");
+    }
+
+    let mut selection_dirs = Vec::new();
+    find_selection_dirs(
+        config,
+        fs,
+        Path::new(base_dir),
+        resolve_depth(config, fs, base_dir)?,
+        &mut selection_dirs,
+    )?;
+    let mut queues = Vec::new();
+    for dir in &selection_dirs {
+        let ranked = ranked_candidates(&dir.to_string_lossy(), config, fs);
+        if let Some(ranked) = skip_entry(config, dir, ranked)? {
+            queues.push(ranked.into_iter());
+        }
+    }
+
+    let mut step = 0;
+    let mut freed = 0;
+    'plan: loop {
+        if !config.force && targets_met(available, dir_bytes) {
+            break;
+        }
+
+        let mut planned_this_pass = false;
+        for queue in &mut queues {
+            let Some((path, size)) = queue.next() else {
+                continue;
+            };
+            step += 1;
+            freed += size;
+            available = available.saturating_add(size);
+            dir_bytes = dir_bytes.map(|bytes| bytes.saturating_sub(size));
+            planned_this_pass = true;
+            println!(
+                "  {:>3}. {} ({}) -> {} free",
+                step,
+                path.to_string_lossy(),
+                format_bytes(size),
+                free(available)
+            );
+
+            if config
+                .max_deletions
+                .is_some_and(|max_deletions| step >= max_deletions)
+            {
+                println!("  Stopping at the MAX_DELETIONS limit ({}).", step);
+                break 'plan;
+            }
+        }
+
+        if config.force || config.clean_mode == CleanMode::Single || !planned_this_pass {
+            break;
+        }
+    }
+
+    let verdict = if targets_met(available, dir_bytes) {
+        "targets reached"
+    } else {
+        "targets NOT reached"
+    };
+    println!(
+        "  Would delete {} folders ({}), leaving {} free: {}.",
+        step,
+        format_bytes(freed),
+        free(available),
+        verdict
+    );
+    Ok(())
+}
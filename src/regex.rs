@@ -0,0 +1,433 @@
+//! A small backtracking regular expression matcher for `INCLUDE_PATTERN`.
+//!
+//! Supports literals, `.`, `^`, `$`, character classes (`[a-z]`, `[^0-9]`),
+//! the `\d`, `\w` and `\s` shorthands and their negations, groups with `|`
+//! (`(...)` and `(?:...)`), and the `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`
+//! quantifiers. Like `regex::Regex::is_match`, a pattern matches if it
+//! matches anywhere in the text; anchor it with `^...$` to match whole names.
+//! `\d` is ASCII digits only; `\w` and `\s` follow Unicode.
+//!
+//! Anything beyond that is refused by `Regex::new` rather than matched
+//! literally: flags such as `(?i)`, named groups, lookaround, other escapes
+//! (`\b`, `\p{..}`, `\x41`, backreferences), nested or POSIX classes
+//! (`[[:alpha:]]`), the `&&` and `~~` class operators, and repetition
+//! counts above `MAX_REPEAT`. There are no match positions or captures, and
+//! matching backtracks, which is fine for the folder names it is used on.
+
+use std::fmt;
+
+/// Largest count allowed in `{n}`, `{n,}` and `{n,m}`.
+const MAX_REPEAT: usize = 1000;
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    /// `\d`, `\w` or `\s`, optionally negated.
+    Perl(char, bool),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+/// A compiled pattern.
+#[derive(Clone)]
+pub struct Regex {
+    pattern: String,
+    alternatives: Vec<Vec<Node>>,
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Regex").field(&self.pattern).finish()
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let alternatives = parser.parse_alternatives()?;
+        if parser.pos < parser.chars.len() {
+            return Err(format!("unmatched `)` at position {}", parser.pos));
+        }
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            alternatives,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let group = Node::Group(self.alternatives.clone());
+        (0..=text.len()).any(|start| match_single(&group, &text, start, &|_| true))
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn parse_alternatives(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.parse_sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.parse_sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let position = self.pos;
+        match self.next() {
+            Some('(') => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                } else if self.peek() == Some('?') {
+                    return Err(format!(
+                        "flags, named groups and lookaround are not supported at position {}",
+                        position
+                    ));
+                }
+                let alternatives = self.parse_alternatives()?;
+                if self.next() != Some(')') {
+                    return Err(format!("unclosed group at position {}", position));
+                }
+                Ok(Node::Group(alternatives))
+            }
+            Some('[') => self.parse_class(position),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('\\') => match self.parse_escape()? {
+                ClassItem::Range(c, _) => Ok(Node::Char(c)),
+                perl => Ok(Node::Class(vec![perl], false)),
+            },
+            Some(c @ ('*' | '+' | '?' | '{')) => Err(format!(
+                "nothing to repeat before `{}` at position {}",
+                c, position
+            )),
+            Some(c) => Ok(Node::Char(c)),
+            None => Err("unexpected end of pattern".to_string()),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<ClassItem, String> {
+        match self.next() {
+            Some(c @ ('d' | 'w' | 's')) => Ok(ClassItem::Perl(c, false)),
+            Some(c @ ('D' | 'W' | 'S')) => Ok(ClassItem::Perl(c.to_ascii_lowercase(), true)),
+            Some('n') => Ok(ClassItem::Range('\n', '\n')),
+            Some('t') => Ok(ClassItem::Range('\t', '\t')),
+            Some(c) if !c.is_alphanumeric() => Ok(ClassItem::Range(c, c)),
+            Some(c) => Err(format!("unsupported escape `\\{}`", c)),
+            None => Err("pattern ends with `\\`".to_string()),
+        }
+    }
+
+    fn parse_class(&mut self, position: usize) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let item = match self.next() {
+                Some(']') if !first => break,
+                Some('[') => {
+                    return Err(format!(
+                        "nested and POSIX classes are not supported at position {}; escape a literal `[`",
+                        self.pos - 1
+                    ))
+                }
+                Some(c @ ('&' | '~')) if self.peek() == Some(c) => {
+                    return Err(format!(
+                        "class operator `{}{}` is not supported at position {}",
+                        c,
+                        c,
+                        self.pos - 1
+                    ))
+                }
+                Some('\\') => self.parse_escape()?,
+                Some(c) => ClassItem::Range(c, c),
+                None => return Err(format!("unclosed `[` at position {}", position)),
+            };
+            first = false;
+
+            // `a-z` is a range; a `-` before `]` is a literal dash.
+            let is_range =
+                self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']');
+            match item {
+                ClassItem::Range(start, _) if is_range => {
+                    self.pos += 1;
+                    let end = match self.next() {
+                        Some('\\') => match self.parse_escape()? {
+                            ClassItem::Range(end, _) => end,
+                            ClassItem::Perl(..) => {
+                                return Err(format!("invalid range at position {}", self.pos))
+                            }
+                        },
+                        Some(end) => end,
+                        None => return Err(format!("unclosed `[` at position {}", position)),
+                    };
+                    if end < start {
+                        return Err(format!("invalid range `{}-{}`", start, end));
+                    }
+                    items.push(ClassItem::Range(start, end));
+                }
+                item => items.push(item),
+            }
+        }
+        Ok(Node::Class(items, negated))
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                let close = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '}')
+                    .ok_or_else(|| format!("unclosed `{{` at position {}", self.pos))?;
+                let body: String = self.chars[self.pos + 1..self.pos + close].iter().collect();
+                let bad = || format!("invalid repetition `{{{}}}`", body);
+                let (min, max) = match body.split_once(',') {
+                    Some((min, "")) => (min.parse().map_err(|_| bad())?, None),
+                    Some((min, max)) => (
+                        min.parse().map_err(|_| bad())?,
+                        Some(max.parse().map_err(|_| bad())?),
+                    ),
+                    None => {
+                        let count = body.parse().map_err(|_| bad())?;
+                        (count, Some(count))
+                    }
+                };
+                if max.is_some_and(|max| max < min) {
+                    return Err(bad());
+                }
+                if max.unwrap_or(min) > MAX_REPEAT {
+                    return Err(format!(
+                        "repetition `{{{}}}` is above the limit of {}",
+                        body, MAX_REPEAT
+                    ));
+                }
+                self.pos += close;
+                (min, max)
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        // Laziness does not change whether a pattern matches at all.
+        if self.peek() == Some('?') {
+            self.pos += 1;
+        }
+        Ok(Node::Repeat(Box::new(atom), min, max))
+    }
+}
+
+fn class_matches(items: &[ClassItem], negated: bool, c: char) -> bool {
+    let matched = items.iter().any(|item| match *item {
+        ClassItem::Range(start, end) => start <= c && c <= end,
+        ClassItem::Perl(kind, negated) => {
+            let matched = match kind {
+                'd' => c.is_ascii_digit(),
+                'w' => c.is_alphanumeric() || c == '_',
+                _ => c.is_whitespace(),
+            };
+            matched != negated
+        }
+    });
+    matched != negated
+}
+
+/// Matches `nodes` at `pos`, calling `next` with each possible end position
+/// until it accepts one.
+fn match_sequence(nodes: &[Node], text: &[char], pos: usize, next: &dyn Fn(usize) -> bool) -> bool {
+    match nodes.split_first() {
+        None => next(pos),
+        Some((Node::Repeat(node, min, max), rest)) => {
+            match_repeat(node, *min, *max, 0, text, pos, &|end| {
+                match_sequence(rest, text, end, next)
+            })
+        }
+        Some((node, rest)) => match_single(node, text, pos, &|end| {
+            match_sequence(rest, text, end, next)
+        }),
+    }
+}
+
+fn match_single(node: &Node, text: &[char], pos: usize, next: &dyn Fn(usize) -> bool) -> bool {
+    match node {
+        Node::Char(c) => text.get(pos) == Some(c) && next(pos + 1),
+        Node::Any => text.get(pos).is_some_and(|&c| c != '\n') && next(pos + 1),
+        Node::Class(items, negated) => {
+            text.get(pos)
+                .is_some_and(|&c| class_matches(items, *negated, c))
+                && next(pos + 1)
+        }
+        Node::Start => pos == 0 && next(pos),
+        Node::End => pos == text.len() && next(pos),
+        Node::Group(alternatives) => alternatives
+            .iter()
+            .any(|alternative| match_sequence(alternative, text, pos, next)),
+        Node::Repeat(node, min, max) => match_repeat(node, *min, *max, 0, text, pos, next),
+    }
+}
+
+fn match_repeat(
+    node: &Node,
+    min: usize,
+    max: Option<usize>,
+    count: usize,
+    text: &[char],
+    pos: usize,
+    next: &dyn Fn(usize) -> bool,
+) -> bool {
+    let may_repeat = max.is_none_or(|max| count < max);
+    // An iteration that consumes nothing cannot make progress, so it only
+    // counts towards `min`.
+    if may_repeat
+        && match_single(node, text, pos, &|end| {
+            (end != pos || count < min) && match_repeat(node, min, max, count + 1, text, end, next)
+        })
+    {
+        return true;
+    }
+    count >= min && next(pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn patterns_match_anywhere_unless_anchored() {
+        assert!(matches("cam", "front-cam-2"));
+        assert!(matches("^front", "front-cam"));
+        assert!(!matches("^cam", "front-cam"));
+        assert!(matches("cam$", "front-cam"));
+        assert!(!matches("^cam$", "cam1"));
+        assert!(matches("", "anything"));
+        assert!(matches("^$", ""));
+    }
+
+    #[test]
+    fn dated_folder_names_are_told_apart_from_the_rest() {
+        let dated = Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap();
+        for name in ["2024-05-01", "1999-12-31"] {
+            assert!(dated.is_match(name), "{}", name);
+        }
+        for name in [
+            "config",
+            "2024-05",
+            "2024-05-01.bak",
+            "x2024-05-01",
+            "２０２４-05-01",
+        ] {
+            assert!(!dated.is_match(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn classes_groups_and_quantifiers() {
+        assert!(matches("^[a-c]+[^0-9]$", "abcx"));
+        assert!(!matches("^[a-c]+[^0-9]$", "abc1"));
+        assert!(matches("^[-a]$", "-") && matches("^[a-]$", "-") && matches("^[]]$", "]"));
+        assert!(matches(r"^[\d_]+$", "2024_05"));
+        assert!(matches("^(cam|nvr)[0-9]?$", "nvr"));
+        assert!(matches("^(?:ab)+$", "ababab"));
+        assert!(!matches("^(?:ab)+$", "aba"));
+        assert!(matches("^a{2}$", "aa") && !matches("^a{2}$", "aaa"));
+        assert!(matches("^a{2,}$", "aaaa") && !matches("^a{2,}$", "a"));
+        assert!(matches("^a{1,2}b$", "aab") && !matches("^a{1,2}b$", "aaab"));
+        assert!(matches("^a*?b+?$", "abb"));
+        assert!(matches("^(a*)*$", "aaa"));
+        assert!(matches(r"^\w+\s\S+$", "café 2024"));
+        assert!(matches(r"^\D+$", "cam") && !matches(r"^\W$", "a"));
+        assert!(matches(r"^a\.b\*$", "a.b*") && !matches(r"^a\.b$", "axb"));
+        assert!(matches("^.$", "é") && !matches("^.$", "\n"));
+    }
+
+    #[test]
+    fn unsupported_or_malformed_patterns_are_rejected() {
+        for (pattern, message) in [
+            (
+                "(?i)cam",
+                "flags, named groups and lookaround are not supported",
+            ),
+            (
+                "(?P<day>\\d+)",
+                "flags, named groups and lookaround are not supported",
+            ),
+            (
+                "cam(?=1)",
+                "flags, named groups and lookaround are not supported",
+            ),
+            ("[[:digit:]]+", "nested and POSIX classes are not supported"),
+            ("[a-z&&[^x]]", "class operator `&&` is not supported"),
+            ("[a[b]]", "nested and POSIX classes are not supported"),
+            ("[a-z&&x]", "class operator `&&` is not supported"),
+            ("[a~~b]", "class operator `~~` is not supported"),
+            (r"\bcam\b", "unsupported escape `\\b`"),
+            (r"\p{L}", "unsupported escape `\\p`"),
+            (r"(a)\1", "unsupported escape `\\1`"),
+            ("a{1001}", "repetition `{1001}` is above the limit of 1000"),
+            ("a{2,1}", "invalid repetition `{2,1}`"),
+            ("a{x}", "invalid repetition `{x}`"),
+            ("a{2", "unclosed `{`"),
+            ("(cam", "unclosed group"),
+            ("cam)", "unmatched `)`"),
+            ("[abc", "unclosed `[`"),
+            ("[z-a]", "invalid range `z-a`"),
+            ("*cam", "nothing to repeat before `*`"),
+            ("cam\\", "pattern ends with `\\`"),
+        ] {
+            let err = Regex::new(pattern).unwrap_err();
+            assert!(err.starts_with(message), "{:?}: {}", pattern, err);
+        }
+    }
+}
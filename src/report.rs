@@ -0,0 +1,127 @@
+//! Machine-readable summary of a run, written to `REPORT_PATH`.
+
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::cleanup::CleanupStats;
+use crate::logging::{format_timestamp, json_escape};
+
+/// What happened to one base directory during a run.
+pub(crate) struct DirectoryReport {
+    pub(crate) dir: String,
+    pub(crate) free_before: Option<f64>,
+    pub(crate) free_after: Option<f64>,
+    pub(crate) stats: CleanupStats,
+    pub(crate) error: Option<String>,
+}
+
+impl DirectoryReport {
+    pub(crate) fn new(dir: &str) -> DirectoryReport {
+        DirectoryReport {
+            dir: dir.to_string(),
+            free_before: None,
+            free_after: None,
+            stats: CleanupStats::default(),
+            error: None,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let percentage = |value: Option<f64>| {
+            value
+                .map(|percentage| format!("{:.2}", percentage))
+                .unwrap_or_else(|| "null".to_string())
+        };
+        let deleted: Vec<String> = self
+            .stats
+            .deleted
+            .iter()
+            .map(|(path, bytes)| format!("{{\"path\":{},\"bytes\":{}}}", json_escape(path), bytes))
+            .collect();
+        format!(
+            "{{\"dir\":{},\"free_space_before\":{},\"free_space_after\":{},\"deleted\":[{}],\"freed_bytes\":{},\"error\":{}}}",
+            json_escape(&self.dir),
+            percentage(self.free_before),
+            percentage(self.free_after),
+            deleted.join(","),
+            self.stats.bytes_freed,
+            self.error
+                .as_deref()
+                .map(json_escape)
+                .unwrap_or_else(|| "null".to_string())
+        )
+    }
+}
+
+/// Summary of one run across every base directory.
+pub(crate) struct RunReport<'a> {
+    pub(crate) instance: &'a str,
+    pub(crate) started: SystemTime,
+    pub(crate) finished: SystemTime,
+    pub(crate) dry_run: bool,
+    pub(crate) directories: Vec<DirectoryReport>,
+}
+
+impl RunReport<'_> {
+    pub(crate) fn to_json(&self) -> String {
+        let total_freed: u64 = self
+            .directories
+            .iter()
+            .map(|dir| dir.stats.bytes_freed)
+            .sum();
+        let directories: Vec<String> = self.directories.iter().map(|dir| dir.to_json()).collect();
+        format!(
+            "{{\"instance\":{},\"started_at\":{},\"finished_at\":{},\"dry_run\":{},\"directories\":[{}],\"total_freed_bytes\":{}}}",
+            json_escape(self.instance),
+            json_escape(&format_timestamp(self.started)),
+            json_escape(&format_timestamp(self.finished)),
+            self.dry_run,
+            directories.join(","),
+            total_freed
+        )
+    }
+}
+
+/// Writes `report` to `path`, replacing the previous report, or with
+/// `append` adds it to the JSON array already stored there. The file is
+/// written next to its destination and renamed over it, so readers never see
+/// half a report.
+pub(crate) fn write_report(path: &str, report: &RunReport, append: bool) -> std::io::Result<()> {
+    let json = report.to_json();
+    let contents = if append {
+        let existing = match fs::read_to_string(path) {
+            Ok(existing) => existing,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let existing = existing.trim();
+        if existing.is_empty() {
+            format!("[\n{}\n]\n", json)
+        } else if let Some(reports) = existing
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            let reports = reports.trim();
+            if reports.is_empty() {
+                format!("[\n{}\n]\n", json)
+            } else {
+                format!("[\n{},\n{}\n]\n", reports, json)
+            }
+        } else if existing.starts_with('{') {
+            // A single report left behind before REPORT_APPEND was enabled.
+            format!("[\n{},\n{}\n]\n", existing, json)
+        } else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} does not hold a JSON array of reports", path),
+            ));
+        }
+    } else {
+        format!("{}\n", json)
+    };
+
+    let partial = format!("{}.partial", path);
+    fs::write(&partial, contents)?;
+    fs::rename(&partial, Path::new(path))
+}
@@ -0,0 +1,1484 @@
+//! Choosing which folder to delete next.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::cleanup::SkipSummary;
+use crate::clock;
+use crate::config::Config;
+use crate::dedup::redundant_copies;
+use crate::filesystem::{FileSystem, Metadata};
+use crate::logging::{log_message, LogLevel};
+use crate::size::{cached_path_size, format_bytes};
+
+/// Which timestamp `get_oldest_entry` uses to decide a folder's age.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    /// Birth time where the filesystem records it, modification time otherwise.
+    Auto,
+    Created,
+    Modified,
+    /// Last access time, for least-recently-used eviction. Needs a mount with
+    /// atime updates enabled (`relatime` or `strictatime`, not `noatime`).
+    Accessed,
+}
+
+impl SortBy {
+    pub(crate) fn parse(value: &str) -> Option<SortBy> {
+        match value.trim().to_lowercase().as_str() {
+            "auto" => Some(SortBy::Auto),
+            "created" => Some(SortBy::Created),
+            "modified" => Some(SortBy::Modified),
+            "accessed" => Some(SortBy::Accessed),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn folder_timestamp(
+    metadata: &Metadata,
+    sort_by: SortBy,
+) -> std::io::Result<SystemTime> {
+    match sort_by {
+        SortBy::Created => metadata.created(),
+        SortBy::Modified => metadata.modified(),
+        SortBy::Accessed => match metadata.accessed() {
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported => metadata.modified(),
+            result => result,
+        },
+        SortBy::Auto => match metadata.created() {
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported => metadata.modified(),
+            result => result,
+        },
+    }
+}
+
+/// Matches `text` against a shell-style pattern where `*` matches any run of
+/// characters and `?` matches exactly one.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Expands a path whose components may contain `*` and `?` into the
+/// directories it matches, sorted. As in a shell, wildcards do not match
+/// names starting with `.` unless the pattern component does too. A path
+/// without wildcards is returned unchanged, whether or not it exists.
+pub(crate) fn expand_dir_pattern(pattern: &str) -> Vec<PathBuf> {
+    let is_pattern = |text: &str| text.contains(['*', '?']);
+    if !is_pattern(pattern) {
+        return vec![PathBuf::from(pattern)];
+    }
+
+    let mut matches = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        let text = component.as_os_str().to_string_lossy();
+        if !is_pattern(&text) {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for dir in &matches {
+            let listing = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir.as_path()
+            };
+            let Ok(entries) = fs::read_dir(listing) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') && !text.starts_with('.') {
+                    continue;
+                }
+                if glob_match(&text, &name) && entry.path().is_dir() {
+                    next.push(dir.join(&name));
+                }
+            }
+        }
+        matches = next;
+    }
+    matches.retain(|path| path.is_dir());
+    matches.sort();
+    matches
+}
+
+/// Why an entry was passed over; see `SkipSummary`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Skip {
+    Excluded,
+    TooYoung,
+    TooSmall,
+    InUse,
+    OtherDevice,
+    Unmarked,
+    Errored,
+}
+
+thread_local! {
+    /// Entries skipped during the current `clean_disk` call on this thread,
+    /// or `None` outside one (`--plan`, `--stats`).
+    static SKIPPED: RefCell<Option<BTreeSet<(Skip, PathBuf)>>> = const { RefCell::new(None) };
+}
+
+/// Starts collecting skipped entries for `take_skips`, dropping any left
+/// over from an earlier call that failed.
+pub(crate) fn track_skips() {
+    SKIPPED.with(|skipped| *skipped.borrow_mut() = Some(BTreeSet::new()));
+}
+
+/// Stops collecting and counts what was skipped since `track_skips`.
+pub(crate) fn take_skips() -> SkipSummary {
+    let skipped = SKIPPED.with(|skipped| skipped.borrow_mut().take().unwrap_or_default());
+    let mut summary = SkipSummary::default();
+    for (reason, _) in skipped {
+        match reason {
+            Skip::Excluded => summary.excluded += 1,
+            Skip::TooYoung => summary.too_young += 1,
+            Skip::TooSmall => summary.too_small += 1,
+            Skip::InUse => summary.in_use += 1,
+            Skip::OtherDevice => summary.other_device += 1,
+            Skip::Unmarked => summary.unmarked += 1,
+            Skip::Errored => summary.errored += 1,
+        }
+    }
+    summary
+}
+
+fn record_skip(reason: Skip, path: &Path) {
+    SKIPPED.with(|skipped| {
+        if let Some(skipped) = skipped.borrow_mut().as_mut() {
+            skipped.insert((reason, path.to_path_buf()));
+        }
+    });
+}
+
+/// Turns a `NotFound` or `PermissionDenied` error for `path` into `Ok(None)`
+/// after logging a warning. Other processes work in the same directories, so
+/// an entry can vanish or become unreadable between listing and use; that
+/// should cost one candidate, not the whole run.
+pub(crate) fn skip_entry<T>(
+    config: &Config,
+    path: &Path,
+    result: std::io::Result<T>,
+) -> std::io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            log_message(
+                &config.log_path,
+                LogLevel::Warn,
+                &format!("Skipping {}: {}", path.to_string_lossy(), err),
+            )?;
+            record_skip(Skip::Errored, path);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns true (and logs the skip) when `path` matches an `EXCLUDE` pattern,
+/// either by its folder name or by its full path.
+pub(crate) fn is_excluded(path: &Path, config: &Config) -> std::io::Result<bool> {
+    let full_path = path.to_string_lossy();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+
+    let excluded = config
+        .exclude
+        .iter()
+        .any(|pattern| glob_match(pattern, &name) || glob_match(pattern, &full_path));
+
+    if excluded {
+        record_skip(Skip::Excluded, path);
+        log_message(
+            &config.log_path,
+            LogLevel::Debug,
+            &format!("Skipping excluded folder: {}", full_path),
+        )?;
+    }
+    Ok(excluded)
+}
+
+/// Returns true unless `MANIFEST` is set and lists neither `path`'s name
+/// nor its full path, in which case the skip is logged.
+pub(crate) fn in_manifest(path: &Path, config: &Config) -> std::io::Result<bool> {
+    let Some(manifest) = &config.manifest else {
+        return Ok(true);
+    };
+    let full_path = path.to_string_lossy();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    if manifest
+        .iter()
+        .any(|pattern| glob_match(pattern, &name) || glob_match(pattern, &full_path))
+    {
+        return Ok(true);
+    }
+    record_skip(Skip::Excluded, path);
+    log_message(
+        &config.log_path,
+        LogLevel::Debug,
+        &format!("Skipping folder not listed in MANIFEST: {}", full_path),
+    )?;
+    Ok(false)
+}
+
+/// File whose presence opts a directory, and everything below it, into
+/// cleanup under `REQUIRE_MARKER`.
+pub(crate) const MARKER_FILE: &str = ".cleanup-ok";
+
+/// Returns true unless `REQUIRE_MARKER` is set and neither `path` (when it
+/// is a directory) nor any directory above it up to its base directory holds
+/// a `.cleanup-ok` file, in which case the skip is logged. The marker itself
+/// is never a candidate.
+pub(crate) fn has_marker(
+    path: &Path,
+    config: &Config,
+    fs: &dyn FileSystem,
+) -> std::io::Result<bool> {
+    if !config.require_marker {
+        return Ok(true);
+    }
+    let is_marker = path
+        .file_name()
+        .is_some_and(|name| name == std::ffi::OsStr::new(MARKER_FILE));
+    if !is_marker {
+        let base_dir = config
+            .base_dirs
+            .iter()
+            .map(Path::new)
+            .filter(|base_dir| path.starts_with(base_dir))
+            .max_by_key(|base_dir| base_dir.components().count());
+        let start = if is_real_dir(path, config, fs)? {
+            Some(path)
+        } else {
+            path.parent()
+        };
+        for dir in start.into_iter().flat_map(Path::ancestors) {
+            if fs
+                .symlink_metadata(&dir.join(MARKER_FILE))
+                .is_ok_and(|marker| marker.is_file())
+            {
+                return Ok(true);
+            }
+            if base_dir.is_none_or(|base_dir| dir == base_dir) {
+                break;
+            }
+        }
+    }
+    record_skip(Skip::Unmarked, path);
+    log_message(
+        &config.log_path,
+        LogLevel::Debug,
+        &format!(
+            "Skipping {} without a {} marker (REQUIRE_MARKER)",
+            path.to_string_lossy(),
+            MARKER_FILE
+        ),
+    )?;
+    Ok(false)
+}
+
+/// Returns true when `path` is a directory in its own right. Symlinks are
+/// never treated as directories, so cleanup cannot be led outside the base
+/// directory through a link; skipped links are logged at debug level.
+pub(crate) fn is_real_dir(
+    path: &Path,
+    config: &Config,
+    fs: &dyn FileSystem,
+) -> std::io::Result<bool> {
+    let metadata = match fs.symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(false),
+    };
+
+    if metadata.is_symlink() {
+        log_message(
+            &config.log_path,
+            LogLevel::Debug,
+            &format!("Skipping symlink: {}", path.to_string_lossy()),
+        )?;
+        return Ok(false);
+    }
+    Ok(metadata.is_dir())
+}
+
+/// Which kinds of entries `TARGET` allows as deletion candidates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Folders,
+    /// Regular files only; folders are left alone.
+    Files,
+    Both,
+}
+
+impl Target {
+    pub(crate) fn parse(value: &str) -> Option<Target> {
+        match value.trim().to_lowercase().as_str() {
+            "folders" => Some(Target::Folders),
+            "files" => Some(Target::Files),
+            "both" => Some(Target::Both),
+            _ => None,
+        }
+    }
+}
+
+/// Returns true when `path` is of a kind `target` allows: a real directory,
+/// or a regular file. Symlinks are never allowed.
+fn is_target(
+    path: &Path,
+    config: &Config,
+    fs: &dyn FileSystem,
+    target: Target,
+) -> std::io::Result<bool> {
+    if is_real_dir(path, config, fs)? {
+        return Ok(target != Target::Files);
+    }
+    let is_file = fs
+        .symlink_metadata(path)
+        .is_ok_and(|metadata| metadata.is_file());
+    Ok(is_file && target != Target::Folders)
+}
+
+/// Returns true unless `STAY_ON_DEVICE` is set and `path` is on another
+/// filesystem than the base directory it was found under, in which case the
+/// skip is logged. Deleting across a mount point frees nothing on the disk
+/// being cleaned.
+pub(crate) fn on_base_device(
+    path: &Path,
+    config: &Config,
+    fs: &dyn FileSystem,
+) -> std::io::Result<bool> {
+    if !config.stay_on_device {
+        return Ok(true);
+    }
+    let Some(base_dir) = config
+        .base_dirs
+        .iter()
+        .map(Path::new)
+        .filter(|base_dir| path.starts_with(base_dir))
+        .max_by_key(|base_dir| base_dir.components().count())
+    else {
+        return Ok(true);
+    };
+    let base = fs.metadata(base_dir)?;
+    let Some(entry) = skip_entry(config, path, fs.symlink_metadata(path))? else {
+        return Ok(false);
+    };
+    if same_device(base.device, entry.device) {
+        return Ok(true);
+    }
+    record_skip(Skip::OtherDevice, path);
+    log_message(
+        &config.log_path,
+        LogLevel::Info,
+        &format!(
+            "Skipping {} on another device than {} (STAY_ON_DEVICE)",
+            path.to_string_lossy(),
+            base_dir.to_string_lossy()
+        ),
+    )?;
+    Ok(false)
+}
+
+/// Whether an entry on `entry_dev` counts as on the base directory's
+/// `base_dev`. Entries whose device is unknown (on Windows, for one) are
+/// never skipped.
+fn same_device(base_dev: Option<u64>, entry_dev: Option<u64>) -> bool {
+    base_dev
+        .zip(entry_dev)
+        .is_none_or(|(base, entry)| base == entry)
+}
+
+/// Returns true if `path`, or anything below it, was modified after
+/// `cutoff`. Symlinks are not followed and entries that vanish during the
+/// walk are ignored; the walk stops at the first recent entry.
+fn modified_since(fs: &dyn FileSystem, path: &Path, cutoff: SystemTime) -> std::io::Result<bool> {
+    let metadata = match fs.symlink_metadata(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        result => result?,
+    };
+    if metadata.modified()? > cutoff {
+        return Ok(true);
+    }
+    if !metadata.is_dir() {
+        return Ok(false);
+    }
+    let entries = match fs.read_dir(path) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        result => result?,
+    };
+    for entry in entries {
+        if modified_since(fs, &entry, cutoff)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns true when `path` may be picked for deletion: it is an entry
+/// `target` allows, is not hidden (with `SKIP_HIDDEN`), matches
+/// `INCLUDE_PATTERN`, is not excluded, is listed in `MANIFEST`, is on the base directory's device
+/// (with `STAY_ON_DEVICE`), is at least `MIN_AGE_DAYS` old,
+/// holds nothing modified within `ACTIVE_GRACE_SECONDS`, is at least
+/// `MIN_FOLDER_BYTES` large and, with `EMPTY_INSTEAD_OF_DELETE`, is not
+/// already empty.
+pub(crate) fn is_candidate(
+    path: &Path,
+    config: &Config,
+    fs: &dyn FileSystem,
+    target: Target,
+) -> std::io::Result<bool> {
+    if !is_target(path, config, fs, target)? || !on_base_device(path, config, fs)? {
+        return Ok(false);
+    }
+
+    // An emptied folder stays where it was in the age order; without this
+    // it would be picked again on every pass while freeing nothing.
+    if config.empty_instead_of_delete && fs.metadata(path).is_ok_and(|metadata| metadata.is_dir()) {
+        let Some(entries) = skip_entry(config, path, fs.read_dir(path))? else {
+            return Ok(false);
+        };
+        if entries.is_empty() {
+            log_message(
+                &config.log_path,
+                LogLevel::Debug,
+                &format!(
+                    "Skipping already emptied folder: {}",
+                    path.to_string_lossy()
+                ),
+            )?;
+            return Ok(false);
+        }
+    }
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    if config.skip_hidden && name.starts_with('.') {
+        record_skip(Skip::Excluded, path);
+        log_message(
+            &config.log_path,
+            LogLevel::Debug,
+            &format!("Skipping hidden folder: {}", path.to_string_lossy()),
+        )?;
+        return Ok(false);
+    }
+
+    if let Some(pattern) = &config.include_pattern {
+        if !pattern.is_match(&name) {
+            record_skip(Skip::Excluded, path);
+            log_message(
+                &config.log_path,
+                LogLevel::Debug,
+                &format!(
+                    "Skipping folder not matching INCLUDE_PATTERN: {}",
+                    path.to_string_lossy()
+                ),
+            )?;
+            return Ok(false);
+        }
+    }
+
+    if is_excluded(path, config)? || !in_manifest(path, config)? || !has_marker(path, config, fs)? {
+        return Ok(false);
+    }
+
+    if let Some(min_age) = config.min_age {
+        let Some(metadata) = skip_entry(config, path, fs.metadata(path))? else {
+            return Ok(false);
+        };
+        let age = clock::age(folder_timestamp(&metadata, config.sort_by)?);
+        if age < min_age {
+            record_skip(Skip::TooYoung, path);
+            log_message(
+                &config.log_path,
+                LogLevel::Debug,
+                &format!(
+                    "Skipping folder younger than MIN_AGE_DAYS: {}",
+                    path.to_string_lossy()
+                ),
+            )?;
+            return Ok(false);
+        }
+    }
+
+    if let Some(grace) = config.active_grace {
+        let cutoff = clock::now()
+            .checked_sub(grace)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        if modified_since(fs, path, cutoff)? {
+            record_skip(Skip::InUse, path);
+            log_message(
+                &config.log_path,
+                LogLevel::Info,
+                &format!(
+                    "Skipping active folder (modified within ACTIVE_GRACE_SECONDS): {}",
+                    path.to_string_lossy()
+                ),
+            )?;
+            return Ok(false);
+        }
+    }
+
+    // Measured last: sizing a large folder costs more than every other check.
+    if let Some(min_folder_bytes) = config.min_folder_bytes {
+        let Some(size) = skip_entry(config, path, cached_path_size(fs, path))? else {
+            return Ok(false);
+        };
+        if size < min_folder_bytes {
+            record_skip(Skip::TooSmall, path);
+            log_message(
+                &config.log_path,
+                LogLevel::Debug,
+                &format!(
+                    "Skipping folder smaller than MIN_FOLDER_BYTES: {} ({})",
+                    path.to_string_lossy(),
+                    format_bytes(size)
+                ),
+            )?;
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Lists the entries in `dir_path` that may be deleted, leaving out the
+/// `KEEP_RECENT` newest ones.
+fn candidates(
+    dir_path: &str,
+    config: &Config,
+    fs: &dyn FileSystem,
+    target: Target,
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut candidates = Vec::new();
+    for path in fs.read_dir(Path::new(dir_path))? {
+        if is_candidate(&path, config, fs, target)? {
+            candidates.push(path);
+        }
+    }
+
+    let Some(keep_recent) = config.keep_recent else {
+        return Ok(candidates);
+    };
+
+    let mut dated = Vec::with_capacity(candidates.len());
+    for path in candidates {
+        let Some(metadata) = skip_entry(config, &path, fs.metadata(&path))? else {
+            continue;
+        };
+        dated.push((folder_timestamp(&metadata, config.sort_by)?, path));
+    }
+    dated.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+
+    let kept = dated.len().min(keep_recent);
+    if kept > 0 {
+        log_message(
+            &config.log_path,
+            LogLevel::Debug,
+            &format!("Keeping {} most recent folders in: {}", kept, dir_path),
+        )?;
+    }
+    Ok(dated.into_iter().skip(kept).map(|(_, path)| path).collect())
+}
+
+/// Returns the oldest candidate in `dir_path` among the entries `target`
+/// allows.
+pub(crate) fn get_oldest_entry(
+    dir_path: &str,
+    config: &Config,
+    fs: &dyn FileSystem,
+    target: Target,
+) -> std::io::Result<Option<String>> {
+    let mut oldest_folder: Option<String> = None;
+    let mut oldest_time: Option<SystemTime> = None;
+
+    for (folder_time, path) in dated_candidates(dir_path, config, fs, target)? {
+        if oldest_time.is_none() || folder_time < oldest_time.unwrap() {
+            oldest_time = Some(folder_time);
+            oldest_folder = Some(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(oldest_folder)
+}
+
+/// Pairs each candidate in `dir_path` with its `SORT_BY` timestamp. With
+/// `SORT_BY=accessed`, candidates that all share one access time mean atime
+/// is not being updated on this mount; modification times are used instead,
+/// with a warning, so the choice is not arbitrary.
+pub(crate) fn dated_candidates(
+    dir_path: &str,
+    config: &Config,
+    fs: &dyn FileSystem,
+    target: Target,
+) -> std::io::Result<Vec<(SystemTime, PathBuf)>> {
+    let mut dated = Vec::new();
+    for path in candidates(dir_path, config, fs, target)? {
+        let Some(metadata) = skip_entry(config, &path, fs.metadata(&path))? else {
+            continue;
+        };
+        dated.push((folder_timestamp(&metadata, config.sort_by)?, path));
+    }
+
+    let atime_frozen = config.sort_by == SortBy::Accessed
+        && dated.len() > 1
+        && dated.iter().all(|(time, _)| *time == dated[0].0);
+    if !atime_frozen {
+        return Ok(dated);
+    }
+
+    log_message(
+        &config.log_path,
+        LogLevel::Warn,
+        &format!(
+            "All candidates in {} share one access time; atime looks disabled on this mount. \
+             Falling back to modification time.",
+            dir_path
+        ),
+    )?;
+    let mut modified = Vec::with_capacity(dated.len());
+    for (_, path) in dated {
+        let Some(metadata) = skip_entry(config, &path, fs.metadata(&path))? else {
+            continue;
+        };
+        modified.push((metadata.modified()?, path));
+    }
+    Ok(modified)
+}
+
+/// Returns the candidates in `dir_path` with their sizes, in the order
+/// `clean_disk` would delete them under the configured strategy.
+pub(crate) fn ranked_candidates(
+    dir_path: &str,
+    config: &Config,
+    fs: &dyn FileSystem,
+) -> std::io::Result<Vec<(PathBuf, u64)>> {
+    let mut ranked = Vec::new();
+    for (time, path) in dated_candidates(dir_path, config, fs, config.target)? {
+        let Some(size) = skip_entry(config, &path, cached_path_size(fs, &path))? else {
+            continue;
+        };
+        ranked.push((time, size, path));
+    }
+
+    // Stable sorts, so ties keep the first-seen folder first, as the
+    // get_*_entry helpers do.
+    match config.strategy {
+        Strategy::Oldest => ranked.sort_by_key(|(time, _, _)| *time),
+        Strategy::Largest => ranked.sort_by_key(|(_, size, _)| std::cmp::Reverse(*size)),
+        Strategy::Score => {
+            let now = clock::now();
+            ranked.sort_by(|(a_time, a_size, _), (b_time, b_size, _)| {
+                score(config, now, *b_time, *b_size)
+                    .total_cmp(&score(config, now, *a_time, *a_size))
+            })
+        }
+        Strategy::Dedup => {
+            let redundant = redundant_copies(config, fs, &ranked)?;
+            ranked.sort_by_key(|(time, _, path)| (!redundant.contains(path), *time));
+        }
+    }
+    Ok(ranked
+        .into_iter()
+        .map(|(_, size, path)| (path, size))
+        .collect())
+}
+
+/// Returns the loose files anywhere below `base_dir` whose extension is in
+/// `EXTENSIONS_PRIORITY`, oldest `SORT_BY` timestamp first, with their
+/// sizes. The walk does not enter excluded or (with `SKIP_HIDDEN`) hidden
+/// folders, symlinks or (with `STAY_ON_DEVICE`) other filesystems. With
+/// `INCLUDE_PATTERN`, a file only counts when its name or a folder it sits
+/// in below `base_dir` matches, as if that folder were the candidate. Files
+/// younger than `MIN_AGE_DAYS` or modified within `ACTIVE_GRACE_SECONDS` are
+/// left alone, as they may still be written; folders are not held to those,
+/// since adding any file to one makes it look new.
+pub(crate) fn priority_files(
+    base_dir: &Path,
+    config: &Config,
+    fs: &dyn FileSystem,
+) -> std::io::Result<Vec<(PathBuf, u64)>> {
+    let matches_include = |path: &Path| {
+        config.include_pattern.as_ref().is_none_or(|pattern| {
+            path.file_name()
+                .is_some_and(|name| pattern.is_match(&name.to_string_lossy()))
+        })
+    };
+    let mut found = Vec::new();
+    let mut pending = vec![(base_dir.to_path_buf(), false)];
+    while let Some((dir, inside_included)) = pending.pop() {
+        let Some(entries) = skip_entry(config, &dir, fs.read_dir(&dir))? else {
+            continue;
+        };
+        for path in entries {
+            if is_excluded(&path, config)? || !on_base_device(&path, config, fs)? {
+                continue;
+            }
+            let hidden = config.skip_hidden
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            let included = inside_included || matches_include(&path);
+            if is_real_dir(&path, config, fs)? {
+                if !hidden {
+                    pending.push((path, included));
+                }
+                continue;
+            }
+            let Some(metadata) = skip_entry(config, &path, fs.symlink_metadata(&path))? else {
+                continue;
+            };
+            let prioritized = path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .is_some_and(|extension| config.extensions_priority.contains(&extension));
+            if !metadata.is_file()
+                || !prioritized
+                || !in_manifest(&path, config)?
+                || !has_marker(&path, config, fs)?
+            {
+                continue;
+            }
+            if hidden || !included {
+                record_skip(Skip::Excluded, &path);
+                continue;
+            }
+            let timestamp = folder_timestamp(&metadata, config.sort_by)?;
+            if config
+                .min_age
+                .is_some_and(|min_age| clock::age(timestamp) < min_age)
+            {
+                record_skip(Skip::TooYoung, &path);
+                continue;
+            }
+            if let Some(grace) = config.active_grace {
+                if clock::age(metadata.modified()?) < grace {
+                    record_skip(Skip::InUse, &path);
+                    continue;
+                }
+            }
+            found.push((timestamp, metadata.len, path));
+        }
+    }
+    found.sort_by_key(|(time, _, _)| *time);
+    Ok(found
+        .into_iter()
+        .map(|(_, size, path)| (path, size))
+        .collect())
+}
+
+/// How `clean_disk` picks the next folder to delete within a subfolder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Oldest,
+    Largest,
+    /// Highest `age_days^SCORE_AGE_WEIGHT * bytes^SCORE_SIZE_WEIGHT` first.
+    Score,
+    /// Redundant copies of byte-identical candidates first, all but the
+    /// newest of each set, then everything else oldest first.
+    Dedup,
+}
+
+impl Strategy {
+    pub(crate) fn parse(value: &str) -> Option<Strategy> {
+        match value.trim().to_lowercase().as_str() {
+            "oldest" => Some(Strategy::Oldest),
+            "largest" => Some(Strategy::Largest),
+            "score" => Some(Strategy::Score),
+            "dedup" => Some(Strategy::Dedup),
+            _ => None,
+        }
+    }
+
+    /// The `STRATEGY` value that selects this strategy.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Strategy::Oldest => "oldest",
+            Strategy::Largest => "largest",
+            Strategy::Score => "score",
+            Strategy::Dedup => "dedup",
+        }
+    }
+}
+
+/// Returns the largest candidate in `dir_path` among the entries `target`
+/// allows.
+pub(crate) fn get_largest_entry(
+    dir_path: &str,
+    config: &Config,
+    fs: &dyn FileSystem,
+    target: Target,
+) -> std::io::Result<Option<String>> {
+    let mut largest_folder: Option<String> = None;
+    let mut largest_size: Option<u64> = None;
+
+    for path in candidates(dir_path, config, fs, target)? {
+        let Some(size) = skip_entry(config, &path, cached_path_size(fs, &path))? else {
+            continue;
+        };
+        log_message(
+            &config.log_path,
+            LogLevel::Debug,
+            &format!(
+                "Candidate {} uses {}",
+                path.to_string_lossy(),
+                format_bytes(size)
+            ),
+        )?;
+
+        if largest_size.is_none() || size > largest_size.unwrap() {
+            largest_size = Some(size);
+            largest_folder = Some(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(largest_folder)
+}
+
+/// Ranks a candidate for `STRATEGY=score`: its age in days and its size in
+/// bytes, each raised to its configured weight and multiplied. A weight of 0
+/// leaves that factor out; timestamps in the future count as age 0.
+pub(crate) fn score(config: &Config, now: SystemTime, time: SystemTime, size: u64) -> f64 {
+    let age_days = now
+        .duration_since(time)
+        .unwrap_or(std::time::Duration::ZERO)
+        .as_secs_f64()
+        / 86_400.0;
+    age_days.powf(config.score_age_weight) * (size as f64).powf(config.score_size_weight)
+}
+
+/// Returns the highest-scoring candidate in `dir_path` among the entries
+/// `target` allows, logging each candidate's score.
+pub(crate) fn get_highest_score_entry(
+    dir_path: &str,
+    config: &Config,
+    fs: &dyn FileSystem,
+    target: Target,
+) -> std::io::Result<Option<String>> {
+    let now = clock::now();
+    let mut best_folder: Option<String> = None;
+    let mut best_score: Option<f64> = None;
+
+    for (time, path) in dated_candidates(dir_path, config, fs, target)? {
+        let Some(size) = skip_entry(config, &path, cached_path_size(fs, &path))? else {
+            continue;
+        };
+        let score = score(config, now, time, size);
+        log_message(
+            &config.log_path,
+            LogLevel::Debug,
+            &format!(
+                "Candidate {} ({}) scores {:.3e}",
+                path.to_string_lossy(),
+                format_bytes(size),
+                score
+            ),
+        )?;
+
+        if best_score.is_none_or(|best| score > best) {
+            best_score = Some(score);
+            best_folder = Some(path.to_string_lossy().to_string());
+        }
+    }
+    Ok(best_folder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::clock::TestClock;
+    use crate::filesystem::{EntryKind, RealFileSystem};
+    use crate::test_support::{set_age, MemoryFileSystem, TempDir};
+
+    fn names(files: &[(PathBuf, u64)]) -> Vec<String> {
+        files
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn metadata(created: Option<u64>, accessed: Option<u64>) -> Metadata {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        Metadata {
+            kind: EntryKind::Dir,
+            len: 0,
+            modified: Some(at(500)),
+            accessed: accessed.map(at),
+            created: created.map(at),
+            device: None,
+            mode: None,
+        }
+    }
+
+    #[test]
+    fn skip_entry_passes_over_vanished_and_unreadable_entries() {
+        let dir = TempDir::new("skip-entry");
+        let config = Config::for_test(dir.path(), "");
+        let path = dir.path().join("base/a/sub/run");
+        let failed = |kind| skip_entry::<()>(&config, &path, Err(std::io::Error::from(kind)));
+
+        track_skips();
+        assert_eq!(skip_entry(&config, &path, Ok(7)).unwrap(), Some(7));
+        assert!(failed(std::io::ErrorKind::NotFound).unwrap().is_none());
+        assert!(failed(std::io::ErrorKind::PermissionDenied)
+            .unwrap()
+            .is_none());
+        let err = failed(std::io::ErrorKind::StorageFull).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+        // The same entry skipped twice counts once.
+        assert_eq!(take_skips().errored, 1);
+    }
+
+    #[test]
+    fn entries_vanishing_mid_scan_are_passed_over() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("vanishing");
+        let sub = dir.path().join("base/a/sub");
+        let tree = MemoryFileSystem::new();
+        tree.file(&sub.join("old/data"), 10, now, 30);
+        tree.file(&sub.join("new/data"), 20, now, 1);
+        tree.set_age(&sub.join("old"), now, 30);
+        tree.set_age(&sub.join("new"), now, 1);
+        tree.ghost(&sub.join("gone"));
+        tree.ghost(&sub.join("new/gone-too"));
+        let config = Config::for_test(dir.path(), "min_folder_bytes = 1");
+
+        // A vanished folder is no longer a folder, and a vanished file
+        // inside one adds nothing to its size; neither is an error.
+        track_skips();
+        let ranked = ranked_candidates(&sub.to_string_lossy(), &config, &tree).unwrap();
+        assert_eq!(ranked, [(sub.join("old"), 10), (sub.join("new"), 20)]);
+        assert_eq!(take_skips().errored, 0);
+    }
+
+    #[test]
+    fn expand_dir_pattern_lists_matching_directories() {
+        let dir = TempDir::new("expand-dir-pattern");
+        for name in ["cam1", "cam2", "other", ".hidden"] {
+            dir.dir(&format!("base/{}", name));
+        }
+        dir.file("base/cam3", b"not a directory", SystemTime::now(), 0);
+        let base = dir.path().join("base");
+        let expand = |pattern: &str| expand_dir_pattern(&base.join(pattern).to_string_lossy());
+
+        assert_eq!(expand("cam*"), [base.join("cam1"), base.join("cam2")]);
+        assert_eq!(expand("cam?"), [base.join("cam1"), base.join("cam2")]);
+        assert_eq!(
+            expand("*"),
+            [base.join("cam1"), base.join("cam2"), base.join("other")]
+        );
+        assert_eq!(expand(".h*"), [base.join(".hidden")]);
+        assert_eq!(expand("missing-*"), Vec::<PathBuf>::new());
+        // Without wildcards the path comes back as given.
+        assert_eq!(expand("missing"), [base.join("missing")]);
+    }
+
+    #[test]
+    fn only_a_known_different_device_is_another_device() {
+        assert!(same_device(Some(2049), Some(2049)));
+        assert!(!same_device(Some(2049), Some(2050)));
+        assert!(same_device(None, Some(2050)));
+        assert!(same_device(Some(2049), None));
+        assert!(same_device(None, None));
+    }
+
+    #[test]
+    fn glob_match_handles_stars_and_question_marks() {
+        assert!(glob_match("*.tmp", "cache.tmp"));
+        assert!(glob_match("*.tmp", ".tmp"));
+        assert!(!glob_match("*.tmp", "cache.tmp.bak"));
+        assert!(glob_match("cam?_*", "cam1_2024-05-01"));
+        assert!(!glob_match("cam?_*", "cam12_2024"));
+        assert!(glob_match("*a*b*c", "xxaxxbxxbxc"));
+        assert!(glob_match("**", ""));
+        assert!(!glob_match("?", ""));
+        assert!(glob_match("résumé", "résumé"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "Exact"));
+    }
+
+    #[test]
+    fn exclude_matches_the_folder_name_or_the_full_path() {
+        let dir = TempDir::new("exclude");
+        let config = Config::for_test(dir.path(), r#"exclude = "keep*,*/cam2/*""#);
+        let base = dir.path().join("base");
+
+        assert!(is_excluded(&base.join("cam1/keep-me"), &config).unwrap());
+        assert!(is_excluded(&base.join("cam2/2024-05-01"), &config).unwrap());
+        assert!(!is_excluded(&base.join("cam1/2024-05-01"), &config).unwrap());
+        assert!(!is_excluded(&base.join("cam1/me-keep"), &config).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_never_candidates() {
+        let dir = TempDir::new("symlink-candidates");
+        let outside = dir.dir("outside");
+        let sub = dir.dir("base/a/sub");
+        dir.dir("base/a/sub/real");
+        std::os::unix::fs::symlink(&outside, sub.join("link")).unwrap();
+        let config = Config::for_test(dir.path(), r#"target = "both""#);
+
+        let found = candidates(
+            &sub.to_string_lossy(),
+            &config,
+            &RealFileSystem,
+            Target::Both,
+        );
+        assert_eq!(found.unwrap(), [sub.join("real")]);
+        assert!(!is_real_dir(&sub.join("link"), &config, &RealFileSystem).unwrap());
+    }
+
+    #[test]
+    fn skip_hidden_passes_over_dot_folders() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("skip-hidden");
+        let cam = dir.path().join("base/cam1");
+        let tree = MemoryFileSystem::new();
+        for name in [".snapshots", "2023-10-01"] {
+            tree.file(&cam.join(name).join("video"), 1, now, 30);
+            tree.set_age(&cam.join(name), now, 30);
+        }
+        let hidden = cam.join(".snapshots");
+        let visible = cam.join("2023-10-01");
+
+        let config = Config::for_test(dir.path(), "skip_hidden = true");
+        track_skips();
+        assert!(!is_candidate(&hidden, &config, &tree, Target::Folders).unwrap());
+        assert!(is_candidate(&visible, &config, &tree, Target::Folders).unwrap());
+        assert_eq!(take_skips().excluded, 1);
+
+        let config = Config::for_test(dir.path(), "skip_hidden = false");
+        track_skips();
+        assert!(is_candidate(&hidden, &config, &tree, Target::Folders).unwrap());
+        assert!(is_candidate(&visible, &config, &tree, Target::Folders).unwrap());
+        assert_eq!(take_skips().excluded, 0);
+    }
+
+    #[test]
+    fn include_pattern_limits_candidates_to_matching_names() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("include-pattern");
+        let sub = dir.path().join("base/a/sub");
+        let tree = MemoryFileSystem::new();
+        for (name, days_old) in [
+            ("2023-10-01", 40),
+            ("config", 90),
+            ("2023-10-20", 20),
+            ("2023-10", 60),
+        ] {
+            tree.file(&sub.join(name).join("data"), 1, now, days_old);
+            tree.set_age(&sub.join(name), now, days_old);
+        }
+        let config = Config::for_test(dir.path(), r#"include_pattern = '^\d{4}-\d{2}-\d{2}$'"#);
+
+        track_skips();
+        let sub_dir = sub.to_string_lossy();
+        let mut found = candidates(&sub_dir, &config, &tree, Target::Folders).unwrap();
+        found.sort();
+        assert_eq!(found, [sub.join("2023-10-01"), sub.join("2023-10-20")]);
+        assert_eq!(take_skips().excluded, 2);
+        // The older undated folders are never the oldest candidate.
+        let oldest = get_oldest_entry(&sub_dir, &config, &tree, Target::Folders).unwrap();
+        assert_eq!(
+            oldest,
+            Some(sub.join("2023-10-01").to_string_lossy().to_string())
+        );
+    }
+
+    #[test]
+    fn manifest_limits_candidates_to_listed_folders() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("manifest");
+        let sub = dir.path().join("base/a/sub");
+        let tree = MemoryFileSystem::new();
+        for name in [
+            "2023-10-01",
+            "2023-10-02",
+            "2023-11-01",
+            "keep",
+            "# not a comment",
+        ] {
+            tree.file(&sub.join(name).join("data"), 1, now, 40);
+        }
+        let manifest = dir.path().join("manifest.txt");
+        std::fs::write(
+            &manifest,
+            format!(
+                "# recordings cleared for deletion\n\n  2023-10-*  \n\t\n{}\n#keep\n",
+                sub.join("2023-11-01").to_string_lossy()
+            ),
+        )
+        .unwrap();
+        let config = Config::for_test(
+            dir.path(),
+            &format!("manifest = {:?}", manifest.to_string_lossy()),
+        );
+
+        track_skips();
+        let mut found =
+            candidates(&sub.to_string_lossy(), &config, &tree, Target::Folders).unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                sub.join("2023-10-01"),
+                sub.join("2023-10-02"),
+                sub.join("2023-11-01")
+            ]
+        );
+        assert_eq!(take_skips().excluded, 2);
+
+        let missing = dir.path().join("missing.txt");
+        let err = Config::try_for_test(
+            dir.path(),
+            &format!("manifest = {:?}", missing.to_string_lossy()),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(err.to_string().starts_with("MANIFEST "), "{}", err);
+    }
+
+    #[test]
+    fn require_marker_limits_candidates_to_marked_directories() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("require-marker");
+        let base = dir.path().join("base");
+        let tree = MemoryFileSystem::new();
+        for camera in ["cam1", "cam2", "cam3"] {
+            for day in ["d1", "d2"] {
+                tree.file(&base.join(camera).join(day).join("data"), 1, now, 40);
+            }
+        }
+        // cam1 is marked as a whole, only one day of cam2.
+        tree.write(&base.join("cam1").join(MARKER_FILE), b"")
+            .unwrap();
+        tree.write(&base.join("cam2/d2").join(MARKER_FILE), b"")
+            .unwrap();
+        let config = Config::for_test(dir.path(), "require_marker = true");
+
+        let mut found = Vec::new();
+        track_skips();
+        for camera in ["cam1", "cam2", "cam3"] {
+            let camera = base.join(camera);
+            found.extend(
+                candidates(&camera.to_string_lossy(), &config, &tree, Target::Folders).unwrap(),
+            );
+        }
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                base.join("cam1/d1"),
+                base.join("cam1/d2"),
+                base.join("cam2/d2")
+            ]
+        );
+        assert_eq!(take_skips().unmarked, 3);
+
+        let unmarked = Config::for_test(dir.path(), "");
+        let all = candidates(
+            &base.join("cam3").to_string_lossy(),
+            &unmarked,
+            &tree,
+            Target::Folders,
+        )
+        .unwrap();
+        assert_eq!(all.len(), 2);
+    }
+
+    #[test]
+    fn folder_timestamp_falls_back_to_modification_time() {
+        let epoch = SystemTime::UNIX_EPOCH;
+        let born = metadata(Some(100), Some(900));
+        assert_eq!(
+            folder_timestamp(&born, SortBy::Auto).unwrap(),
+            epoch + Duration::from_secs(100)
+        );
+        assert_eq!(
+            folder_timestamp(&born, SortBy::Accessed).unwrap(),
+            epoch + Duration::from_secs(900)
+        );
+
+        let unborn = metadata(None, None);
+        for sort_by in [SortBy::Auto, SortBy::Modified, SortBy::Accessed] {
+            assert_eq!(
+                folder_timestamp(&unborn, sort_by).unwrap(),
+                epoch + Duration::from_secs(500)
+            );
+        }
+        // Asking for birth time explicitly does not silently use another.
+        let err = folder_timestamp(&unborn, SortBy::Created).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn sort_by_accessed_dates_candidates_by_their_access_time() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("sort-by-accessed");
+        let cam = dir.path().join("base/cam1");
+        let tree = MemoryFileSystem::new();
+        let days = |days: u64| now - Duration::from_secs(days * 24 * 60 * 60);
+        // a was written long ago but watched yesterday; b the other way round.
+        for (name, accessed, modified) in [("a", 1, 30), ("b", 20, 10)] {
+            tree.file(&cam.join(name).join("video"), 1, now, modified);
+            tree.set_times(&cam.join(name), days(accessed), days(modified))
+                .unwrap();
+        }
+        let config = Config::for_test(dir.path(), "sort_by = \"accessed\"");
+        let cam_dir = cam.to_string_lossy();
+
+        let mut dated = dated_candidates(&cam_dir, &config, &tree, Target::Folders).unwrap();
+        dated.sort();
+        assert_eq!(dated, [(days(20), cam.join("b")), (days(1), cam.join("a"))]);
+        let oldest = get_oldest_entry(&cam_dir, &config, &tree, Target::Folders).unwrap();
+        assert_eq!(oldest, Some(cam.join("b").to_string_lossy().to_string()));
+
+        // One shared access time means atime is not kept up to date.
+        for (name, modified) in [("a", 30), ("b", 10)] {
+            tree.set_times(&cam.join(name), days(5), days(modified))
+                .unwrap();
+        }
+        let mut dated = dated_candidates(&cam_dir, &config, &tree, Target::Folders).unwrap();
+        dated.sort();
+        assert_eq!(
+            dated,
+            [(days(30), cam.join("a")), (days(10), cam.join("b"))]
+        );
+        let log = std::fs::read_to_string(dir.path().join("cleanup.log")).unwrap();
+        assert!(
+            log.contains(&format!(
+                "All candidates in {} share one access time; atime looks disabled",
+                cam_dir
+            )),
+            "{}",
+            log
+        );
+    }
+
+    #[test]
+    fn score_weights_decide_between_old_and_large_folders() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("score-weights");
+        let cam = dir.path().join("base/cam1");
+        let tree = MemoryFileSystem::new();
+        for (name, size, days_old) in [("old-small", 100, 40), ("new-large", 1000, 10)] {
+            tree.file(&cam.join(name).join("video"), size, now, days_old);
+            tree.set_age(&cam.join(name), now, days_old);
+        }
+        let cam_dir = cam.to_string_lossy();
+
+        for (age_weight, size_weight, first, second) in [
+            // 40 × 100 against 10 × 1000.
+            (1.0, 1.0, "new-large", "old-small"),
+            // 1600 × 100 against 100 × 1000.
+            (2.0, 1.0, "old-small", "new-large"),
+            (0.0, 1.0, "new-large", "old-small"),
+            (1.0, 0.0, "old-small", "new-large"),
+        ] {
+            let config = Config::for_test(
+                dir.path(),
+                &format!(
+                    "strategy = \"score\"\nscore_age_weight = {}\nscore_size_weight = {}",
+                    age_weight, size_weight
+                ),
+            );
+            let best = get_highest_score_entry(&cam_dir, &config, &tree, Target::Folders).unwrap();
+            assert_eq!(
+                best,
+                Some(cam.join(first).to_string_lossy().to_string()),
+                "age^{} × size^{}",
+                age_weight,
+                size_weight
+            );
+            let ranked: Vec<PathBuf> = ranked_candidates(&cam_dir, &config, &tree)
+                .unwrap()
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect();
+            assert_eq!(ranked, [cam.join(first), cam.join(second)]);
+        }
+    }
+
+    #[test]
+    fn min_folder_bytes_passes_over_small_folders_of_any_age() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("min-folder-bytes");
+        let cam = dir.path().join("base/cam1");
+        let tree = MemoryFileSystem::new();
+        for (name, size, days_old) in [
+            ("tiny-oldest", 50, 60),
+            ("big-old", 500, 40),
+            ("exact", 200, 30),
+            ("small-new", 10, 5),
+            ("big-new", 800, 3),
+        ] {
+            tree.file(&cam.join(name).join("video"), size, now, days_old);
+            tree.set_age(&cam.join(name), now, days_old);
+        }
+        let config = Config::for_test(dir.path(), "min_folder_bytes = \"200\"");
+        let cam_dir = cam.to_string_lossy();
+
+        track_skips();
+        let mut found = candidates(&cam_dir, &config, &tree, Target::Folders).unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            [cam.join("big-new"), cam.join("big-old"), cam.join("exact")]
+        );
+        assert_eq!(take_skips().too_small, 2);
+        let oldest = get_oldest_entry(&cam_dir, &config, &tree, Target::Folders).unwrap();
+        assert_eq!(
+            oldest,
+            Some(cam.join("big-old").to_string_lossy().to_string())
+        );
+        let largest = get_largest_entry(&cam_dir, &config, &tree, Target::Folders).unwrap();
+        assert_eq!(
+            largest,
+            Some(cam.join("big-new").to_string_lossy().to_string())
+        );
+    }
+
+    #[test]
+    fn min_age_days_holds_a_folder_back_until_the_clock_passes_it() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = TestClock::install(now);
+        let dir = TempDir::new("min-age");
+        dir.file("base/a/sub/run/data", b"x", now, 3);
+        let run = dir.path().join("base/a/sub/run");
+        set_age(&run, now, 3);
+        let config = Config::for_test(
+            dir.path(),
+            r#"sort_by = "modified"
+min_age_days = 7"#,
+        );
+
+        assert!(!is_candidate(&run, &config, &RealFileSystem, Target::Folders).unwrap());
+        clock.advance(Duration::from_secs(4 * 24 * 60 * 60 - 60));
+        assert!(!is_candidate(&run, &config, &RealFileSystem, Target::Folders).unwrap());
+        clock.advance(Duration::from_secs(120));
+        assert!(is_candidate(&run, &config, &RealFileSystem, Target::Folders).unwrap());
+    }
+
+    #[test]
+    fn active_grace_waits_for_the_newest_file_inside() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let clock = TestClock::install(now);
+        let dir = TempDir::new("active-grace");
+        dir.file("base/a/sub/run/old", b"x", now, 30);
+        let written = dir.file("base/a/sub/run/nested/latest", b"x", now, 0);
+        let times = fs::FileTimes::new().set_modified(now - Duration::from_secs(600));
+        fs::File::open(&written).unwrap().set_times(times).unwrap();
+        for folder in ["base/a/sub/run/nested", "base/a/sub/run"] {
+            set_age(&dir.path().join(folder), now, 30);
+        }
+        let run = dir.path().join("base/a/sub/run");
+        let config = Config::for_test(dir.path(), "active_grace_seconds = 3600");
+
+        assert!(!is_candidate(&run, &config, &RealFileSystem, Target::Folders).unwrap());
+        clock.advance(Duration::from_secs(3000 - 1));
+        assert!(!is_candidate(&run, &config, &RealFileSystem, Target::Folders).unwrap());
+        clock.advance(Duration::from_secs(2));
+        assert!(is_candidate(&run, &config, &RealFileSystem, Target::Folders).unwrap());
+    }
+
+    #[test]
+    fn priority_files_takes_listed_extensions_in_age_order() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("priority-mixed");
+        dir.file("base/a/old.tmp", b"123", now, 30);
+        dir.file("base/a/notes.txt", b"keep", now, 40);
+        dir.file("base/a/deep/er/half.PARTIAL", b"12", now, 20);
+        dir.file("base/b/newer.tmp", b"1", now, 10);
+        dir.file("base/b/archive.tar", b"keep", now, 50);
+        let config = Config::for_test(
+            dir.path(),
+            r#"extensions_priority = ".tmp,partial"
+sort_by = "modified""#,
+        );
+
+        let found = priority_files(&dir.path().join("base"), &config, &RealFileSystem).unwrap();
+        assert_eq!(names(&found), ["old.tmp", "half.PARTIAL", "newer.tmp"]);
+        assert_eq!(found[0].1, 3);
+    }
+
+    #[test]
+    fn priority_files_applies_the_folder_guards() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("priority-guards");
+        dir.file("base/2024-01/old.tmp", b"x", now, 30);
+        dir.file("base/2024-01/fresh.tmp", b"x", now, 0);
+        dir.file("base/2024-01/.cache/hidden.tmp", b"x", now, 30);
+        dir.file("base/2024-01/.dotfile.tmp", b"x", now, 30);
+        dir.file("base/scratch/unincluded.tmp", b"x", now, 30);
+        dir.file("base/scratch/2024-02.tmp", b"x", now, 30);
+        let config = Config::for_test(
+            dir.path(),
+            r#"extensions_priority = "tmp"
+sort_by = "modified"
+min_age_days = 7
+skip_hidden = true
+include_pattern = "^20[0-9]{2}-""#,
+        );
+
+        track_skips();
+        let found = priority_files(&dir.path().join("base"), &config, &RealFileSystem).unwrap();
+        let skips = take_skips();
+        let mut found = names(&found);
+        found.sort();
+        assert_eq!(found, ["2024-02.tmp", "old.tmp"]);
+        assert_eq!(skips.too_young, 1);
+        // .dotfile.tmp and unincluded.tmp; the hidden folder is not entered.
+        assert_eq!(skips.excluded, 2);
+    }
+}
@@ -0,0 +1,47 @@
+//! SIGINT/SIGTERM handling for watch mode.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn request_stop(_signal: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Installs handlers that turn SIGINT and SIGTERM into a stop request instead
+/// of killing the process mid-deletion.
+pub(crate) fn install_handlers() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = request_stop as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+pub(crate) fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Forgets a stop request, so a test that raised a signal leaves the
+/// process as it found it.
+#[cfg(test)]
+pub(crate) fn clear_stop_request() {
+    STOP_REQUESTED.store(false, Ordering::SeqCst);
+}
+
+/// Sleeps for `duration`, waking early if a stop is requested. Returns `true`
+/// when the full duration elapsed.
+pub(crate) fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while !stop_requested() {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(200)));
+    }
+    false
+}
@@ -0,0 +1,353 @@
+//! Recursive folder sizes and byte formatting.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use crate::filesystem::FileSystem;
+
+/// Sums the sizes of all files below `path`. Entries removed while the walk
+/// is in progress count as zero bytes instead of failing the whole sum.
+#[cfg(not(feature = "parallel"))]
+pub(crate) fn folder_size(fs: &dyn FileSystem, path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in fs.read_dir(path)? {
+        size += entry_size(fs, &entry)?;
+    }
+    Ok(size)
+}
+
+/// Same as the sequential version, but subdirectories are summed in parallel
+/// on rayon's global thread pool.
+#[cfg(feature = "parallel")]
+pub(crate) fn folder_size(fs: &dyn FileSystem, path: &Path) -> std::io::Result<u64> {
+    use rayon::prelude::*;
+
+    fs.read_dir(path)?
+        .into_par_iter()
+        .map(|entry| entry_size(fs, &entry))
+        .try_reduce(|| 0, |a, b| Ok(a + b))
+}
+
+/// Size of `path` itself when it is a file, or of everything below it when it
+/// is a directory.
+pub(crate) fn path_size(fs: &dyn FileSystem, path: &Path) -> std::io::Result<u64> {
+    let metadata = fs.symlink_metadata(path)?;
+    if metadata.is_dir() {
+        folder_size(fs, path)
+    } else {
+        Ok(metadata.len)
+    }
+}
+
+thread_local! {
+    /// Folder sizes measured during the current `clean_disk` call on this
+    /// thread, with the folder's modification time when each was measured;
+    /// `None` outside one.
+    static SIZE_CACHE: RefCell<Option<HashMap<PathBuf, (SystemTime, u64)>>> =
+        const { RefCell::new(None) };
+}
+
+/// Starts an empty size cache for `cached_path_size`, dropping any left over
+/// from an earlier call that failed.
+pub(crate) fn start_size_cache() {
+    SIZE_CACHE.with(|cache| *cache.borrow_mut() = Some(HashMap::new()));
+}
+
+pub(crate) fn stop_size_cache() {
+    SIZE_CACHE.with(|cache| *cache.borrow_mut() = None);
+}
+
+/// `path_size`, but a folder measured earlier in the same `clean_disk` call
+/// is not walked again while its modification time is unchanged. That time
+/// only moves when direct children are added, removed or renamed, so growth
+/// deeper down goes unnoticed until the next call; that is close enough to
+/// rank candidates, and deletions still measure for real.
+pub(crate) fn cached_path_size(fs: &dyn FileSystem, path: &Path) -> std::io::Result<u64> {
+    let metadata = fs.symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len);
+    }
+    let Some(modified) = metadata.modified else {
+        return folder_size(fs, path);
+    };
+    let cached = SIZE_CACHE.with(|cache| {
+        cache
+            .borrow()
+            .as_ref()
+            .and_then(|cache| cache.get(path))
+            .filter(|(time, _)| *time == modified)
+            .map(|(_, size)| *size)
+    });
+    if let Some(size) = cached {
+        return Ok(size);
+    }
+    let size = folder_size(fs, path)?;
+    SIZE_CACHE.with(|cache| {
+        if let Some(cache) = cache.borrow_mut().as_mut() {
+            cache.insert(path.to_path_buf(), (modified, size));
+        }
+    });
+    Ok(size)
+}
+
+fn entry_size(fs: &dyn FileSystem, entry: &Path) -> std::io::Result<u64> {
+    match path_size(fs, entry) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        result => result,
+    }
+}
+
+/// Unit family used by `format_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeUnits {
+    /// Powers of 1024: KiB, MiB, GiB, ...
+    Binary,
+    /// Powers of 1000: kB, MB, GB, ...
+    Decimal,
+}
+
+impl SizeUnits {
+    pub(crate) fn parse(value: &str) -> Option<SizeUnits> {
+        match value.trim().to_lowercase().as_str() {
+            "binary" | "iec" => Some(SizeUnits::Binary),
+            "decimal" | "si" => Some(SizeUnits::Decimal),
+            _ => None,
+        }
+    }
+}
+
+/// Process-wide `SIZE_UNITS`, set once from `Config` at startup.
+static DECIMAL_UNITS: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_size_units(units: SizeUnits) {
+    DECIMAL_UNITS.store(units == SizeUnits::Decimal, Ordering::Relaxed);
+}
+
+/// Formats a byte count in the configured `SIZE_UNITS`, e.g. `3.20 GiB`.
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    let units = if DECIMAL_UNITS.load(Ordering::Relaxed) {
+        SizeUnits::Decimal
+    } else {
+        SizeUnits::Binary
+    };
+    format_bytes_in(bytes, units)
+}
+
+/// Formats a byte count with two decimals in the largest unit that keeps the
+/// value at or above 1, e.g. `1.50 GiB` or `1.61 GB`.
+pub(crate) fn format_bytes_in(bytes: u64, units: SizeUnits) -> String {
+    let (base, names) = match units {
+        SizeUnits::Binary => (1024.0, ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"]),
+        SizeUnits::Decimal => (1000.0, ["kB", "MB", "GB", "TB", "PB", "EB"]),
+    };
+
+    if (bytes as f64) < base {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / base;
+    let mut unit = 0;
+    // Move up early when two decimals would round to `1024.00 KiB`.
+    while value >= base - 0.005 && unit < names.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, names[unit])
+}
+
+/// Parses a size such as `1048576`, `50GiB`, `1.5 TB` or `500M`. Units are
+/// case-insensitive; `kB`/`MB`/... are powers of 1000, `KiB`/`MiB`/... and
+/// the single letters `K`/`M`/`G`/`T`/`P`/`E` are powers of 1024.
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let invalid = || format!("invalid size {:?}", value);
+
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "p" | "pib" => 1 << 50,
+        "e" | "eib" => 1 << 60,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "pb" => 1_000_000_000_000_000,
+        "eb" => 1_000_000_000_000_000_000,
+        _ => return Err(invalid()),
+    };
+
+    // Whole numbers are multiplied exactly; only fractions go through f64.
+    if let Ok(whole) = number.parse::<u64>() {
+        return whole
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("size {:?} is too large", value));
+    }
+    let fraction: f64 = number
+        .parse()
+        .ok()
+        .filter(|n: &f64| n.is_finite())
+        .ok_or_else(invalid)?;
+    let bytes = (fraction * multiplier as f64).round();
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("size {:?} is too large", value));
+    }
+    Ok(bytes as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MemoryFileSystem;
+    use std::time::Duration;
+
+    /// Builds `width` subfolders per level, `depth` levels deep, with two
+    /// files of distinct sizes in every folder, and returns their total.
+    fn grow(tree: &MemoryFileSystem, path: &Path, width: usize, depth: usize) -> u64 {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut total = 0;
+        for (i, name) in ["a.bin", "b.bin"].iter().enumerate() {
+            let size = depth * 7 + i + 1;
+            tree.file(&path.join(name), size, now, 0);
+            total += size as u64;
+        }
+        if depth > 0 {
+            for i in 0..width {
+                total += grow(tree, &path.join(format!("d{}", i)), width, depth - 1);
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn cached_sizes_are_reused_until_the_folder_changes() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let tree = MemoryFileSystem::new();
+        let folder = Path::new("/srv/cam1/a");
+        tree.file(&folder.join("sub/video"), 100, now, 10);
+        tree.set_age(folder, now, 10);
+
+        start_size_cache();
+        let walked = tree.read_dir_calls();
+        assert_eq!(cached_path_size(&tree, folder).unwrap(), 100);
+        assert_eq!(tree.read_dir_calls() - walked, 2, "a and a/sub");
+        let walked = tree.read_dir_calls();
+        assert_eq!(cached_path_size(&tree, folder).unwrap(), 100);
+        assert_eq!(tree.read_dir_calls(), walked, "served from the cache");
+
+        // Growth below a direct child leaves the folder's mtime alone.
+        tree.file(&folder.join("sub/extra"), 50, now, 0);
+        tree.set_age(folder, now, 10);
+        assert_eq!(cached_path_size(&tree, folder).unwrap(), 100);
+        tree.set_age(folder, now, 0);
+        assert_eq!(cached_path_size(&tree, folder).unwrap(), 150);
+        assert_eq!(tree.read_dir_calls() - walked, 2);
+
+        // Without a cache every lookup walks the folder.
+        stop_size_cache();
+        let walked = tree.read_dir_calls();
+        assert_eq!(cached_path_size(&tree, folder).unwrap(), 150);
+        assert_eq!(cached_path_size(&tree, folder).unwrap(), 150);
+        assert_eq!(tree.read_dir_calls() - walked, 4);
+    }
+
+    #[test]
+    fn format_bytes_in_picks_the_largest_unit_at_or_above_one() {
+        let binary = |bytes| format_bytes_in(bytes, SizeUnits::Binary);
+        let decimal = |bytes| format_bytes_in(bytes, SizeUnits::Decimal);
+        assert_eq!(binary(0), "0 B");
+        assert_eq!(binary(1023), "1023 B");
+        assert_eq!(binary(1024), "1.00 KiB");
+        assert_eq!(binary(1536), "1.50 KiB");
+        // Would be 1024.00 KiB at two decimals.
+        assert_eq!(binary((1 << 20) - 1), "1.00 MiB");
+        assert_eq!(binary(3 << 30), "3.00 GiB");
+        assert_eq!(binary(u64::MAX), "16.00 EiB");
+        assert_eq!(decimal(999), "999 B");
+        assert_eq!(decimal(1000), "1.00 kB");
+        assert_eq!(decimal(1_610_000_000), "1.61 GB");
+    }
+
+    #[test]
+    fn parse_size_accepts_plain_numbers_and_both_unit_families() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size(" 1048576 "), Ok(1 << 20));
+        assert_eq!(parse_size("50GiB"), Ok(50 << 30));
+        assert_eq!(parse_size("50gib"), Ok(50 << 30));
+        assert_eq!(parse_size("500M"), Ok(500 << 20));
+        assert_eq!(parse_size("1.5 TB"), Ok(1_500_000_000_000));
+        assert_eq!(parse_size("2kB"), Ok(2000));
+        assert_eq!(parse_size("7 b"), Ok(7));
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn parse_size_rejects_malformed_and_oversized_values() {
+        for value in ["", ".", "1.2.3", "GiB", "-1", "5 lb", "1e3", "NaN"] {
+            assert!(parse_size(value).is_err(), "{:?}", value);
+        }
+        assert_eq!(
+            parse_size("16EiB"),
+            Err("size \"16EiB\" is too large".to_string())
+        );
+        assert!(parse_size("16.5EiB").is_err());
+    }
+
+    #[test]
+    fn formatted_sizes_parse_back_to_the_same_bytes() {
+        // Sizes two decimals of their unit represent exactly.
+        let binary = [0, 1, 1023, 1024, 1536, 5 << 30, 3 << 39, 1 << 60];
+        let decimal = [0, 999, 1000, 1_500_000, 1_610_000_000, 25 * 10u64.pow(16)];
+        for (units, sizes) in [
+            (SizeUnits::Binary, &binary[..]),
+            (SizeUnits::Decimal, &decimal[..]),
+        ] {
+            for &bytes in sizes {
+                let formatted = format_bytes_in(bytes, units);
+                assert_eq!(parse_size(&formatted), Ok(bytes), "{}", formatted);
+            }
+        }
+    }
+
+    #[test]
+    fn folder_size_sums_a_deep_wide_tree() {
+        let tree = MemoryFileSystem::new();
+        let root = Path::new("/data");
+        let expected = grow(&tree, root, 3, 6);
+        assert_eq!(folder_size(&tree, root).unwrap(), expected);
+        assert_eq!(
+            path_size(&tree, &root.join("d0/d1/a.bin")).unwrap(),
+            4 * 7 + 1
+        );
+    }
+
+    #[test]
+    fn folder_size_walks_a_long_chain_of_folders() {
+        let tree = MemoryFileSystem::new();
+        let root = Path::new("/data");
+        let expected = grow(&tree, root, 1, 200);
+        assert_eq!(folder_size(&tree, root).unwrap(), expected);
+    }
+
+    #[test]
+    fn folder_size_ignores_vanished_entries_and_does_not_follow_links() {
+        let tree = MemoryFileSystem::new();
+        let root = Path::new("/data");
+        let expected = grow(&tree, root, 2, 2);
+        tree.ghost(&root.join("d1/gone.bin"));
+        tree.ghost(&root.join("d0/d0/gone"));
+        // The link itself is counted, not the folder it points at.
+        tree.symlink(&root.join("d0"), &root.join("link")).unwrap();
+        let link = tree.symlink_metadata(&root.join("link")).unwrap().len;
+        assert_eq!(folder_size(&tree, root).unwrap(), expected + link);
+    }
+}
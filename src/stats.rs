@@ -0,0 +1,86 @@
+//! The `--stats` report: how deletion candidates are spread across ages, to
+//! help choose `MIN_AGE_DAYS` and `KEEP_RECENT`.
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::cleanup::{find_selection_dirs, resolve_depth};
+use crate::clock;
+use crate::config::Config;
+use crate::filesystem::FileSystem;
+use crate::scan::{dated_candidates, skip_entry};
+use crate::size::{format_bytes, path_size};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Upper bound of each age bucket and its label; the last bucket is open.
+const BUCKETS: [(Option<u64>, &str); 4] = [
+    (Some(DAY), "<1d"),
+    (Some(7 * DAY), "1-7d"),
+    (Some(30 * DAY), "7-30d"),
+    (None, ">30d"),
+];
+
+pub(crate) fn print_stats(config: &Config, fs: &dyn FileSystem) -> std::io::Result<()> {
+    for base_dir in &config.base_dirs {
+        print_directory_stats(config.for_dir(base_dir), fs, base_dir)?;
+    }
+    Ok(())
+}
+
+/// Buckets every candidate `clean_disk` could pick under `base_dir` by the
+/// age of its `SORT_BY` timestamp. Candidates held back by `KEEP_RECENT`,
+/// `MIN_AGE_DAYS` or the exclusions are not counted.
+fn print_directory_stats(
+    config: &Config,
+    fs: &dyn FileSystem,
+    base_dir: &str,
+) -> std::io::Result<()> {
+    let mut selection_dirs = Vec::new();
+    find_selection_dirs(
+        config,
+        fs,
+        Path::new(base_dir),
+        resolve_depth(config, fs, base_dir)?,
+        &mut selection_dirs,
+    )?;
+
+    let now = clock::now();
+    let mut counts = [0usize; BUCKETS.len()];
+    let mut sizes = [0u64; BUCKETS.len()];
+    for dir in &selection_dirs {
+        let dated = dated_candidates(&dir.to_string_lossy(), config, fs, config.target);
+        let Some(dated) = skip_entry(config, dir, dated)? else {
+            continue;
+        };
+        for (time, path) in dated {
+            let Some(size) = skip_entry(config, &path, path_size(fs, &path))? else {
+                continue;
+            };
+            // Timestamps in the future count as brand new.
+            let age = now.duration_since(time).unwrap_or(Duration::ZERO).as_secs();
+            let bucket = BUCKETS
+                .iter()
+                .position(|(limit, _)| limit.is_none_or(|limit| age < limit))
+                .unwrap_or(BUCKETS.len() - 1);
+            counts[bucket] += 1;
+            sizes[bucket] += size;
+        }
+    }
+
+    println!(
+        "[{}] {} candidates ({}) by age:",
+        base_dir,
+        counts.iter().sum::<usize>(),
+        format_bytes(sizes.iter().sum())
+    );
+    for (index, (_, label)) in BUCKETS.iter().enumerate() {
+        println!(
+            "  {:<6} {:>6} {:>12}",
+            label,
+            counts[index],
+            format_bytes(sizes[index])
+        );
+    }
+    Ok(())
+}
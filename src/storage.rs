@@ -0,0 +1,580 @@
+//! Free-space lookup for the disk holding a directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use sysinfo::{Disk, DiskExt, RefreshKind, SystemExt};
+
+use crate::config::Config;
+use crate::size::format_bytes;
+
+/// Returns the free-space percentage of a disk: `available` as a share of
+/// `total`, so 100 total / 10 available is 10.0% free. Disks that report no
+/// capacity at all (some pseudo-filesystems do) yield `None`.
+pub(crate) fn free_percentage(total: u64, available: u64) -> Option<f64> {
+    if total == 0 {
+        return None;
+    }
+    Some((available as f64 / total as f64) * 100.0)
+}
+
+/// How `START_THRESHOLD` and `STOP_THRESHOLD` are read: as free-space or
+/// used-space percentages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdMode {
+    Free,
+    /// `START_THRESHOLD=80` starts cleanup above 80% used, i.e. below 20%
+    /// free, so here the start threshold is the larger one.
+    Used,
+}
+
+impl ThresholdMode {
+    pub(crate) fn parse(value: &str) -> Option<ThresholdMode> {
+        match value.trim().to_lowercase().as_str() {
+            "free" => Some(ThresholdMode::Free),
+            "used" => Some(ThresholdMode::Used),
+            _ => None,
+        }
+    }
+}
+
+/// Which disk's free space gates cleanup when a base directory sits on a
+/// union filesystem (overlayfs, mergerfs) spread over several disks. For a
+/// base directory on a single disk every setting behaves like `Primary`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetDisk {
+    /// The disk the base directory's path resolves to, as without a union.
+    Primary,
+    /// Whichever backing disk has the smallest free percentage.
+    Fullest,
+    /// The backing disk with this device name or mount point.
+    Named(String),
+}
+
+impl TargetDisk {
+    pub(crate) fn parse(value: &str) -> TargetDisk {
+        match value.trim() {
+            "" | "primary" => TargetDisk::Primary,
+            "fullest" => TargetDisk::Fullest,
+            name => TargetDisk::Named(name.to_string()),
+        }
+    }
+}
+
+/// One disk behind a base directory, as listed by `Cleaner::backing_disks`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackingDisk {
+    pub name: String,
+    pub mount_point: PathBuf,
+    pub total: u64,
+    pub free: FreeSpace,
+}
+
+/// Free space on the disk holding a base directory, both as a share of its
+/// capacity and in bytes, so percentage and byte thresholds can be checked
+/// against the same lookup.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FreeSpace {
+    pub percentage: f64,
+    pub available: u64,
+}
+
+impl FreeSpace {
+    /// Whether cleanup should start: below `START_FREE_BYTES` when set,
+    /// otherwise below the `START_THRESHOLD` percentage.
+    pub(crate) fn below_start(&self, config: &Config) -> bool {
+        match config.start_free_bytes {
+            Some(bytes) => self.available < bytes,
+            None => self.percentage < config.start_threshold,
+        }
+    }
+
+    /// Whether cleanup may stop: above `STOP_FREE_BYTES` when set, otherwise
+    /// above the `STOP_THRESHOLD` percentage.
+    pub(crate) fn above_stop(&self, config: &Config) -> bool {
+        match config.stop_free_bytes {
+            Some(bytes) => self.available > bytes,
+            None => self.percentage > config.stop_threshold,
+        }
+    }
+}
+
+/// Bytes that must still be freed on a disk with `total` bytes, `available`
+/// of them free, before `FreeSpace::above_stop` holds.
+pub(crate) fn bytes_to_stop(config: &Config, total: u64, available: u64) -> u64 {
+    let target = match config.stop_free_bytes {
+        Some(bytes) => bytes.saturating_add(1),
+        None => (total as f64 * config.stop_threshold / 100.0).floor() as u64 + 1,
+    };
+    target.saturating_sub(available)
+}
+
+impl std::fmt::Display for FreeSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2}% ({})",
+            self.percentage,
+            format_bytes(self.available)
+        )
+    }
+}
+
+/// Brings a path into the form used for mount point matching. On Windows,
+/// `fs::canonicalize` returns verbatim paths such as `\\?\C:\data` while
+/// disks report `C:\`, and drive letters compare case-insensitively, so the
+/// verbatim prefix is dropped and the path lowercased.
+#[cfg(windows)]
+fn normalize_path(path: &Path) -> PathBuf {
+    let path = path.to_string_lossy();
+    let path = match path.strip_prefix(r"\\?\UNC\") {
+        Some(rest) => format!(r"\\{}", rest),
+        None => path.strip_prefix(r"\\?\").unwrap_or(&path).to_string(),
+    };
+    PathBuf::from(path.to_lowercase())
+}
+
+#[cfg(not(windows))]
+fn normalize_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Returns the index of the mount point that contains `path` with the longest
+/// prefix, so nested mounts such as `/data/logs` win over `/data` and `/`.
+pub(crate) fn find_mount_point<P: AsRef<Path>>(path: &Path, mount_points: &[P]) -> Option<usize> {
+    let path = normalize_path(path);
+    mount_points
+        .iter()
+        .map(|mount_point| normalize_path(mount_point.as_ref()))
+        .enumerate()
+        .filter(|(_, mount_point)| path.starts_with(mount_point))
+        .max_by_key(|(_, mount_point)| mount_point.components().count())
+        .map(|(index, _)| index)
+}
+
+/// Undoes the octal escapes `/proc/self/mounts` uses for spaces, tabs,
+/// newlines and backslashes in paths.
+#[cfg(target_os = "linux")]
+fn unescape_mount_field(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let code = rest
+            .get(index + 1..index + 4)
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(code as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// Returns the disks behind the union filesystem holding `base_dir`: those
+/// holding the `upperdir` and `lowerdir` layers of an overlay, or the
+/// source directories of a mergerfs pool, each listed once. Other
+/// filesystems, and branches not visible from this mount namespace, yield
+/// nothing.
+#[cfg(target_os = "linux")]
+fn union_disks(base_dir: &str) -> Vec<BackingDisk> {
+    let Ok(mounts) = fs::read_to_string("/proc/self/mounts") else {
+        return Vec::new();
+    };
+    // Device, mount point, filesystem type and options.
+    let mounts: Vec<Vec<String>> = mounts
+        .lines()
+        .map(|line| line.split(' ').map(unescape_mount_field).collect())
+        .filter(|fields: &Vec<String>| fields.len() >= 4)
+        .collect();
+    let mount_points: Vec<&Path> = mounts.iter().map(|fields| Path::new(&fields[1])).collect();
+
+    let base_path =
+        fs::canonicalize(base_dir).unwrap_or_else(|_| Path::new(base_dir).to_path_buf());
+    let Some(union) = find_mount_point(&base_path, &mount_points) else {
+        return Vec::new();
+    };
+    let branches: Vec<&str> = match mounts[union][2].as_str() {
+        "overlay" => mounts[union][3]
+            .split(',')
+            .filter_map(|option| {
+                option
+                    .strip_prefix("upperdir=")
+                    .or_else(|| option.strip_prefix("lowerdir="))
+            })
+            .flat_map(|dirs| dirs.split(':'))
+            .collect(),
+        "fuse.mergerfs" => mounts[union][0].split(':').collect(),
+        _ => Vec::new(),
+    };
+
+    let mut seen = Vec::new();
+    let mut disks = Vec::new();
+    for branch in branches {
+        let Ok(branch) = fs::canonicalize(branch) else {
+            continue;
+        };
+        let Some(index) = find_mount_point(&branch, &mount_points) else {
+            continue;
+        };
+        if index == union || seen.contains(&index) {
+            continue;
+        }
+        seen.push(index);
+        let Some((total, available)) = statvfs_space(&mounts[index][1]) else {
+            continue;
+        };
+        if let Some(percentage) = free_percentage(total, available) {
+            disks.push(BackingDisk {
+                name: mounts[index][0].clone(),
+                mount_point: PathBuf::from(&mounts[index][1]),
+                total,
+                free: FreeSpace {
+                    percentage,
+                    available,
+                },
+            });
+        }
+    }
+    disks
+}
+
+#[cfg(not(target_os = "linux"))]
+fn union_disks(_base_dir: &str) -> Vec<BackingDisk> {
+    Vec::new()
+}
+
+/// Picks the `TARGET_DISK` among the disks behind a union filesystem.
+fn select_target_disk<'a>(
+    disks: &'a [BackingDisk],
+    target: &TargetDisk,
+) -> Option<&'a BackingDisk> {
+    match target {
+        TargetDisk::Primary => None,
+        TargetDisk::Fullest => disks
+            .iter()
+            .min_by(|a, b| a.free.percentage.total_cmp(&b.free.percentage)),
+        TargetDisk::Named(name) => {
+            let mount_point = normalize_path(Path::new(name));
+            disks
+                .iter()
+                .find(|disk| disk.name == *name || normalize_path(&disk.mount_point) == mount_point)
+        }
+    }
+}
+
+/// Finds the disk holding `base_dir`: the one named by `DISK_NAME` or
+/// mounted at `MOUNT_POINT` when either is set, otherwise the disk with the
+/// longest mount point containing the directory.
+fn find_disk<'a>(sys: &'a sysinfo::System, config: &Config, base_dir: &str) -> Option<&'a Disk> {
+    if let Some(name) = &config.disk_name {
+        return sys
+            .disks()
+            .iter()
+            .find(|disk| disk.name().to_string_lossy() == name.as_str());
+    }
+    if let Some(mount_point) = &config.mount_point {
+        let mount_point = normalize_path(Path::new(mount_point));
+        return sys
+            .disks()
+            .iter()
+            .find(|disk| normalize_path(disk.mount_point()) == mount_point);
+    }
+
+    let base_path =
+        fs::canonicalize(base_dir).unwrap_or_else(|_| Path::new(base_dir).to_path_buf());
+    let disks: Vec<_> = sys
+        .disks()
+        .iter()
+        .filter(|disk| disk.total_space() > 0)
+        .collect();
+    let mount_points: Vec<&Path> = disks.iter().map(|disk| disk.mount_point()).collect();
+
+    find_mount_point(&base_path, &mount_points).map(|index| disks[index])
+}
+
+/// Queries the filesystem holding `path` directly with `statvfs`, for
+/// mounts sysinfo does not list, such as overlayfs in containers or some NFS
+/// shares. Returns total and available bytes like `Disks::disk_space`.
+#[cfg(unix)]
+fn statvfs_space(path: &str) -> Option<(u64, u64)> {
+    let path = std::ffi::CString::new(path).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let fragment = stat.f_frsize as u64;
+    Some((
+        (stat.f_blocks as u64).saturating_mul(fragment),
+        (stat.f_bavail as u64).saturating_mul(fragment),
+    ))
+}
+
+#[cfg(not(unix))]
+fn statvfs_space(_path: &str) -> Option<(u64, u64)> {
+    None
+}
+
+/// Disk information kept for the life of a `Cleaner`. Creating a
+/// `sysinfo::System` with `new_all` enumerates every process as well as the
+/// disks, so one instance is built up front and only its disk data is
+/// refreshed before each lookup.
+pub(crate) struct Disks {
+    sys: Mutex<sysinfo::System>,
+}
+
+impl Disks {
+    pub(crate) fn new() -> Disks {
+        Disks {
+            sys: Mutex::new(sysinfo::System::new_with_specifics(
+                RefreshKind::new().with_disks_list(),
+            )),
+        }
+    }
+}
+
+/// Source of the free-space figures cleanup decisions are based on. The
+/// cleanup and planning code only sees this trait, so a
+/// [`Cleaner`](crate::Cleaner) can run against fixed figures as well as the
+/// real disks sysinfo reports.
+pub trait DiskInfo: Send + Sync {
+    /// Returns the total and available bytes of the disk holding `base_dir`.
+    fn disk_space(&self, config: &Config, base_dir: &str) -> Option<(u64, u64)>;
+
+    /// Returns the free space of the disk holding `base_dir`. The thresholds
+    /// are compared against this value.
+    fn check_storage(&self, config: &Config, base_dir: &str) -> Option<FreeSpace> {
+        self.disk_space(config, base_dir)
+            .and_then(|(total, available)| {
+                free_percentage(total, available).map(|percentage| FreeSpace {
+                    percentage,
+                    available,
+                })
+            })
+    }
+
+    /// Returns the available bytes on the disk holding `base_dir`.
+    fn available_bytes(&self, config: &Config, base_dir: &str) -> Option<u64> {
+        self.disk_space(config, base_dir)
+            .map(|(_, available)| available)
+    }
+
+    /// Describes the disk holding `base_dir` for the log, e.g.
+    /// `/dev/sdb1 at /mnt/data`.
+    fn describe_disk(&self, _config: &Config, _base_dir: &str) -> Option<String> {
+        None
+    }
+
+    /// Returns every disk behind the union filesystem holding `base_dir`,
+    /// or nothing when it is on a single disk.
+    fn backing_disks(&self, _base_dir: &str) -> Vec<BackingDisk> {
+        Vec::new()
+    }
+}
+
+impl DiskInfo for Disks {
+    /// When sysinfo lists no disk containing `base_dir`, its filesystem is
+    /// queried directly; a configured `DISK_NAME` or `MOUNT_POINT` that
+    /// matches nothing stays unmatched, and so does a `TARGET_DISK` that
+    /// matches none of the disks behind a union filesystem.
+    fn disk_space(&self, config: &Config, base_dir: &str) -> Option<(u64, u64)> {
+        if config.target_disk != TargetDisk::Primary {
+            let disks = union_disks(base_dir);
+            if !disks.is_empty() {
+                return select_target_disk(&disks, &config.target_disk)
+                    .map(|disk| (disk.total, disk.free.available));
+            }
+        }
+        let mut sys = self
+            .sys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        sys.refresh_disks();
+        if find_disk(&sys, config, base_dir).is_none() {
+            // The disk may have been mounted since the list was built.
+            sys.refresh_disks_list();
+        }
+        match find_disk(&sys, config, base_dir) {
+            Some(disk) => Some((disk.total_space(), disk.available_space())),
+            None if config.disk_name.is_none() && config.mount_point.is_none() => {
+                statvfs_space(base_dir)
+            }
+            None => None,
+        }
+    }
+
+    fn describe_disk(&self, config: &Config, base_dir: &str) -> Option<String> {
+        if config.target_disk != TargetDisk::Primary {
+            let disks = union_disks(base_dir);
+            if !disks.is_empty() {
+                return select_target_disk(&disks, &config.target_disk).map(|disk| {
+                    format!("{} at {}", disk.name, disk.mount_point.to_string_lossy())
+                });
+            }
+        }
+        let sys = self
+            .sys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match find_disk(&sys, config, base_dir) {
+            Some(disk) => Some(format!(
+                "{} at {}",
+                disk.name().to_string_lossy(),
+                disk.mount_point().to_string_lossy()
+            )),
+            None if config.disk_name.is_none() && config.mount_point.is_none() => {
+                statvfs_space(base_dir).map(|_| "unlisted filesystem (statvfs)".to_string())
+            }
+            None => None,
+        }
+    }
+
+    fn backing_disks(&self, base_dir: &str) -> Vec<BackingDisk> {
+        union_disks(base_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_percentage_is_the_available_share() {
+        assert_eq!(free_percentage(100, 10), Some(10.0));
+        assert_eq!(free_percentage(100, 100), Some(100.0));
+        assert_eq!(free_percentage(4, 1), Some(25.0));
+        assert_eq!(free_percentage(100, 0), Some(0.0));
+    }
+
+    #[test]
+    fn free_percentage_of_a_disk_without_capacity_is_unknown() {
+        assert_eq!(free_percentage(0, 0), None);
+        assert_eq!(free_percentage(0, 10), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn statvfs_space_measures_the_filesystem_holding_a_path() {
+        let dir = crate::test_support::TempDir::new("statvfs");
+        let (total, available) = statvfs_space(&dir.path().to_string_lossy()).unwrap();
+        assert!(available > 0, "{} of {}", available, total);
+        assert!(total >= available, "{} of {}", available, total);
+
+        let missing = dir.path().join("missing");
+        assert_eq!(statvfs_space(&missing.to_string_lossy()), None);
+        assert_eq!(statvfs_space("bad\0path"), None);
+    }
+
+    #[test]
+    fn free_byte_thresholds_beat_the_percentages() {
+        let dir = crate::test_support::TempDir::new("free-bytes");
+        let space = |percentage: f64, available: u64| FreeSpace {
+            percentage,
+            available,
+        };
+        let percent = Config::for_test(dir.path(), "start_threshold = 20\nstop_threshold = 25");
+        assert!(space(19.9, 0).below_start(&percent));
+        assert!(!space(20.0, 0).below_start(&percent));
+        assert!(!space(25.0, u64::MAX).above_stop(&percent));
+        assert!(space(25.1, 0).above_stop(&percent));
+
+        // With both set the byte thresholds decide, whatever the percentage.
+        let bytes = Config::for_test(
+            dir.path(),
+            "start_threshold = 20\nstop_threshold = 25\n\
+             start_free_bytes = \"300\"\nstop_free_bytes = \"500\"",
+        );
+        assert!(space(90.0, 299).below_start(&bytes));
+        assert!(!space(1.0, 300).below_start(&bytes));
+        assert!(!space(90.0, 500).above_stop(&bytes));
+        assert!(space(1.0, 501).above_stop(&bytes));
+        assert_eq!(bytes_to_stop(&bytes, 2000, 400), 101);
+        assert_eq!(bytes_to_stop(&bytes, 2000, 501), 0);
+        assert_eq!(bytes_to_stop(&percent, 2000, 400), 101);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn select_target_disk_picks_among_the_backing_disks() {
+        let disk = |name: &str, mount_point: &str, percentage: f64| BackingDisk {
+            name: name.to_string(),
+            mount_point: PathBuf::from(mount_point),
+            total: 1000,
+            free: FreeSpace {
+                percentage,
+                available: (percentage * 10.0) as u64,
+            },
+        };
+        let disks = [
+            disk("/dev/sdb1", "/mnt/disk1", 40.0),
+            disk("/dev/sdc1", "/mnt/disk2", 5.0),
+            disk("/dev/sdd1", "/mnt/disk3", 25.0),
+        ];
+        let select = |target: &str| {
+            select_target_disk(&disks, &TargetDisk::parse(target)).map(|disk| disk.name.as_str())
+        };
+
+        assert_eq!(select("primary"), None);
+        assert_eq!(select("fullest"), Some("/dev/sdc1"));
+        assert_eq!(select("/dev/sdd1"), Some("/dev/sdd1"));
+        assert_eq!(select("/mnt/disk1"), Some("/dev/sdb1"));
+        assert_eq!(select("/mnt/disk1/"), Some("/dev/sdb1"));
+        assert_eq!(select("/dev/sde1"), None);
+        assert_eq!(select("/mnt"), None);
+        assert_eq!(select_target_disk(&[], &TargetDisk::Fullest), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_mount_point_prefers_the_longest_containing_mount() {
+        let mounts = ["/", "/data", "/data/logs", "/data2"];
+        let find = |path: &str| find_mount_point(Path::new(path), &mounts);
+
+        assert_eq!(find("/data/logs/cam1"), Some(2));
+        assert_eq!(find("/data/log"), Some(1));
+        assert_eq!(find("/data"), Some(1));
+        // Whole components only: /data2 is not below /data.
+        assert_eq!(find("/data2/x"), Some(3));
+        assert_eq!(find("/home/user"), Some(0));
+        assert_eq!(find_mount_point(Path::new("/home"), &["/data"]), None);
+        assert_eq!(find_mount_point::<&str>(Path::new("/home"), &[]), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn normalize_path_drops_the_verbatim_prefix_and_case() {
+        assert_eq!(
+            normalize_path(Path::new(r"\\?\C:\Data\Logs")),
+            PathBuf::from(r"c:\data\logs")
+        );
+        assert_eq!(
+            normalize_path(Path::new(r"\\?\UNC\Server\Share\cam1")),
+            PathBuf::from(r"\\server\share\cam1")
+        );
+        assert_eq!(normalize_path(Path::new(r"D:\")), PathBuf::from(r"d:\"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn find_mount_point_matches_drives_case_insensitively() {
+        let mounts = [r"C:\", r"D:\", r"C:\Data\Logs"];
+        let find = |path: &str| find_mount_point(Path::new(path), &mounts);
+
+        assert_eq!(find(r"C:\data\cam1"), Some(0));
+        assert_eq!(find(r"c:\DATA\LOGS\cam1"), Some(2));
+        assert_eq!(find(r"\\?\C:\data\logs"), Some(2));
+        assert_eq!(find(r"\\?\d:\recordings"), Some(1));
+        assert_eq!(find(r"E:\recordings"), None);
+        assert_eq!(
+            find_mount_point(Path::new(r"C:\data\logs"), &[r"C:\"]),
+            Some(0)
+        );
+    }
+}
@@ -0,0 +1,568 @@
+//! Helpers shared by the unit tests.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::clock::{self, FixedClock};
+use crate::config::Config;
+use crate::filesystem::{EntryKind, FileSystem, Metadata};
+use crate::storage::DiskInfo;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh directory under the system temp dir, removed with everything in
+/// it when dropped.
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "util-test-{}-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes `contents` to `relative`, creating parent directories, and
+    /// dates the file `days_old` days before `now`.
+    pub(crate) fn file(
+        &self,
+        relative: &str,
+        contents: &[u8],
+        now: SystemTime,
+        days_old: u64,
+    ) -> PathBuf {
+        let path = self.path.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        set_age(&path, now, days_old);
+        path
+    }
+
+    pub(crate) fn dir(&self, relative: &str) -> PathBuf {
+        let path = self.path.join(relative);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
+
+/// Sets the access and modification times of `path` to `days_old` days
+/// before `now`.
+pub(crate) fn set_age(path: &Path, now: SystemTime, days_old: u64) {
+    let time = now - Duration::from_secs(days_old * 24 * 60 * 60);
+    let times = fs::FileTimes::new().set_accessed(time).set_modified(time);
+    fs::File::open(path).unwrap().set_times(times).unwrap();
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+enum Content {
+    Dir,
+    File(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+struct Node {
+    content: Content,
+    modified: SystemTime,
+    accessed: Option<SystemTime>,
+    created: Option<SystemTime>,
+}
+
+impl Node {
+    fn new(content: Content) -> Node {
+        let now = clock::now();
+        Node {
+            content,
+            modified: now,
+            accessed: Some(now),
+            created: None,
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        let (kind, len) = match &self.content {
+            Content::Dir => (EntryKind::Dir, 4096),
+            Content::File(data) => (EntryKind::File, data.len() as u64),
+            Content::Symlink(target) => (EntryKind::Symlink, target.as_os_str().len() as u64),
+        };
+        Metadata {
+            kind,
+            len,
+            modified: Some(self.modified),
+            accessed: self.accessed,
+            created: self.created,
+            device: Some(0),
+            mode: Some(if kind == EntryKind::Dir { 0o755 } else { 0o644 }),
+        }
+    }
+}
+
+type Nodes = BTreeMap<PathBuf, Node>;
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found", path.to_string_lossy()),
+    )
+}
+
+fn error(kind: io::ErrorKind, path: &Path) -> io::Error {
+    io::Error::new(kind, path.to_string_lossy().to_string())
+}
+
+/// Paths of the entries directly inside `dir`, sorted.
+fn children(nodes: &Nodes, dir: &Path) -> Vec<PathBuf> {
+    nodes
+        .range(dir.to_path_buf()..)
+        .skip(1)
+        .take_while(|(path, _)| path.starts_with(dir))
+        .filter(|(path, _)| path.parent() == Some(dir))
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Resolves `path` one component at a time, following symlinks and `..`
+/// like `std::fs::canonicalize`; every component must exist.
+fn resolve(nodes: &Nodes, path: &Path) -> io::Result<PathBuf> {
+    let mut resolved = PathBuf::from("/");
+    let mut pending: VecDeque<Component> = path.components().collect();
+    let mut links = 0;
+    while let Some(component) = pending.pop_front() {
+        let name = match component {
+            Component::Normal(name) => name,
+            Component::ParentDir => {
+                resolved.pop();
+                continue;
+            }
+            _ => continue,
+        };
+        let next = resolved.join(name);
+        match &nodes.get(&next).ok_or_else(|| not_found(&next))?.content {
+            Content::Symlink(target) => {
+                links += 1;
+                if links > 40 {
+                    return Err(error(io::ErrorKind::InvalidInput, &next));
+                }
+                if target.is_absolute() {
+                    resolved = PathBuf::from("/");
+                }
+                for component in target.components().rev() {
+                    pending.push_front(component);
+                }
+            }
+            _ => resolved = next,
+        }
+    }
+    Ok(resolved)
+}
+
+/// Records a change to the entries of `path`'s parent, as a real directory's
+/// modification time does.
+fn touch_parent(nodes: &mut Nodes, path: &Path) {
+    if let Some(parent) = path.parent().and_then(|parent| nodes.get_mut(parent)) {
+        parent.modified = clock::now();
+    }
+}
+
+/// Checks that `path` can be created: its parent is a directory and nothing
+/// is there yet.
+fn check_new(nodes: &Nodes, path: &Path) -> io::Result<()> {
+    let parent = path.parent().ok_or_else(|| not_found(path))?;
+    match nodes.get(parent).map(|node| &node.content) {
+        Some(Content::Dir) => {}
+        Some(_) => return Err(error(io::ErrorKind::NotADirectory, parent)),
+        None => return Err(not_found(parent)),
+    }
+    if nodes.contains_key(path) {
+        return Err(error(io::ErrorKind::AlreadyExists, path));
+    }
+    Ok(())
+}
+
+/// A directory tree held in memory, for running cleanup without touching
+/// the disk. Every entry is on device 0 and records no creation time unless
+/// given one with `set_created`, so `SORT_BY=auto` orders by modification
+/// time. Clones share the same tree.
+#[derive(Clone)]
+pub(crate) struct MemoryFileSystem {
+    nodes: Arc<Mutex<Nodes>>,
+    /// Listed by `read_dir` but gone by the time anything else looks.
+    ghosts: Arc<Mutex<Vec<PathBuf>>>,
+    /// Entries that cannot be removed or moved, with the error it gives.
+    pinned: Arc<Mutex<Vec<(PathBuf, io::ErrorKind)>>>,
+    /// `read_dir` calls so far, for tests of what gets walked.
+    read_dirs: Arc<AtomicUsize>,
+    /// A clock moved forward by every removal, for a slow disk.
+    removal_delay: Arc<Mutex<Option<(FixedClock, Duration)>>>,
+}
+
+impl MemoryFileSystem {
+    pub(crate) fn new() -> MemoryFileSystem {
+        let mut nodes = Nodes::new();
+        nodes.insert(PathBuf::from("/"), Node::new(Content::Dir));
+        MemoryFileSystem {
+            nodes: Arc::new(Mutex::new(nodes)),
+            ghosts: Arc::new(Mutex::new(Vec::new())),
+            pinned: Arc::new(Mutex::new(Vec::new())),
+            read_dirs: Arc::new(AtomicUsize::new(0)),
+            removal_delay: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Makes `path` show up in its parent's listing without existing, like
+    /// an entry another process deletes between the listing and its use.
+    pub(crate) fn ghost(&self, path: &Path) {
+        self.ghosts.lock().unwrap().push(path.to_path_buf());
+    }
+
+    /// Makes removing or moving `path`, or anything below it, fail with
+    /// `kind`, like a folder without write permission or one in use.
+    pub(crate) fn pin(&self, path: &Path, kind: io::ErrorKind) {
+        self.pinned.lock().unwrap().push((path.to_path_buf(), kind));
+    }
+
+    /// Makes every `remove_file`, `remove_dir` and `remove_dir_all` take
+    /// `by` on `clock`, like a disk that is slow to delete from.
+    pub(crate) fn slow_removals(&self, clock: &FixedClock, by: Duration) {
+        *self.removal_delay.lock().unwrap() = Some((clock.clone(), by));
+    }
+
+    fn take_removal_time(&self) {
+        if let Some((clock, by)) = &*self.removal_delay.lock().unwrap() {
+            clock.advance(*by);
+        }
+    }
+
+    fn check_unpinned(&self, path: &Path) -> io::Result<()> {
+        match self
+            .pinned
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(pinned, _)| path.starts_with(pinned) || pinned.starts_with(path))
+        {
+            Some((_, kind)) => Err(error(*kind, path)),
+            None => Ok(()),
+        }
+    }
+
+    fn nodes(&self) -> std::sync::MutexGuard<'_, Nodes> {
+        self.nodes.lock().unwrap()
+    }
+
+    /// Creates a file of `size` bytes at `path` and the directories above
+    /// it, and dates the file `days_old` days before `now`.
+    pub(crate) fn file(&self, path: &Path, size: usize, now: SystemTime, days_old: u64) {
+        self.create_dir_all(path.parent().unwrap()).unwrap();
+        self.write(path, &vec![0; size]).unwrap();
+        self.set_age(path, now, days_old);
+    }
+
+    /// Dates `path` `days_old` days before `now`, like `set_age`.
+    pub(crate) fn set_age(&self, path: &Path, now: SystemTime, days_old: u64) {
+        let time = now - Duration::from_secs(days_old * 24 * 60 * 60);
+        self.set_times(path, time, time).unwrap();
+    }
+
+    /// Records a creation time for `path`.
+    pub(crate) fn set_created(&self, path: &Path, created: SystemTime) {
+        self.nodes().get_mut(path).unwrap().created = Some(created);
+    }
+
+    /// Drops the access time of `path`, like a filesystem that does not
+    /// record one.
+    pub(crate) fn clear_accessed(&self, path: &Path) {
+        self.nodes().get_mut(path).unwrap().accessed = None;
+    }
+
+    /// How many times `read_dir` has been called on the tree.
+    pub(crate) fn read_dir_calls(&self) -> usize {
+        self.read_dirs.load(Ordering::Relaxed)
+    }
+
+    /// Bytes held by all files in the tree.
+    pub(crate) fn used_bytes(&self) -> u64 {
+        self.nodes()
+            .values()
+            .map(|node| match &node.content {
+                Content::File(data) => data.len() as u64,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Names of the entries directly inside `dir`, sorted.
+    pub(crate) fn names(&self, dir: &Path) -> Vec<String> {
+        children(&self.nodes(), dir)
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+}
+
+/// Appends to a `MemoryFileSystem` file as it is written.
+struct MemoryFile {
+    nodes: Arc<Mutex<Nodes>>,
+    path: PathBuf,
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let node = nodes
+            .get_mut(&self.path)
+            .ok_or_else(|| not_found(&self.path))?;
+        match &mut node.content {
+            Content::File(data) => data.extend_from_slice(buf),
+            _ => return Err(error(io::ErrorKind::IsADirectory, &self.path)),
+        }
+        node.modified = clock::now();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.read_dirs.fetch_add(1, Ordering::Relaxed);
+        let nodes = self.nodes();
+        let path = resolve(&nodes, path)?;
+        match nodes[&path].content {
+            Content::Dir => {
+                let mut entries = children(&nodes, &path);
+                let ghosts = self.ghosts.lock().unwrap();
+                entries.extend(
+                    ghosts
+                        .iter()
+                        .filter(|ghost| ghost.parent() == Some(&path))
+                        .cloned(),
+                );
+                Ok(entries)
+            }
+            _ => Err(error(io::ErrorKind::NotADirectory, &path)),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let nodes = self.nodes();
+        Ok(nodes[&resolve(&nodes, path)?].metadata())
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.nodes()
+            .get(path)
+            .map(Node::metadata)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.nodes().get(path).map(|node| &node.content) {
+            Some(Content::Symlink(target)) => Ok(target.clone()),
+            Some(_) => Err(error(io::ErrorKind::InvalidInput, path)),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        resolve(&self.nodes(), path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        let nodes = self.nodes();
+        let path = resolve(&nodes, path)?;
+        match &nodes[&path].content {
+            Content::File(data) => Ok(Box::new(io::Cursor::new(data.clone()))),
+            _ => Err(error(io::ErrorKind::IsADirectory, &path)),
+        }
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        let mut nodes = self.nodes();
+        match nodes.get(path).map(|node| &node.content) {
+            Some(Content::File(_)) => {}
+            Some(_) => return Err(error(io::ErrorKind::IsADirectory, path)),
+            None => {
+                check_new(&nodes, path)?;
+                touch_parent(&mut nodes, path);
+            }
+        }
+        nodes.insert(path.to_path_buf(), Node::new(Content::File(Vec::new())));
+        Ok(Box::new(MemoryFile {
+            nodes: Arc::clone(&self.nodes),
+            path: path.to_path_buf(),
+        }))
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        self.symlink_metadata(path).map(|_| ())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+        let mut data = Vec::new();
+        self.open(from)?.read_to_end(&mut data)?;
+        self.write(to, &data)?;
+        Ok(data.len() as u64)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes();
+        check_new(&nodes, path)?;
+        touch_parent(&mut nodes, path);
+        nodes.insert(path.to_path_buf(), Node::new(Content::Dir));
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|dir| !self.nodes().contains_key(*dir))
+            .collect();
+        missing.reverse();
+        for dir in missing {
+            self.create_dir(dir)?;
+        }
+        match self.metadata(path)?.kind {
+            EntryKind::Dir => Ok(()),
+            _ => Err(error(io::ErrorKind::NotADirectory, path)),
+        }
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.check_unpinned(path)?;
+        self.take_removal_time();
+        let mut nodes = self.nodes();
+        match nodes.get(path).map(|node| &node.content) {
+            Some(Content::Dir) => return Err(error(io::ErrorKind::IsADirectory, path)),
+            Some(_) => {}
+            None => return Err(not_found(path)),
+        }
+        nodes.remove(path);
+        touch_parent(&mut nodes, path);
+        Ok(())
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.check_unpinned(path)?;
+        self.take_removal_time();
+        let mut nodes = self.nodes();
+        match nodes.get(path).map(|node| &node.content) {
+            Some(Content::Dir) => {}
+            Some(_) => return Err(error(io::ErrorKind::NotADirectory, path)),
+            None => return Err(not_found(path)),
+        }
+        if !children(&nodes, path).is_empty() {
+            return Err(error(io::ErrorKind::DirectoryNotEmpty, path));
+        }
+        nodes.remove(path);
+        touch_parent(&mut nodes, path);
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.check_unpinned(path)?;
+        self.take_removal_time();
+        let mut nodes = self.nodes();
+        match nodes.get(path).map(|node| &node.content) {
+            Some(Content::Dir) => {}
+            Some(_) => return Err(error(io::ErrorKind::NotADirectory, path)),
+            None => return Err(not_found(path)),
+        }
+        nodes.retain(|entry, _| !entry.starts_with(path));
+        touch_parent(&mut nodes, path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_unpinned(from)?;
+        let mut nodes = self.nodes();
+        if !nodes.contains_key(from) {
+            return Err(not_found(from));
+        }
+        if to.starts_with(from) {
+            return Err(error(io::ErrorKind::InvalidInput, to));
+        }
+        if nodes.contains_key(to) {
+            if !children(&nodes, to).is_empty() {
+                return Err(error(io::ErrorKind::DirectoryNotEmpty, to));
+            }
+            nodes.remove(to);
+        }
+        check_new(&nodes, to)?;
+        let moved: Vec<PathBuf> = nodes
+            .keys()
+            .filter(|entry| entry.starts_with(from))
+            .cloned()
+            .collect();
+        for entry in moved {
+            let node = nodes.remove(&entry).unwrap();
+            let relative = entry.strip_prefix(from).unwrap();
+            let destination = if relative.as_os_str().is_empty() {
+                to.to_path_buf()
+            } else {
+                to.join(relative)
+            };
+            nodes.insert(destination, node);
+        }
+        touch_parent(&mut nodes, from);
+        touch_parent(&mut nodes, to);
+        Ok(())
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes();
+        check_new(&nodes, link)?;
+        touch_parent(&mut nodes, link);
+        nodes.insert(
+            link.to_path_buf(),
+            Node::new(Content::Symlink(target.to_path_buf())),
+        );
+        Ok(())
+    }
+
+    fn set_times(&self, path: &Path, accessed: SystemTime, modified: SystemTime) -> io::Result<()> {
+        let mut nodes = self.nodes();
+        let path = resolve(&nodes, path)?;
+        let node = nodes.get_mut(&path).unwrap();
+        node.accessed = Some(accessed);
+        node.modified = modified;
+        Ok(())
+    }
+}
+
+/// A disk of `total` bytes holding a `MemoryFileSystem`, with `reserved`
+/// bytes used by files outside it. Its free space follows every write and
+/// delete in the tree.
+pub(crate) struct MemoryDisk {
+    pub(crate) fs: MemoryFileSystem,
+    pub(crate) total: u64,
+    pub(crate) reserved: u64,
+}
+
+impl DiskInfo for MemoryDisk {
+    fn disk_space(&self, _config: &Config, _base_dir: &str) -> Option<(u64, u64)> {
+        let used = self.reserved + self.fs.used_bytes();
+        Some((self.total, self.total.saturating_sub(used)))
+    }
+}
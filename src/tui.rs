@@ -0,0 +1,611 @@
+//! The `--tui` dashboard (`tui` feature): free space per base directory, the
+//! latest deletions and a scrolling log, redrawn every second, with keys to
+//! start a cleanup or a dry run by hand. Drawn with plain ANSI escapes on a
+//! raw-mode terminal, so the feature adds no dependencies.
+//!
+//! It covers a deliberately small subset of what a terminal UI library
+//! would:
+//!
+//! - Unix terminals only, put into raw mode through `termios`; elsewhere,
+//!   and when stdin or stdout is not a terminal, `--tui` fails with an error.
+//! - Single-byte keys only: `c`, `d`, `q`, Esc and Ctrl-C. Escape sequences
+//!   such as arrow or function keys, non-ASCII characters and pasted text
+//!   are ignored rather than read as Esc followed by other keys.
+//! - No mouse input and no resize events; the size is queried before every
+//!   frame, falling back to 80x24 when the terminal does not report one.
+//! - One column per character: wide and combining characters in paths can
+//!   make a line run long. Control characters in paths and log messages are
+//!   shown as `?`, so they cannot move the cursor or recolor the screen.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::logging::{format_timestamp, set_logger, write_to_log_file, LogLevel, Logger, Record};
+use crate::size::format_bytes;
+use crate::{clock, signal, Cleaner, Outcome};
+
+/// Number of deletions listed under "Last deletions".
+const DELETIONS_SHOWN: usize = 8;
+/// Log lines kept for the log pane; older ones scroll away.
+const LOG_LINES_KEPT: usize = 500;
+
+/// What the dashboard has seen through its `Logger`.
+#[derive(Default)]
+struct Feed {
+    log: VecDeque<String>,
+    /// Path, bytes and event of the most recent deletions, newest last.
+    deletions: VecDeque<(String, u64, String)>,
+}
+
+/// Collects records for the dashboard and still appends them to the log
+/// file the built-in logger would have written.
+struct FeedLogger {
+    feed: Arc<Mutex<Feed>>,
+}
+
+impl Logger for FeedLogger {
+    fn log(&self, record: &Record) -> std::io::Result<()> {
+        {
+            let mut feed = self
+                .feed
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let time = format_timestamp(clock::now());
+            let level = match record.level {
+                LogLevel::Debug => "DEBUG",
+                LogLevel::Info => "INFO ",
+                LogLevel::Warn => "WARN ",
+                LogLevel::Error => "ERROR",
+            };
+            feed.log
+                .push_back(format!("{} {} {}", &time[11..19], level, record.message));
+            if feed.log.len() > LOG_LINES_KEPT {
+                feed.log.pop_front();
+            }
+            if let ("delete" | "trash" | "archive" | "dry_run_delete", Some(path)) =
+                (record.event, record.path)
+            {
+                feed.deletions.push_back((
+                    path.to_string(),
+                    record.freed_bytes.unwrap_or(0),
+                    record.event.to_string(),
+                ));
+                if feed.deletions.len() > DELETIONS_SHOWN {
+                    feed.deletions.pop_front();
+                }
+            }
+        }
+        write_to_log_file(record)
+    }
+}
+
+/// What a key press asks the dashboard to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Quit,
+    Clean,
+    DryRun,
+}
+
+/// Maps the bytes of one read from the terminal to an action. Only single
+/// bytes are keys; a longer read is an escape sequence, a multi-byte
+/// character or a paste, and does nothing.
+fn action(input: &[u8]) -> Option<Action> {
+    match input {
+        [b'q' | b'Q' | 0x1b | 0x03] => Some(Action::Quit),
+        [b'c' | b'C'] => Some(Action::Clean),
+        [b'd' | b'D'] => Some(Action::DryRun),
+        _ => None,
+    }
+}
+
+/// Puts the terminal into raw mode on the alternate screen, and restores it
+/// when dropped. Reads from stdin return after at most a second even when no
+/// key was pressed, which paces the redraws.
+#[cfg(unix)]
+struct RawTerminal {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawTerminal {
+    fn enter() -> std::io::Result<RawTerminal> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut raw = original;
+        // ISIG is off too, so Ctrl-C arrives as a key and the terminal is
+        // always restored.
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 10;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        print!("\x1b[?1049h\x1b[?25l");
+        std::io::stdout().flush()?;
+        Ok(RawTerminal { original })
+    }
+
+    /// Returns what the input within the next second asks for, if anything.
+    /// The terminal delivers an escape sequence in one read, so reading more
+    /// than a byte is what tells an arrow key apart from Esc.
+    fn read_action(&self) -> std::io::Result<Option<Action>> {
+        let mut input = [0u8; 16];
+        let read =
+            unsafe { libc::read(libc::STDIN_FILENO, input.as_mut_ptr().cast(), input.len()) };
+        match read {
+            0 => Ok(None),
+            1.. => Ok(action(&input[..read as usize])),
+            _ => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    Ok(None)
+                } else {
+                    Err(err)
+                }
+            }
+        }
+    }
+
+    /// Columns and rows of the terminal, or 80x24 when it does not say.
+    fn size(&self) -> (usize, usize) {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0
+            || size.ws_col == 0
+            || size.ws_row == 0
+        {
+            return (80, 24);
+        }
+        (usize::from(size.ws_col), usize::from(size.ws_row))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Cuts `line` to `width` characters, showing control characters as `?`.
+fn fit(line: &str, width: usize) -> String {
+    line.chars()
+        .take(width)
+        .map(|c| if c.is_control() { '?' } else { c })
+        .collect()
+}
+
+/// Renders one frame, `rows` lines of at most `width` characters each.
+fn draw(cleaner: &Cleaner, feed: &Feed, status: &str, width: usize, rows: usize) -> String {
+    let config = cleaner.config();
+    let mut lines = vec![
+        format!(
+            "Disk cleanup{} - {}",
+            if config.instance_id.is_empty() {
+                String::new()
+            } else {
+                format!(" on {}", config.instance_id)
+            },
+            format_timestamp(clock::now())
+        ),
+        String::new(),
+        "Free space".to_string(),
+    ];
+    let label_width = config
+        .base_dirs
+        .iter()
+        .map(|dir| dir.chars().count())
+        .max()
+        .unwrap_or(0)
+        .min(width / 3);
+    let mut bars = Vec::new();
+    for base_dir in &config.base_dirs {
+        let dir_config = config.for_dir(base_dir);
+        let name = format!("  {:<label_width$}", fit(base_dir, label_width));
+        let Some(free) = cleaner.check_storage(base_dir) else {
+            lines.push(format!("{}  disk not found", name));
+            bars.push(None);
+            continue;
+        };
+        let detail = format!(
+            "  {} (start below {}, stop above {})",
+            free,
+            dir_config.start_label(),
+            dir_config.stop_label()
+        );
+        let bar_width = width
+            .saturating_sub(name.chars().count() + detail.chars().count() + 4)
+            .max(10);
+        let filled = ((free.percentage / 100.0) * bar_width as f64).round() as usize;
+        let color = if free.below_start(dir_config) {
+            "31"
+        } else if !free.above_stop(dir_config) {
+            "33"
+        } else {
+            "32"
+        };
+        lines.push(format!(
+            "{}  [{}{}]{}",
+            name,
+            "#".repeat(filled.min(bar_width)),
+            "-".repeat(bar_width.saturating_sub(filled)),
+            detail
+        ));
+        bars.push(Some((
+            color,
+            name.chars().count() + 3,
+            filled.min(bar_width),
+        )));
+    }
+
+    lines.push(String::new());
+    lines.push("Last deletions".to_string());
+    if feed.deletions.is_empty() {
+        lines.push("  none yet".to_string());
+    }
+    for (path, bytes, event) in feed.deletions.iter().rev() {
+        let action = match event.as_str() {
+            "dry_run_delete" => " (dry run)",
+            "trash" => " (trashed)",
+            "archive" => " (archived)",
+            _ => "",
+        };
+        lines.push(format!("  {} ({}){}", path, format_bytes(*bytes), action));
+    }
+
+    lines.push(String::new());
+    lines.push("Log".to_string());
+    // Room is left for the status line at the bottom.
+    let log_rows = rows.saturating_sub(lines.len() + 2);
+    let skip = feed.log.len().saturating_sub(log_rows);
+    lines.extend(feed.log.iter().skip(skip).map(|line| format!("  {}", line)));
+    while lines.len() < rows.saturating_sub(1) {
+        lines.push(String::new());
+    }
+    lines.truncate(rows.saturating_sub(1));
+    lines.push(format!("{}  [c] clean now  [d] dry run  [q] quit", status));
+
+    let mut frame = String::from("\x1b[H");
+    for (index, line) in lines.iter().enumerate() {
+        let mut line = fit(line, width);
+        // Color the filled part of each free-space bar by threshold state.
+        if let Some(Some((color, start, filled))) = index.checked_sub(3).and_then(|i| bars.get(i)) {
+            if *filled > 0 && start + filled <= line.chars().count() {
+                let chars: Vec<char> = line.chars().collect();
+                line = format!(
+                    "{}\x1b[{}m{}\x1b[0m{}",
+                    chars[..*start].iter().collect::<String>(),
+                    color,
+                    chars[*start..start + filled].iter().collect::<String>(),
+                    chars[start + filled..].iter().collect::<String>()
+                );
+            }
+        }
+        frame.push_str(&line);
+        frame.push_str("\x1b[K");
+        if index + 1 < lines.len() {
+            frame.push_str("\r\n");
+        }
+    }
+    frame
+}
+
+/// Runs a cleanup (or dry run) under the instance lock, returning the status
+/// line to show afterwards.
+fn clean(cleaner: &Cleaner, dry_run: bool) -> String {
+    let what = if dry_run { "Dry run" } else { "Cleanup" };
+    let lock = match cleaner.lock() {
+        Ok(Some(lock)) => lock,
+        Ok(None) => return "Another instance is running; try again later".to_string(),
+        Err(err) => return format!("Could not take the lock file: {}", err),
+    };
+    let result = if dry_run {
+        cleaner.dry_run()
+    } else {
+        cleaner.run()
+    };
+    drop(lock);
+    let time = format_timestamp(clock::now());
+    match result {
+        Ok(outcome) => {
+            let outcome = match outcome {
+                Outcome::NoActionNeeded => "no cleanup needed",
+                Outcome::CleanedSufficient => "targets reached",
+                Outcome::CleanedInsufficient => "targets NOT reached",
+                Outcome::AlreadyRunning => "another instance is running",
+                Outcome::Error => "failed",
+            };
+            format!("{} at {}: {}", what, &time[11..19], outcome)
+        }
+        Err(err) => format!("{} at {} failed: {}", what, &time[11..19], err),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn run(cleaner: &Cleaner) -> std::io::Result<()> {
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        return Err(std::io::Error::other("--tui needs an interactive terminal"));
+    }
+    let feed = Arc::new(Mutex::new(Feed::default()));
+    set_logger(Box::new(FeedLogger { feed: feed.clone() }));
+    signal::install_handlers();
+    cleaner.log_status_banner()?;
+
+    let terminal = RawTerminal::enter()?;
+    let mut status = "Idle".to_string();
+    let mut out = std::io::stdout();
+    print!("\x1b[2J");
+    loop {
+        let (width, rows) = terminal.size();
+        let frame = {
+            let feed = feed.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            draw(cleaner, &feed, &status, width, rows)
+        };
+        out.write_all(frame.as_bytes())?;
+        out.flush()?;
+        if signal::stop_requested() {
+            break;
+        }
+
+        match terminal.read_action()? {
+            Some(Action::Quit) => break,
+            Some(action @ (Action::Clean | Action::DryRun)) => {
+                let dry_run = action == Action::DryRun;
+                let (width, rows) = terminal.size();
+                let running = if dry_run {
+                    "Dry run in progress..."
+                } else {
+                    "Cleanup in progress..."
+                };
+                let frame = {
+                    let feed = feed.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    draw(cleaner, &feed, running, width, rows)
+                };
+                out.write_all(frame.as_bytes())?;
+                out.flush()?;
+                status = clean(cleaner, dry_run);
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn run(_cleaner: &Cleaner) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "--tui is only available on Unix terminals",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    use crate::clock::TestClock;
+    use crate::config::Config;
+    use crate::storage::DiskInfo;
+    use crate::test_support::{MemoryDisk, MemoryFileSystem, TempDir};
+
+    /// A disk whose free space cannot be read.
+    struct Missing;
+
+    impl DiskInfo for Missing {
+        fn disk_space(&self, _config: &Config, _base_dir: &str) -> Option<(u64, u64)> {
+            None
+        }
+    }
+
+    /// A 2000-byte disk with 800 bytes free.
+    fn cleaner(dir: &TempDir, disks: Box<dyn DiskInfo>) -> Cleaner {
+        let config = Config::for_test(dir.path(), "start_threshold = 20\nstop_threshold = 30");
+        Cleaner::with_backends(config, disks, Box::new(MemoryFileSystem::new()))
+    }
+
+    fn memory_disk() -> Box<dyn DiskInfo> {
+        Box::new(MemoryDisk {
+            fs: MemoryFileSystem::new(),
+            total: 2000,
+            reserved: 1200,
+        })
+    }
+
+    /// The frame's lines with the escape sequences removed.
+    fn visible(frame: &str) -> Vec<String> {
+        frame
+            .split("\r\n")
+            .map(|line| {
+                let mut text = String::new();
+                let mut chars = line.chars();
+                while let Some(c) = chars.next() {
+                    if c == '\x1b' {
+                        // CSI sequences end with a letter.
+                        for c in chars.by_ref() {
+                            if c.is_ascii_alphabetic() {
+                                break;
+                            }
+                        }
+                    } else {
+                        text.push(c);
+                    }
+                }
+                text
+            })
+            .collect()
+    }
+
+    fn record<'a>(
+        dir: &'a str,
+        event: &'a str,
+        message: &'a str,
+        path: Option<&'a str>,
+    ) -> Record<'a> {
+        Record {
+            level: LogLevel::Info,
+            event,
+            message,
+            path,
+            freed_bytes: Some(1024),
+            log_path: dir,
+        }
+    }
+
+    #[test]
+    fn fit_cuts_to_width_and_masks_control_characters() {
+        assert_eq!(fit("abcdef", 4), "abcd");
+        assert_eq!(fit("abc", 10), "abc");
+        assert_eq!(fit("ünï", 2), "ün");
+        assert_eq!(fit("a\x1b[2Jb\nc\td", 20), "a?[2Jb?c?d");
+        assert_eq!(fit("abc", 0), "");
+    }
+
+    #[test]
+    fn action_takes_single_byte_keys_only() {
+        assert_eq!(action(b"q"), Some(Action::Quit));
+        assert_eq!(action(b"Q"), Some(Action::Quit));
+        assert_eq!(action(b"\x1b"), Some(Action::Quit));
+        assert_eq!(action(b"\x03"), Some(Action::Quit));
+        assert_eq!(action(b"c"), Some(Action::Clean));
+        assert_eq!(action(b"D"), Some(Action::DryRun));
+        // Arrow and function keys, non-ASCII characters and pastes.
+        assert_eq!(action(b"\x1b[A"), None);
+        assert_eq!(action(b"\x1bOP"), None);
+        assert_eq!(action("é".as_bytes()), None);
+        assert_eq!(action(b"cd"), None);
+        assert_eq!(action(b"x"), None);
+        assert_eq!(action(b""), None);
+    }
+
+    #[test]
+    fn feed_logger_keeps_recent_lines_and_deletions() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("tui-feed");
+        let log_path = dir.path().to_string_lossy().to_string();
+        let feed = Arc::new(Mutex::new(Feed::default()));
+        let logger = FeedLogger { feed: feed.clone() };
+
+        for i in 0..LOG_LINES_KEPT + 5 {
+            let message = format!("line {}", i);
+            logger
+                .log(&record(&log_path, "message", &message, Some("/ignored")))
+                .unwrap();
+        }
+        for i in 0..DELETIONS_SHOWN + 2 {
+            let path = format!("/base/cam1/{}", i);
+            let event = ["delete", "trash", "archive", "dry_run_delete"][i % 4];
+            logger
+                .log(&record(&log_path, event, "Deleted", Some(&path)))
+                .unwrap();
+        }
+        // A deletion event without a path is only a log line.
+        logger
+            .log(&record(&log_path, "delete", "Deleted", None))
+            .unwrap();
+
+        let feed = feed.lock().unwrap();
+        assert_eq!(feed.log.len(), LOG_LINES_KEPT);
+        assert!(feed.log.back().unwrap().ends_with("INFO  Deleted"));
+        assert!(feed.log.iter().all(|line| !line.ends_with("line 0")));
+        let paths: Vec<&str> = feed
+            .deletions
+            .iter()
+            .map(|(path, ..)| path.as_str())
+            .collect();
+        assert_eq!(paths.len(), DELETIONS_SHOWN);
+        assert_eq!(paths[0], "/base/cam1/2");
+        assert_eq!(paths[DELETIONS_SHOWN - 1], "/base/cam1/9");
+        assert_eq!(
+            feed.deletions[0],
+            ("/base/cam1/2".to_string(), 1024, "archive".to_string())
+        );
+        // The records still reach the log file.
+        let written = std::fs::read_to_string(dir.path().join("cleanup.log")).unwrap();
+        assert_eq!(
+            written.lines().count(),
+            LOG_LINES_KEPT + DELETIONS_SHOWN + 8
+        );
+    }
+
+    #[test]
+    fn draw_fills_the_screen_without_overflowing_it() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("tui-draw");
+        let cleaner = cleaner(&dir, memory_disk());
+        let mut feed = Feed::default();
+        feed.deletions
+            .push_back(("/base/old".to_string(), 2048, "trash".to_string()));
+        feed.deletions.push_back((
+            "/base/new\x1b[31m".to_string(),
+            1024,
+            "dry_run_delete".to_string(),
+        ));
+        for i in 0..40 {
+            feed.log.push_back(format!("12:00:00 INFO  line {}", i));
+        }
+
+        let frame = draw(&cleaner, &feed, "Idle", 60, 24);
+        let lines = visible(&frame);
+
+        assert!(frame.starts_with("\x1b[H"));
+        assert_eq!(lines.len(), 24);
+        assert!(lines.iter().all(|line| line.chars().count() <= 60));
+        assert!(lines[0].starts_with("Disk cleanup"));
+        assert!(lines[0].ends_with(" - 2023-11-14T22:13:20Z"));
+        assert_eq!(lines[2], "Free space");
+        assert!(lines[3].contains("40.00%"), "{}", lines[3]);
+        // 40% free is above the stop threshold, so the bar is green.
+        assert!(frame.contains("\x1b[32m#"));
+        assert_eq!(lines[5], "Last deletions");
+        assert_eq!(lines[6], "  /base/new?[31m (1.00 KiB) (dry run)");
+        assert_eq!(lines[7], "  /base/old (2.00 KiB) (trashed)");
+        assert_eq!(lines[9], "Log");
+        // The newest log lines fill the rows left, one short of the status
+        // line.
+        assert_eq!(lines[10], "  12:00:00 INFO  line 28");
+        assert_eq!(lines[21], "  12:00:00 INFO  line 39");
+        assert_eq!(lines[22], "");
+        assert_eq!(lines[23], "Idle  [c] clean now  [d] dry run  [q] quit");
+    }
+
+    #[test]
+    fn draw_reports_missing_disks_and_an_empty_feed() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("tui-draw-missing");
+        let cleaner = cleaner(&dir, Box::new(Missing));
+
+        let frame = draw(&cleaner, &Feed::default(), "Idle", 80, 12);
+        let lines = visible(&frame);
+
+        assert_eq!(lines.len(), 12);
+        assert!(lines[3].ends_with("  disk not found"), "{}", lines[3]);
+        assert!(!frame.contains("\x1b[3"));
+        assert_eq!(lines[6], "  none yet");
+        assert_eq!(lines[8], "Log");
+        assert!(lines[9..11].iter().all(|line| line.is_empty()));
+        assert!(lines[11].starts_with("Idle"));
+    }
+
+    #[test]
+    fn draw_shrinks_to_tiny_terminals() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = TestClock::install(now);
+        let dir = TempDir::new("tui-draw-tiny");
+        let cleaner = cleaner(&dir, memory_disk());
+
+        let lines = visible(&draw(&cleaner, &Feed::default(), "Idle", 10, 3));
+
+        assert_eq!(lines, ["Disk clean", "", "Idle  [c] "]);
+    }
+}